@@ -0,0 +1,64 @@
+# bash completion for doit; load it with: source <(doit --completions bash)
+_doit() {
+  local cur prev i
+  local -a file_args
+  cur="${COMP_WORDS[COMP_CWORD]}"
+  prev="${COMP_WORDS[COMP_CWORD-1]}"
+
+  for ((i = 1; i < COMP_CWORD - 1; i++)); do
+    case "${COMP_WORDS[i]}" in
+      -f | --file) file_args+=(-f "${COMP_WORDS[i+1]}") ;;
+      -C | --chdir) file_args+=(-C "${COMP_WORDS[i+1]}") ;;
+    esac
+  done
+
+  case "$prev" in
+    --show)
+      COMPREPLY=($(compgen -W "$(doit "${file_args[@]}" --cmds --format plain 2>/dev/null)" -- "$cur"))
+      return ;;
+    -f | --file)
+      COMPREPLY=($(compgen -f -- "$cur"))
+      return ;;
+    -C | --chdir)
+      COMPREPLY=($(compgen -d -- "$cur"))
+      return ;;
+    --format)
+      COMPREPLY=($(compgen -W "text json plain" -- "$cur"))
+      return ;;
+    --color)
+      COMPREPLY=($(compgen -W "auto always never" -- "$cur"))
+      return ;;
+    --completions)
+      COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
+      return ;;
+    -j | --jobs)
+      return ;;
+  esac
+
+  if [[ "$cur" == -* ]]; then
+    COMPREPLY=($(compgen -W "$(doit --help 2>/dev/null | grep -o -- '--[a-z][a-z-]*' | sort -u)" -- "$cur"))
+    return
+  fi
+
+  # Only the first word that isn't an option is a command (or a subcommand, which run and show take one after);
+  # the ones after it are its arguments.
+  local subcommands="run list show check init"
+  for ((i = 1; i < COMP_CWORD; i++)); do
+    case "${COMP_WORDS[i]}" in
+      -f | --file | -C | --chdir | -j | --jobs | --show | --format | --color | --completions) ((i++)) ;;
+      -*) ;;
+      run | show)
+        if ((i == COMP_CWORD - 1)); then
+          subcommands=
+          break
+        fi
+        COMPREPLY=($(compgen -f -- "$cur"))
+        return ;;
+      *)
+        COMPREPLY=($(compgen -f -- "$cur"))
+        return ;;
+    esac
+  done
+  COMPREPLY=($(compgen -W "$(doit "${file_args[@]}" --cmds --format plain 2>/dev/null) $subcommands" -- "$cur"))
+}
+complete -F _doit doit
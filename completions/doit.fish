@@ -0,0 +1,52 @@
+# fish completion for doit; load it with: doit --completions fish | source
+function __doit_tasks
+    set -l tokens (commandline -opc)
+    set -l file_args
+    for i in (seq (math (count $tokens) - 1))
+        if contains -- $tokens[$i] -f --file
+            set -a file_args -f $tokens[(math $i + 1)]
+        else if contains -- $tokens[$i] -C --chdir
+            set -a file_args -C $tokens[(math $i + 1)]
+        end
+    end
+    doit $file_args --cmds --format plain 2>/dev/null
+end
+
+# Only the first word that isn't an option is a command (or a subcommand, which run and show take one after);
+# the ones after it are its arguments. With `first`, only succeeds before that first word.
+function __doit_needs_task
+    set -l tokens (commandline -opc)
+    set -e tokens[1]
+    set -l skip 0
+    set -l subcommand
+    for token in $tokens
+        if test $skip -eq 1
+            set skip 0
+            continue
+        end
+        if test -n "$subcommand"
+            return 1
+        end
+        switch $token
+            case -f --file -C --chdir -j --jobs --show --format --color --completions
+                set skip 1
+            case '-*'
+            case run show
+                set subcommand $token
+            case '*'
+                return 1
+        end
+    end
+    test -z "$subcommand" -o "$argv[1]" != first
+end
+
+complete -c doit -f -n __doit_needs_task -a '(__doit_tasks)'
+complete -c doit -f -n '__doit_needs_task first' -a 'run list show check init'
+complete -c doit -n 'string match -q -- "-*" (commandline -ct)' -a '(doit --help 2>/dev/null | string match -ar -- "--[a-z][a-z-]*" | sort -u)'
+complete -c doit -l show -x -a '(__doit_tasks)'
+complete -c doit -s f -l file -r -F
+complete -c doit -s C -l chdir -x -a '(__fish_complete_directories)'
+complete -c doit -l format -x -a 'text json plain'
+complete -c doit -l color -x -a 'auto always never'
+complete -c doit -l completions -x -a 'bash zsh fish'
+complete -c doit -s j -l jobs -x
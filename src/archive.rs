@@ -0,0 +1,712 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Reading and writing the tar, tar.gz, tar.zst and zip archives of &archive and &extract. It is all done here, with
+//! no archiving programs, so that archives come out the same wherever they are made. zstd needs the `zstd` feature,
+//! which is on by default.
+use crate::deflate;
+use std::{
+  fs,
+  os::unix::fs::{MetadataExt, PermissionsExt},
+  path::{Component, Path, PathBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+  Tar,
+  TarGz,
+  TarZst,
+  Zip,
+}
+
+impl Format {
+  /// The format an archive's name says it is in.
+  pub fn from_name(name: &str) -> Option<Format> {
+    let name = name.to_lowercase();
+    let ends = |suffixes: &[&str]| suffixes.iter().any(|suffix| name.ends_with(suffix));
+    match () {
+      _ if ends(&[".tar.gz", ".tgz"]) => Some(Format::TarGz),
+      _ if ends(&[".tar.zst", ".tzst"]) => Some(Format::TarZst),
+      _ if ends(&[".tar"]) => Some(Format::Tar),
+      _ if ends(&[".zip"]) => Some(Format::Zip),
+      _ => None,
+    }
+  }
+
+  /// The format an existing archive's first bytes say it is in.
+  fn sniff(data: &[u8]) -> Option<Format> {
+    match data {
+      [0x1f, 0x8b, ..] => Some(Format::TarGz),
+      [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Format::TarZst),
+      [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => Some(Format::Zip),
+      _ if data.len() >= 512 && &data[257..262] == b"ustar" => Some(Format::Tar),
+      _ => None,
+    }
+  }
+}
+
+/// One file, directory or symlink in an archive.
+struct Entry {
+  /// The path within the archive, with `/` separators and no trailing `/`.
+  name: String,
+  mode: u32,
+  mtime: u64,
+  kind: Kind,
+}
+
+enum Kind {
+  File(Vec<u8>),
+  Dir,
+  Symlink(String),
+}
+
+/// Gathers `path` and, for directories, everything under it in name order, named relative to `base`.
+fn collect(base: &Path, path: &Path, entries: &mut Vec<Entry>) -> Result<(), String> {
+  let fail = |e: std::io::Error| format!("{}: {}", base.join(path).display(), e);
+  let full = base.join(path);
+  let metadata = fs::symlink_metadata(&full).map_err(fail)?;
+  let name = path.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+  let (mode, mtime) = (metadata.mode() & 0o7777, metadata.mtime().max(0) as u64);
+  if metadata.file_type().is_symlink() {
+    let target = fs::read_link(&full).map_err(fail)?.display().to_string();
+    entries.push(Entry { name, mode, mtime, kind: Kind::Symlink(target) });
+  } else if metadata.is_dir() {
+    if !name.is_empty() && name != "." {
+      entries.push(Entry { name, mode, mtime, kind: Kind::Dir });
+    }
+    let mut children: Vec<_> = fs::read_dir(&full).map_err(fail)?.flatten().map(|child| child.file_name()).collect();
+    children.sort();
+    for child in children {
+      collect(base, &path.join(child), entries)?;
+    }
+  } else {
+    entries.push(Entry { name, mode, mtime, kind: Kind::File(fs::read(&full).map_err(fail)?) });
+  }
+  Ok(())
+}
+
+/// Writes an archive of `paths`, which are relative to `base` and keep those names in the archive.
+pub fn create(archive: &Path, format: Format, base: &Path, paths: &[PathBuf]) -> Result<(), String> {
+  let mut entries = Vec::new();
+  for path in paths {
+    if path.is_absolute() || path.components().any(|part| part == Component::ParentDir) {
+      return Err(format!("{}: archived paths must be relative and not go up with ..", path.display()));
+    }
+    collect(base, path, &mut entries)?;
+  }
+  let data = match format {
+    Format::Zip => write_zip(&entries)?,
+    Format::Tar => write_tar(&entries)?,
+    Format::TarGz => gzip(&write_tar(&entries)?),
+    Format::TarZst => zstd_compress(&write_tar(&entries)?)?,
+  };
+  fs::write(archive, data).map_err(|e| format!("{}: {}", archive.display(), e))
+}
+
+/// Extracts an archive into `dest`, dropping the first `strip` parts of each name.
+pub fn extract(archive: &Path, dest: &Path, strip: usize) -> Result<(), String> {
+  let data = fs::read(archive).map_err(|e| format!("{}: {}", archive.display(), e))?;
+  let format = Format::sniff(&data)
+    .or_else(|| Format::from_name(&archive.display().to_string()))
+    .ok_or_else(|| format!("{}: not a tar, tar.gz, tar.zst or zip archive", archive.display()))?;
+  let entries = match format {
+    Format::Zip => read_zip(&data)?,
+    Format::Tar => read_tar(&data)?,
+    Format::TarGz => read_tar(&gunzip(&data)?)?,
+    Format::TarZst => read_tar(&zstd_decompress(&data)?)?,
+  };
+  fs::create_dir_all(dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+  let root = dest.canonicalize().map_err(|e| format!("{}: {}", dest.display(), e))?;
+  for entry in entries {
+    let name: Vec<&str> = entry.name.split('/').filter(|part| !part.is_empty() && *part != ".").skip(strip).collect();
+    if name.is_empty() {
+      continue;
+    }
+    if name.contains(&"..") || entry.name.starts_with('/') {
+      return Err(format!("{}: refusing to extract {} outside of {}", archive.display(), entry.name, dest.display()));
+    }
+    let path = dest.join(name.join("/"));
+    // A symlink extracted earlier mustn't lead what comes after it out of dest.
+    let within = if matches!(entry.kind, Kind::Dir) { Some(path.as_path()) } else { path.parent() };
+    if !within.is_some_and(|within| resolves_under(&root, within)) {
+      return Err(format!("{}: refusing to extract {} outside of {}", archive.display(), entry.name, dest.display()));
+    }
+    let fail = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).map_err(fail)?;
+    }
+    match entry.kind {
+      Kind::Dir => fs::create_dir_all(&path).map_err(fail)?,
+      Kind::Symlink(target) => {
+        let _ = fs::remove_file(&path);
+        std::os::unix::fs::symlink(target, &path).map_err(fail)?;
+        continue;
+      }
+      Kind::File(content) => {
+        let _ = fs::remove_file(&path);
+        fs::write(&path, content).map_err(fail)?;
+      }
+    }
+    if entry.mode != 0 {
+      fs::set_permissions(&path, fs::Permissions::from_mode(entry.mode)).map_err(fail)?;
+    }
+  }
+  Ok(())
+}
+
+/// Whether `path` is under `root`, which is canonical, once the directories it needs are created: the nearest
+/// part of it that is there already has to resolve, symlinks and all, to somewhere under root.
+fn resolves_under(root: &Path, path: &Path) -> bool {
+  path
+    .ancestors()
+    .find(|ancestor| fs::symlink_metadata(ancestor).is_ok())
+    .and_then(|existing| existing.canonicalize().ok())
+    .is_some_and(|real| real.starts_with(root))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(data: &[u8]) -> Result<Vec<u8>, String> {
+  Ok(ruzstd::encoding::compress_to_vec(data, ruzstd::encoding::CompressionLevel::Fastest))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(mut data: &[u8]) -> Result<Vec<u8>, String> {
+  use std::io::Read;
+  let mut out = Vec::new();
+  // Like gzip, zstd data may be several frames one after another.
+  while !data.is_empty() {
+    let mut frame = ruzstd::decoding::StreamingDecoder::new(&mut data).map_err(|e| format!("zstd: {}", e))?;
+    frame.read_to_end(&mut out).map_err(|e| format!("zstd: {}", e))?;
+  }
+  Ok(out)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_: &[u8]) -> Result<Vec<u8>, String> {
+  Err("this doit was built without the zstd feature, so it can't write .tar.zst archives".into())
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_: &[u8]) -> Result<Vec<u8>, String> {
+  Err("this doit was built without the zstd feature, so it can't read .tar.zst archives".into())
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+  // No name or time in the header, so the same files always give the same bytes.
+  let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+  out.extend(deflate::compress(data));
+  out.extend(deflate::crc32(data).to_le_bytes());
+  out.extend((data.len() as u32).to_le_bytes());
+  out
+}
+
+fn gunzip(mut data: &[u8]) -> Result<Vec<u8>, String> {
+  let mut out = Vec::new();
+  // A gzip file may be several members one after another.
+  while data.len() >= 18 && data[..2] == [0x1f, 0x8b] {
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & 4 != 0 {
+      pos += 2 + u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+    }
+    for flag in [8, 16] {
+      if flags & flag != 0 {
+        pos += data.get(pos..).and_then(|rest| rest.iter().position(|b| *b == 0)).ok_or("bad gzip header")? + 1;
+      }
+    }
+    if flags & 2 != 0 {
+      pos += 2;
+    }
+    let (member, used) = deflate::decompress(data.get(pos..).ok_or("bad gzip header")?)?;
+    let trailer = data.get(pos + used..pos + used + 8).ok_or("gzip data is cut short")?;
+    if u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != deflate::crc32(&member) {
+      return Err("gzip data is corrupt (bad CRC)".into());
+    }
+    out.extend(member);
+    data = &data[pos + used + 8..];
+  }
+  Ok(out)
+}
+
+fn octal(field: &mut [u8], value: u64) {
+  let text = format!("{:0width$o}\0", value, width = field.len() - 1);
+  field.copy_from_slice(&text.as_bytes()[text.len() - field.len()..]);
+}
+
+/// The ustar prefix and name a path is split into, when it fits.
+fn ustar_name(name: &str) -> Option<(&str, &str)> {
+  match name.len() {
+    0..=100 => Some(("", name)),
+    _ => match name[..name.len().min(156)].rfind('/') {
+      Some(split) if name.len() - split - 1 <= 100 => Some((&name[..split], &name[split + 1..])),
+      _ => None,
+    },
+  }
+}
+
+/// The longest start of `text` that fits in `len` bytes.
+fn truncated(text: &str, len: usize) -> &str {
+  let mut end = text.len().min(len);
+  while !text.is_char_boundary(end) {
+    end -= 1;
+  }
+  &text[..end]
+}
+
+/// A pax extended header record: its length, counting the digits of the length itself, then `key=value`.
+fn pax_record(key: &str, value: &str) -> String {
+  let len = key.len() + value.len() + 3;
+  let mut total = len + len.to_string().len();
+  if total.to_string().len() != len.to_string().len() {
+    total += 1;
+  }
+  format!("{} {}={}\n", total, key, value)
+}
+
+/// The headers for one entry: a pax header first when the name or symlink target doesn't fit in ustar's fields.
+fn tar_headers(name: &str, kind: u8, size: u64, mode: u32, mtime: u64, link: &str) -> Vec<u8> {
+  let mut out = Vec::new();
+  let mut pax = String::new();
+  let (prefix, short) = ustar_name(name).unwrap_or_else(|| {
+    pax.push_str(&pax_record("path", name));
+    ("", truncated(name.trim_end_matches('/'), 100))
+  });
+  let link = if link.len() <= 100 {
+    link
+  } else {
+    pax.push_str(&pax_record("linkpath", link));
+    truncated(link, 100)
+  };
+  if !pax.is_empty() {
+    out.extend(tar_header("", short, b'x', pax.len() as u64, 0o644, mtime, ""));
+    out.extend(pax.as_bytes());
+    out.resize(out.len().div_ceil(512) * 512, 0);
+  }
+  out.extend(tar_header(prefix, short, kind, size, mode, mtime, link));
+  out
+}
+
+fn tar_header(prefix: &str, short: &str, kind: u8, size: u64, mode: u32, mtime: u64, link: &str) -> [u8; 512] {
+  let mut header = [0u8; 512];
+  header[..short.len()].copy_from_slice(short.as_bytes());
+  octal(&mut header[100..108], mode as u64);
+  octal(&mut header[108..116], 0);
+  octal(&mut header[116..124], 0);
+  octal(&mut header[124..136], size);
+  octal(&mut header[136..148], mtime);
+  header[156] = kind;
+  header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+  header[257..265].copy_from_slice(b"ustar\x0000");
+  header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+  header[148..156].fill(b' ');
+  let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+  octal(&mut header[148..155], checksum as u64);
+  header[155] = b' ';
+  header
+}
+
+fn write_tar(entries: &[Entry]) -> Result<Vec<u8>, String> {
+  let mut out = Vec::new();
+  for entry in entries {
+    match &entry.kind {
+      Kind::File(content) => {
+        out.extend(tar_headers(&entry.name, b'0', content.len() as u64, entry.mode, entry.mtime, ""));
+        out.extend(content);
+        out.resize(out.len().div_ceil(512) * 512, 0);
+      }
+      Kind::Dir => out.extend(tar_headers(&format!("{}/", entry.name), b'5', 0, entry.mode, entry.mtime, "")),
+      Kind::Symlink(target) => out.extend(tar_headers(&entry.name, b'2', 0, entry.mode, entry.mtime, target)),
+    }
+  }
+  out.extend([0u8; 1024]);
+  Ok(out)
+}
+
+fn text(field: &[u8]) -> String {
+  String::from_utf8_lossy(&field[..field.iter().position(|b| *b == 0).unwrap_or(field.len())]).into()
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64, String> {
+  let digits = text(field);
+  let digits = digits.trim();
+  if digits.is_empty() {
+    return Ok(0);
+  }
+  u64::from_str_radix(digits, 8).map_err(|_| format!("bad number {:?} in tar header", digits))
+}
+
+fn read_tar(data: &[u8]) -> Result<Vec<Entry>, String> {
+  let mut entries = Vec::new();
+  let mut pos = 0;
+  // Set by GNU long name and link headers or a pax header for the entry that follows.
+  let (mut long_name, mut long_link): (Option<String>, Option<String>) = (None, None);
+  while let Some(header) = data.get(pos..pos + 512) {
+    if header.iter().all(|b| *b == 0) {
+      break;
+    }
+    let size = parse_octal(&header[124..136])? as usize;
+    let content = data.get(pos + 512..pos + 512 + size).ok_or("tar archive is cut short")?;
+    pos += 512 + size.div_ceil(512) * 512;
+    match header[156] {
+      b'L' => {
+        long_name = Some(text(content));
+        continue;
+      }
+      b'K' => {
+        long_link = Some(text(content));
+        continue;
+      }
+      b'x' => {
+        for record in String::from_utf8_lossy(content).lines() {
+          match record.split_once(' ').and_then(|(_, record)| record.split_once('=')) {
+            Some(("path", path)) => long_name = Some(path.to_string()),
+            Some(("linkpath", link)) => long_link = Some(link.to_string()),
+            _ => {}
+          }
+        }
+        continue;
+      }
+      _ => {}
+    }
+    let prefix = text(&header[345..500]);
+    let name = match long_name.take() {
+      Some(name) => name,
+      None if prefix.is_empty() || &header[257..262] != b"ustar" => text(&header[..100]),
+      None => format!("{}/{}", prefix, text(&header[..100])),
+    };
+    let link = long_link.take().unwrap_or_else(|| text(&header[157..257]));
+    let (mode, mtime) = (parse_octal(&header[100..108])? as u32, parse_octal(&header[136..148])?);
+    let name = name.trim_end_matches('/').to_string();
+    let kind = match header[156] {
+      b'0' | 0 | b'7' => Kind::File(content.to_vec()),
+      b'5' => Kind::Dir,
+      b'2' => Kind::Symlink(link),
+      _ => continue,
+    };
+    entries.push(Entry { name, mode: mode & 0o7777, mtime, kind });
+  }
+  Ok(entries)
+}
+
+/// The DOS date and time zip headers keep, from seconds since 1970 in UTC.
+fn dos_time(mtime: u64) -> (u16, u16) {
+  let days = (mtime / 86400) as i64;
+  let secs = mtime % 86400;
+  // Civil date from days since 1970, after Howard Hinnant's algorithm.
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }).clamp(1980, 2107);
+  let time = (secs / 3600) << 11 | (secs % 3600 / 60) << 5 | ((secs % 60) / 2);
+  let date = ((year - 1980) as u64) << 9 | (month as u64) << 5 | day as u64;
+  (time as u16, date as u16)
+}
+
+fn write_zip(entries: &[Entry]) -> Result<Vec<u8>, String> {
+  let mut out = Vec::new();
+  let mut central = Vec::new();
+  for entry in entries {
+    let (name, content, unix_type) = match &entry.kind {
+      Kind::File(content) => (entry.name.clone(), content.clone(), 0o100000),
+      Kind::Dir => (format!("{}/", entry.name), Vec::new(), 0o040000),
+      Kind::Symlink(target) => (entry.name.clone(), target.as_bytes().to_vec(), 0o120000),
+    };
+    let crc = deflate::crc32(&content);
+    let compressed = deflate::compress(&content);
+    let (method, stored): (u16, &[u8]) =
+      if compressed.len() < content.len() { (8, &compressed) } else { (0, &content) };
+    if out.len() > u32::MAX as usize || content.len() > u32::MAX as usize {
+      return Err(format!("{}: too big for a zip archive", name));
+    }
+    let (time, date) = dos_time(entry.mtime);
+    let offset = out.len() as u32;
+    let mut fields = Vec::new();
+    fields.extend(20u16.to_le_bytes());
+    fields.extend(0x0800u16.to_le_bytes());
+    fields.extend(method.to_le_bytes());
+    fields.extend(time.to_le_bytes());
+    fields.extend(date.to_le_bytes());
+    fields.extend(crc.to_le_bytes());
+    fields.extend((stored.len() as u32).to_le_bytes());
+    fields.extend((content.len() as u32).to_le_bytes());
+    fields.extend((name.len() as u16).to_le_bytes());
+    fields.extend(0u16.to_le_bytes());
+
+    out.extend(b"PK\x03\x04");
+    out.extend(&fields);
+    out.extend(name.as_bytes());
+    out.extend(stored);
+
+    central.extend(b"PK\x01\x02");
+    central.extend((3u16 << 8 | 20).to_le_bytes());
+    central.extend(&fields);
+    central.extend(0u16.to_le_bytes());
+    central.extend(0u16.to_le_bytes());
+    central.extend(0u16.to_le_bytes());
+    central.extend(((unix_type | entry.mode) << 16 | if unix_type == 0o040000 { 0x10 } else { 0 }).to_le_bytes());
+    central.extend(offset.to_le_bytes());
+    central.extend(name.as_bytes());
+  }
+  let (central_offset, central_len) = (out.len() as u32, central.len() as u32);
+  out.extend(central);
+  out.extend(b"PK\x05\x06");
+  out.extend([0u8; 4]);
+  out.extend((entries.len() as u16).to_le_bytes());
+  out.extend((entries.len() as u16).to_le_bytes());
+  out.extend(central_len.to_le_bytes());
+  out.extend(central_offset.to_le_bytes());
+  out.extend(0u16.to_le_bytes());
+  Ok(out)
+}
+
+fn read_zip(data: &[u8]) -> Result<Vec<Entry>, String> {
+  let u16_at = |pos: usize| data.get(pos..pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize);
+  let u32_at = |pos: usize| data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+  let end = (0..data.len().saturating_sub(21)).rev().find(|pos| data[*pos..].starts_with(b"PK\x05\x06"));
+  let end = end.ok_or("not a zip archive (no end of central directory)")?;
+  let count = u16_at(end + 10).ok_or("zip archive is cut short")?;
+  let mut pos = u32_at(end + 16).ok_or("zip archive is cut short")?;
+  let mut entries = Vec::new();
+  for _ in 0..count {
+    let short = || "zip archive is cut short".to_string();
+    if !data.get(pos..).is_some_and(|rest| rest.starts_with(b"PK\x01\x02")) {
+      return Err("bad zip central directory".into());
+    }
+    let (made_by, method) = (u16_at(pos + 4).ok_or_else(short)? >> 8, u16_at(pos + 10).ok_or_else(short)?);
+    let crc = u32_at(pos + 16).ok_or_else(short)? as u32;
+    let (compressed_len, len) = (u32_at(pos + 20).ok_or_else(short)?, u32_at(pos + 24).ok_or_else(short)?);
+    let name_len = u16_at(pos + 28).ok_or_else(short)?;
+    let skip = name_len + u16_at(pos + 30).ok_or_else(short)? + u16_at(pos + 32).ok_or_else(short)?;
+    let attributes = u32_at(pos + 38).ok_or_else(short)? as u32;
+    let local = u32_at(pos + 42).ok_or_else(short)?;
+    let name = String::from_utf8_lossy(data.get(pos + 46..pos + 46 + name_len).ok_or_else(short)?).to_string();
+    pos += 46 + skip;
+
+    let start = local + 30 + u16_at(local + 26).ok_or_else(short)? + u16_at(local + 28).ok_or_else(short)?;
+    let stored = data.get(start..start + compressed_len).ok_or_else(short)?;
+    let content = match method {
+      0 => stored.to_vec(),
+      8 => deflate::decompress(stored)?.0,
+      _ => return Err(format!("{}: unsupported zip compression method {}", name, method)),
+    };
+    if content.len() != len || deflate::crc32(&content) != crc {
+      return Err(format!("{}: corrupt in the zip archive", name));
+    }
+    let unix_mode = if made_by == 3 { attributes >> 16 } else { 0 };
+    let kind = match unix_mode & 0o170000 {
+      0o120000 => Kind::Symlink(String::from_utf8_lossy(&content).into()),
+      0o040000 => Kind::Dir,
+      _ if name.ends_with('/') => Kind::Dir,
+      _ => Kind::File(content),
+    };
+    let name = name.trim_end_matches('/').to_string();
+    entries.push(Entry { name, mode: unix_mode & 0o7777, mtime: 0, kind });
+  }
+  Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::TempDir;
+
+  const LONG_DIR: &str =
+    "a-directory-name-that-goes-on-for-a-while/and-another-one-under-it-that-also-goes-on/and-one-more-for-luck";
+
+  /// A tree with a bit of everything: an executable, an empty file and directory, symlinks, and names too long for
+  /// ustar's name field and for its prefix field too, as well as a symlink target too long for its link field.
+  fn make_tree(root: &Path) -> String {
+    let very_long = ["part-of-a-very-long-path"; 12].join("/");
+    let write = |name: &str, content: &[u8], mode: u32| {
+      let path = root.join(name);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(&path, content).unwrap();
+      fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    };
+    write("tree/README", b"hello\n", 0o644);
+    write("tree/bin/run.sh", b"#!/bin/sh\necho hi\n", 0o755);
+    write("tree/empty", b"", 0o600);
+    write(&format!("tree/{}/file.txt", LONG_DIR), &b"long ".repeat(1000), 0o644);
+    write(&format!("tree/{}/file.txt", very_long), b"very long", 0o644);
+    fs::create_dir_all(root.join("tree/empty-dir")).unwrap();
+    std::os::unix::fs::symlink("README", root.join("tree/link")).unwrap();
+    std::os::unix::fs::symlink(format!("{}/file.txt", very_long), root.join("tree/far-link")).unwrap();
+    very_long
+  }
+
+  /// Everything under `root/path`, described so that two trees can be compared.
+  fn describe(root: &Path, path: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    collect(root, Path::new(path), &mut entries).unwrap();
+    entries
+      .iter()
+      .map(|entry| match &entry.kind {
+        Kind::File(content) => format!("file {} {:o} {:?}", entry.name, entry.mode, content),
+        Kind::Dir => format!("dir {} {:o}", entry.name, entry.mode),
+        Kind::Symlink(target) => format!("symlink {} -> {}", entry.name, target),
+      })
+      .collect()
+  }
+
+  fn round_trip(name: &str, format: Format) {
+    let dir = TempDir::new(name);
+    let (source, dest) = (dir.0.join("source"), dir.0.join("dest"));
+    make_tree(&source);
+    let archive = dir.0.join(name);
+    create(&archive, format, &source, &[PathBuf::from("tree")]).unwrap();
+    assert_eq!(Format::sniff(&fs::read(&archive).unwrap()), Some(format));
+    extract(&archive, &dest, 0).unwrap();
+    assert_eq!(describe(&dest, "tree"), describe(&source, "tree"));
+  }
+
+  #[test]
+  fn tar_round_trips() {
+    round_trip("test.tar", Format::Tar);
+  }
+
+  #[test]
+  fn tar_gz_round_trips() {
+    round_trip("test.tar.gz", Format::TarGz);
+  }
+
+  #[cfg(feature = "zstd")]
+  #[test]
+  fn tar_zst_round_trips() {
+    round_trip("test.tar.zst", Format::TarZst);
+  }
+
+  #[test]
+  fn zip_round_trips() {
+    round_trip("test.zip", Format::Zip);
+  }
+
+  #[test]
+  fn the_same_files_give_the_same_archive() {
+    let dir = TempDir::new("same");
+    make_tree(&dir.0);
+    let (first, second) = (dir.0.join("first.tar.gz"), dir.0.join("second.tar.gz"));
+    create(&first, Format::TarGz, &dir.0, &[PathBuf::from("tree")]).unwrap();
+    create(&second, Format::TarGz, &dir.0, &[PathBuf::from("tree")]).unwrap();
+    assert_eq!(fs::read(first).unwrap(), fs::read(second).unwrap());
+  }
+
+  #[test]
+  fn long_names_use_the_ustar_prefix_or_a_pax_header() {
+    let name = format!("{}/file.txt", LONG_DIR);
+    assert!(name.len() > 100);
+    assert_eq!(ustar_name(&name), Some((LONG_DIR, "file.txt")));
+    assert_eq!(tar_headers(&name, b'0', 0, 0o644, 0, "").len(), 512);
+    let very_long = ["part-of-a-very-long-path"; 12].join("/");
+    assert_eq!(ustar_name(&very_long), None);
+    let headers = tar_headers(&very_long, b'0', 0, 0o644, 0, "");
+    assert_eq!(headers[156], b'x');
+    assert_eq!(read_tar(&[headers, vec![0; 1024]].concat()).unwrap()[0].name, very_long);
+  }
+
+  #[test]
+  fn pax_records_count_their_own_length() {
+    assert_eq!(pax_record("path", "a"), "9 path=a\n");
+    assert_eq!(pax_record("path", "ab"), "11 path=ab\n");
+    // Around where the length goes from two digits to three.
+    for len in 85..95 {
+      let record = pax_record("path", &"x".repeat(len));
+      assert_eq!(record.len().to_string(), record.split(' ').next().unwrap());
+    }
+  }
+
+  #[test]
+  fn strips_leading_parts() {
+    let dir = TempDir::new("strip");
+    make_tree(&dir.0.join("source"));
+    let archive = dir.0.join("test.tar");
+    create(&archive, Format::Tar, &dir.0.join("source"), &[PathBuf::from("tree")]).unwrap();
+    extract(&archive, &dir.0.join("dest"), 1).unwrap();
+    assert_eq!(fs::read(dir.0.join("dest/bin/run.sh")).unwrap(), b"#!/bin/sh\necho hi\n");
+  }
+
+  #[test]
+  fn refuses_to_extract_outside_the_destination() {
+    let dir = TempDir::new("escape");
+    let mut tar = tar_headers("../escaped", b'0', 2, 0o644, 0, "");
+    tar.extend(b"hi");
+    tar.resize(1024 + 1024, 0);
+    let archive = dir.0.join("evil.tar");
+    fs::write(&archive, tar).unwrap();
+    let error = extract(&archive, &dir.0.join("dest"), 0).unwrap_err();
+    assert!(error.contains("refusing to extract ../escaped"), "{}", error);
+    assert!(!dir.0.join("escaped").exists());
+  }
+
+  #[test]
+  fn refuses_to_follow_a_symlink_out_of_the_destination() {
+    let dir = TempDir::new("symlink-escape");
+    let outside = dir.0.join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    let mut tar = tar_headers("esc", b'2', 0, 0o777, 0, &outside.display().to_string());
+    tar.extend(tar_headers("esc/pwned.txt", b'0', 2, 0o644, 0, ""));
+    tar.extend(b"hi");
+    tar.resize(tar.len() + 510 + 1024, 0);
+    let archive = dir.0.join("evil.tar");
+    fs::write(&archive, tar).unwrap();
+    let error = extract(&archive, &dir.0.join("dest"), 0).unwrap_err();
+    assert!(error.contains("refusing to extract esc/pwned.txt"), "{}", error);
+    assert!(!outside.join("pwned.txt").exists());
+
+    // Nor one that goes up and out, or points at nothing.
+    for (target, name) in [("../outside", "up"), ("../nowhere/at/all", "dangling")] {
+      let mut tar = tar_headers(name, b'2', 0, 0o777, 0, target);
+      tar.extend(tar_headers(&format!("{}/sub", name), b'5', 0, 0o755, 0, ""));
+      tar.resize(tar.len() + 1024, 0);
+      fs::write(&archive, tar).unwrap();
+      assert!(extract(&archive, &dir.0.join("dest"), 0).unwrap_err().contains("refusing"));
+    }
+    assert!(!outside.join("sub").exists() && !dir.0.join("nowhere").exists());
+  }
+
+  #[test]
+  fn refuses_paths_going_up() {
+    let dir = TempDir::new("up");
+    let error = create(&dir.0.join("x.tar"), Format::Tar, &dir.0, &[PathBuf::from("../x")]).unwrap_err();
+    assert!(error.contains("must be relative"), "{}", error);
+  }
+
+  #[test]
+  fn formats_from_names() {
+    assert_eq!(Format::from_name("dist.TAR.GZ"), Some(Format::TarGz));
+    assert_eq!(Format::from_name("dist.tgz"), Some(Format::TarGz));
+    assert_eq!(Format::from_name("dist.tzst"), Some(Format::TarZst));
+    assert_eq!(Format::from_name("dist.tar"), Some(Format::Tar));
+    assert_eq!(Format::from_name("dist.zip"), Some(Format::Zip));
+    assert_eq!(Format::from_name("dist.rar"), None);
+  }
+
+  #[test]
+  fn gzip_round_trips_and_checks_the_crc() {
+    for data in [&b""[..], b"hello", &b"abc".repeat(10_000)] {
+      assert_eq!(gunzip(&gzip(data)).unwrap(), data);
+    }
+    let mut corrupt = gzip(b"hello");
+    let len = corrupt.len();
+    corrupt[len - 8] ^= 1;
+    assert_eq!(gunzip(&corrupt), Err("gzip data is corrupt (bad CRC)".into()));
+    assert_eq!(gunzip(&[gzip(b"one "), gzip(b"two")].concat()).unwrap(), b"one two");
+  }
+
+  #[cfg(feature = "zstd")]
+  #[test]
+  fn zstd_reads_several_frames() {
+    let data = [zstd_compress(b"one ").unwrap(), zstd_compress(b"two").unwrap()].concat();
+    assert_eq!(zstd_decompress(&data).unwrap(), b"one two");
+    assert_eq!(zstd_decompress(&zstd_compress(b"").unwrap()).unwrap(), b"");
+  }
+
+  #[test]
+  fn dos_times() {
+    assert_eq!(dos_time(0), (0, 1 << 5 | 1));
+    // 2024-02-29 13:45:30 UTC.
+    assert_eq!(dos_time(1709214330), (13 << 11 | 45 << 5 | 15, 44 << 9 | 2 << 5 | 29));
+  }
+}
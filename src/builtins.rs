@@ -0,0 +1,867 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! The `&name` builtin commands, and the plugins an unknown `&name` runs.
+use crate::{
+  archive,
+  config::{doit_dir, find_task, SETTINGS_SECTION},
+  exec::{builtin_wait, check_cancelled, open_redirect, parse_duration, run_program, RunOptions, CANCELLED},
+  loaded_config, reporter, settings, sha256,
+  show::toml_json,
+  task::{env_var, glob_paths, process_cmd, CAPTURES, STEP_STATUS},
+  template::render_value,
+  wasm, DoitError,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  env,
+  fs::{remove_file, write as write_file, File},
+  io::{Read, Write},
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  sync::{atomic::Ordering, Mutex},
+  thread,
+  time::{Duration, Instant},
+};
+use toml_edit::DocumentMut;
+
+static VERSION_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d+)(?:\.(\d+))?(?:\.(\d+))?").unwrap());
+static REQUIREMENT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^([^<>=]+)(>=|<=|>|<|=)(.+)$").unwrap());
+
+/// A builtin's leading `--flag` and `--option value` arguments, and the ones after them.
+struct BuiltinArgs<'a> {
+  flags: HashSet<&'a str>,
+  values: HashMap<&'a str, &'a str>,
+  rest: &'a [String],
+}
+
+/// Splits the options `cmd` accepts off the front of its arguments, up to the first other one or a `--`. Options
+/// with values may be given as `--option value` or `--option=value`.
+fn parse_builtin_args<'a>(
+  cmd: &str,
+  args: &'a [String],
+  flags: &[&str],
+  with_values: &[&str],
+) -> Result<BuiltinArgs<'a>, DoitError> {
+  let mut parsed = BuiltinArgs { flags: HashSet::new(), values: HashMap::new(), rest: args };
+  while let Some((arg, rest)) = parsed.rest.split_first() {
+    match arg.as_str() {
+      "--" => {
+        parsed.rest = rest;
+        break;
+      }
+      flag if flags.contains(&flag) => {
+        parsed.flags.insert(flag);
+        parsed.rest = rest;
+      }
+      option if option.split_once('=').is_some_and(|(option, _)| with_values.contains(&option)) => {
+        let (option, value) = option.split_once('=').unwrap_or_default();
+        parsed.values.insert(option, value);
+        parsed.rest = rest;
+      }
+      option if with_values.contains(&option) => {
+        let (value, rest) =
+          rest.split_first().ok_or_else(|| DoitError::Builtin(format!("&{}: {} needs a value", cmd, option)))?;
+        parsed.values.insert(option, value);
+        parsed.rest = rest;
+      }
+      option if option.starts_with("--") => {
+        return Err(DoitError::Builtin(format!("&{}: unknown option {}", cmd, option)));
+      }
+      _ => break,
+    }
+  }
+  Ok(parsed)
+}
+
+/// A path given to a builtin, relative to the step's `cwd` when it has one.
+pub fn builtin_path(options: &RunOptions, path: &str) -> PathBuf {
+  match &options.cwd {
+    Some(cwd) => cwd.join(path),
+    None => PathBuf::from(path),
+  }
+}
+
+/// `&write-file [--append] [--mode 644] <path> [line...]` writes the lines, each ending in a newline.
+fn builtin_write_file(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("write-file", args, &["--append"], &["--mode"])?;
+  let (path, lines) =
+    parsed.rest.split_first().ok_or_else(|| DoitError::Builtin("&write-file needs a path to write".into()))?;
+  let path = builtin_path(options, path);
+  let fail = |e: std::io::Error| DoitError::Builtin(format!("&write-file: {}: {}", path.display(), e));
+  let content: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+  create_parent(&path).map_err(fail)?;
+  let mut file = std::fs::OpenOptions::new()
+    .create(true)
+    .write(true)
+    .append(parsed.flags.contains("--append"))
+    .truncate(!parsed.flags.contains("--append"))
+    .open(&path)
+    .map_err(fail)?;
+  file.write_all(content.as_bytes()).map_err(fail)?;
+  if let Some(mode) = parsed.values.get("--mode") {
+    let mode = u32::from_str_radix(mode, 8)
+      .map_err(|_| DoitError::Builtin(format!("&write-file: --mode must be octal, like 644, not {}", mode)))?;
+    std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(mode)).map_err(fail)?;
+  }
+  Ok(())
+}
+
+/// Creates the directory a file is about to be written in, if it isn't there yet.
+fn create_parent(path: &Path) -> std::io::Result<()> {
+  match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+    Some(dir) => std::fs::create_dir_all(dir),
+    None => Ok(()),
+  }
+}
+
+/// The paths a file builtin is given, with glob patterns expanded to the paths they match.
+fn builtin_paths(cmd: &str, args: &[String], options: &RunOptions) -> Result<Vec<PathBuf>, DoitError> {
+  let mut paths = Vec::new();
+  for arg in args {
+    let path = builtin_path(options, arg);
+    if !arg.contains(['*', '?', '[']) {
+      paths.push(path);
+      continue;
+    }
+    let matched =
+      glob_paths(&path.display().to_string()).map_err(|e| DoitError::Builtin(format!("&{}: {}", cmd, e)))?;
+    if matched.is_empty() {
+      return Err(DoitError::Builtin(format!("&{}: {} matched nothing", cmd, arg)));
+    }
+    paths.extend(matched.into_iter().map(PathBuf::from));
+  }
+  Ok(paths)
+}
+
+/// Copies a file, or with `recursive` a directory and everything in it.
+fn copy_path(from: &Path, to: &Path, recursive: bool) -> std::io::Result<()> {
+  if !from.is_dir() {
+    return std::fs::copy(from, to).map(|_| ());
+  }
+  if !recursive {
+    return Err(std::io::Error::other("is a directory; use --recursive"));
+  }
+  std::fs::create_dir_all(to)?;
+  for entry in std::fs::read_dir(from)? {
+    let entry = entry?;
+    copy_path(&entry.path(), &to.join(entry.file_name()), recursive)?;
+  }
+  Ok(())
+}
+
+/// `&copy [--recursive] <from...> <to>` and `&move <from...> <to>`; with several sources `to` must be a directory.
+fn builtin_copy_or_move(cmd: &str, args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let flags: &[&str] = if cmd == "copy" { &["--recursive"] } else { &[] };
+  let parsed = parse_builtin_args(cmd, args, flags, &[])?;
+  let (to, from) = match parsed.rest.split_last() {
+    Some((to, from)) if !from.is_empty() => (builtin_path(options, to), builtin_paths(cmd, from, options)?),
+    _ => return Err(DoitError::Builtin(format!("&{} needs at least one path to {} and where to", cmd, cmd))),
+  };
+  if from.len() > 1 && !to.is_dir() {
+    return Err(DoitError::Builtin(format!("&{}: {} is not a directory", cmd, to.display())));
+  }
+  for from in from {
+    let target = match from.file_name() {
+      Some(name) if to.is_dir() => to.join(name),
+      _ => to.clone(),
+    };
+    let fail = |e: std::io::Error| DoitError::Builtin(format!("&{}: {}: {}", cmd, from.display(), e));
+    if cmd == "copy" {
+      copy_path(&from, &target, parsed.flags.contains("--recursive")).map_err(fail)?;
+      continue;
+    }
+    // Renaming fails across filesystems, where the only way is to copy and remove.
+    match std::fs::rename(&from, &target) {
+      Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+        copy_path(&from, &target, true).map_err(fail)?;
+        remove_path(&from, true).map_err(fail)?;
+      }
+      result => result.map_err(fail)?,
+    }
+  }
+  Ok(())
+}
+
+/// Removes a file, or with `recursive` a directory and everything in it.
+fn remove_path(path: &Path, recursive: bool) -> std::io::Result<()> {
+  match std::fs::symlink_metadata(path)?.is_dir() {
+    true if recursive => std::fs::remove_dir_all(path),
+    true => std::fs::remove_dir(path),
+    false => std::fs::remove_file(path),
+  }
+}
+
+/// `&remove [--recursive] [--force] <path...>`, where --force ignores paths that don't exist.
+fn builtin_remove(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("remove", args, &["--recursive", "--force"], &[])?;
+  let force = parsed.flags.contains("--force");
+  let paths = match builtin_paths("remove", parsed.rest, options) {
+    Err(_) if force => return Ok(()),
+    paths => paths?,
+  };
+  for path in paths {
+    match remove_path(&path, parsed.flags.contains("--recursive")) {
+      Err(e) if force && e.kind() == std::io::ErrorKind::NotFound => {}
+      result => result.map_err(|e| DoitError::Builtin(format!("&remove: {}: {}", path.display(), e)))?,
+    }
+  }
+  Ok(())
+}
+
+/// `&mkdir [--parents] <dir...>`, where --parents also creates missing parents and allows existing directories.
+fn builtin_mkdir(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("mkdir", args, &["--parents"], &[])?;
+  for arg in parsed.rest {
+    let dir = builtin_path(options, arg);
+    let made =
+      if parsed.flags.contains("--parents") { std::fs::create_dir_all(&dir) } else { std::fs::create_dir(&dir) };
+    made.map_err(|e| DoitError::Builtin(format!("&mkdir: {}: {}", dir.display(), e)))?;
+  }
+  Ok(())
+}
+
+/// Where `program` is found on the PATH the step's commands get, if it is.
+fn find_in_path(program: &str, options: &RunOptions) -> Option<PathBuf> {
+  if program.contains('/') {
+    return Some(builtin_path(options, program)).filter(|path| path.is_file());
+  }
+  let path = match options.env.get("PATH") {
+    Some(path) => path.clone()?.into(),
+    None if options.env_clear => return None,
+    None => env::var_os("PATH")?,
+  };
+  env::split_paths(&path).map(|dir| dir.join(program)).find(|path| path.is_file())
+}
+
+/// An HTTP client that doesn't take error statuses as errors, trusts the certificates the system does and goes
+/// through the proxy the environment names, if any.
+fn http_agent(timeout: Option<Duration>) -> ureq::Agent {
+  let tls = ureq::tls::TlsConfig::builder().root_certs(ureq::tls::RootCerts::PlatformVerifier).build();
+  let config =
+    ureq::Agent::config_builder().http_status_as_error(false).timeout_global(timeout).tls_config(tls).build();
+  ureq::Agent::new_with_config(config)
+}
+
+fn mib(bytes: u64) -> String {
+  format!("{:.1} MiB", bytes as f64 / (1 << 20) as f64)
+}
+
+/// Copies a download into `file`, showing how far it has got on stderr when that is a terminal.
+fn copy_download(
+  name: &str,
+  body: &mut dyn Read,
+  file: &mut File,
+  done: u64,
+  total: Option<u64>,
+) -> Result<(), String> {
+  let progress = unsafe { libc::isatty(libc::STDERR_FILENO) } != 0 && !settings().quiet;
+  let show = |done: u64| match total {
+    Some(total) if total > 0 => eprint!("\r{}: {} of {} ({}%)\x1b[K", name, mib(done), mib(total), done * 100 / total),
+    _ => eprint!("\r{}: {}\x1b[K", name, mib(done)),
+  };
+  let (mut done, mut shown) = (done, Instant::now());
+  let mut buf = vec![0u8; 1 << 16];
+  loop {
+    check_cancelled().map_err(|e| e.to_string())?;
+    let n = body.read(&mut buf).map_err(|e| e.to_string())?;
+    if n == 0 {
+      break;
+    }
+    file.write_all(&buf[..n]).map_err(|e| e.to_string())?;
+    done += n as u64;
+    if progress && shown.elapsed() >= Duration::from_millis(100) {
+      show(done);
+      shown = Instant::now();
+    }
+  }
+  if progress {
+    show(done);
+    eprintln!();
+  }
+  Ok(())
+}
+
+/// `&download [--sha256 hex] <url> [path]` fetches an http:// or https:// url into path (by default the last part
+/// of the url). A download that is cut short is resumed the next time, and with --sha256 a file that is already
+/// there and matches is left alone.
+fn builtin_download(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("download", args, &[], &["--sha256"])?;
+  let (url, path) = match parsed.rest {
+    [url] => (url, url.trim_end_matches('/').rsplit('/').next().unwrap_or_default()),
+    [url, path] => (url, path.as_str()),
+    _ => return Err(DoitError::Builtin("&download needs a url and optionally where to save it".into())),
+  };
+  if path.is_empty() {
+    return Err(DoitError::Builtin(format!("&download: no file name in {}; say where to save it", url)));
+  }
+  let cwd = env::current_dir().map_err(|e| DoitError::Builtin(format!("&download: {}", e)))?;
+  let path = cwd.join(builtin_path(options, path));
+  let fail = |path: &Path, e: std::io::Error| DoitError::Builtin(format!("&download: {}: {}", path.display(), e));
+  let sha256 = parsed.values.get("--sha256").map(|sha256| sha256.to_lowercase());
+  if let Some(sha256) = &sha256 {
+    if path.is_file() && sha256::file_digest(&path).map_err(|e| fail(&path, e))? == *sha256 {
+      reporter::verbose(&format!("&download: {} is already there", path.display()));
+      return Ok(());
+    }
+  }
+
+  // The .part file sits next to the download, so this makes room for both.
+  create_parent(&path).map_err(|e| fail(&path, e))?;
+  let partial = PathBuf::from(format!("{}.part", path.display()));
+  let failed = |e: String| DoitError::Builtin(format!("&download: {}: {}", url, e));
+  let have = partial.metadata().map_or(0, |metadata| metadata.len());
+  let mut request = http_agent(None).get(url.as_str());
+  if have > 0 {
+    request = request.header("Range", format!("bytes={}-", have));
+  }
+  let mut response = request.call().map_err(|e| failed(e.to_string()))?;
+  let status = response.status().as_u16();
+  // 416 is a server saying there's nothing after what we have, so the last download only missed being renamed.
+  if status != 416 {
+    if !(200..300).contains(&status) {
+      return Err(failed(format!("HTTP status {}", status)));
+    }
+    let resumed = status == 206;
+    let done = if resumed { have } else { 0 };
+    let total = response.body().content_length().map(|length| length + done);
+    let mut file = std::fs::OpenOptions::new()
+      .create(true)
+      .write(true)
+      .append(resumed)
+      .truncate(!resumed)
+      .open(&partial)
+      .map_err(|e| fail(&partial, e))?;
+    let name = path.file_name().map_or_else(|| url.clone(), |name| name.to_string_lossy().into_owned());
+    copy_download(&name, &mut response.body_mut().as_reader(), &mut file, done, total).map_err(failed)?;
+  }
+
+  if let Some(sha256) = &sha256 {
+    let actual = sha256::file_digest(&partial).map_err(|e| fail(&partial, e))?;
+    if actual != *sha256 {
+      let _ = remove_file(&partial);
+      return Err(DoitError::Builtin(format!("&download: {}: expected sha256 {} but got {}", url, sha256, actual)));
+    }
+  }
+  std::fs::rename(&partial, &path).map_err(|e| fail(&path, e))
+}
+
+/// `&env-require <NAME[=regex]...>` fails, listing every problem at once, unless each variable is set (in the
+/// environment the step's commands would get) and non-empty, and matches its regex if one is given.
+fn builtin_env_require(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  if args.is_empty() {
+    return Err(DoitError::Builtin("&env-require needs the names of the variables to check".into()));
+  }
+  let mut problems = Vec::new();
+  for arg in args {
+    let (name, pattern) = match arg.split_once('=') {
+      Some((name, pattern)) => (name, Some(pattern)),
+      None => (arg.as_str(), None),
+    };
+    let value = match options.env.get(name) {
+      Some(value) => value.clone(),
+      None if options.env_clear => None,
+      None => env_var(name),
+    };
+    match (value, pattern) {
+      (None, _) => problems.push(format!("{} is not set", name)),
+      (Some(value), _) if value.is_empty() => problems.push(format!("{} is empty", name)),
+      (Some(value), Some(pattern)) => {
+        let regex =
+          Regex::new(pattern).map_err(|e| DoitError::Builtin(format!("&env-require: {}: bad pattern: {}", name, e)))?;
+        if !regex.is_match(&value) {
+          problems.push(format!("{}={} does not match {}", name, value, pattern));
+        }
+      }
+      _ => {}
+    }
+  }
+  if problems.is_empty() {
+    return Ok(());
+  }
+  Err(DoitError::Builtin(format!("&env-require:\n  {}", problems.join("\n  "))))
+}
+
+/// The first `major[.minor[.patch]]` in a program's --version output.
+fn parse_version(text: &str) -> Option<(u64, u64, u64)> {
+  let caps = VERSION_RE.captures(text)?;
+  let part = |i| caps.get(i).map_or(Some(0), |part: regex::Match| part.as_str().parse().ok());
+  Some((part(1)?, part(2)?, part(3)?))
+}
+
+/// `&which <program[>=version]...>` fails, listing every problem at once, unless each program is on PATH and, when
+/// a version is given with `>=`, `>`, `<=`, `<` or `=`, its `--version` output says it is one.
+fn builtin_which(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  if args.is_empty() {
+    return Err(DoitError::Builtin("&which needs the names of the programs to look for".into()));
+  }
+  let mut problems = Vec::new();
+  for arg in args {
+    let (program, requirement) = match REQUIREMENT_RE.captures(arg) {
+      Some(caps) => {
+        let part = |i| caps.get(i).map_or("", |part: regex::Match<'_>| part.as_str());
+        (part(1), Some((part(2), part(3))))
+      }
+      None => (arg.as_str(), None),
+    };
+    let Some(path) = find_in_path(program, options) else {
+      problems.push(format!("{} is not on PATH", program));
+      continue;
+    };
+    let Some((op, wanted)) = requirement else {
+      continue;
+    };
+    let wanted_version = parse_version(wanted)
+      .ok_or_else(|| DoitError::Builtin(format!("&which: {}: bad version {}", program, wanted)))?;
+    let output = Command::new(&path).arg("--version").stdin(Stdio::null()).stderr(Stdio::piped()).output();
+    let version = output.ok().and_then(|output| {
+      parse_version(&format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)))
+    });
+    let Some(version) = version else {
+      problems.push(format!("{}: can't tell its version from {} --version", program, path.display()));
+      continue;
+    };
+    let ok = match op {
+      ">=" => version >= wanted_version,
+      ">" => version > wanted_version,
+      "<=" => version <= wanted_version,
+      "<" => version < wanted_version,
+      // `=2` or `=2.1` only asks for that much of the version to match.
+      _ => match wanted.split('.').count() {
+        1 => version.0 == wanted_version.0,
+        2 => (version.0, version.1) == (wanted_version.0, wanted_version.1),
+        _ => version == wanted_version,
+      },
+    };
+    if !ok {
+      let (major, minor, patch) = version;
+      problems.push(format!("{} is version {}.{}.{}, not {}{}", program, major, minor, patch, op, wanted));
+    }
+  }
+  if problems.is_empty() {
+    return Ok(());
+  }
+  Err(DoitError::Builtin(format!("&which:\n  {}", problems.join("\n  "))))
+}
+
+/// `&template <input> <output>` writes input rendered as a template with the task's keys, as if it were an argument
+/// starting with `:`.
+fn builtin_template(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let [input, output] = args else {
+    return Err(DoitError::Builtin("&template needs the file to render and where to write it".into()));
+  };
+  let (input, output) = (builtin_path(options, input), builtin_path(options, output));
+  let template = std::fs::read_to_string(&input)
+    .map_err(|e| DoitError::Builtin(format!("&template: {}: {}", input.display(), e)))?;
+  let rendered = render_value(&options.table, &template)
+    .map_err(|e| e.map_message(|e| format!("&template: {}: {}", input.display(), e)))?;
+  write_file(&output, rendered).map_err(|e| DoitError::Builtin(format!("&template: {}: {}", output.display(), e)))
+}
+
+/// The status code of a GET of an http:// or https:// url, after any redirects.
+fn http_status(url: &str, timeout: Duration) -> Result<u16, String> {
+  let response = http_agent(Some(timeout)).get(url).call().map_err(|e| e.to_string())?;
+  Ok(response.status().as_u16())
+}
+
+/// Connects to `host:port`, trying each address it resolves to.
+fn connect(address: &str, timeout: Duration) -> Result<std::net::TcpStream, String> {
+  use std::net::ToSocketAddrs;
+  let mut last_error = format!("{}: no addresses", address);
+  for socket in address.to_socket_addrs().map_err(|e| format!("{}: {}", address, e))? {
+    match std::net::TcpStream::connect_timeout(&socket, timeout) {
+      Ok(stream) => return Ok(stream),
+      Err(e) => last_error = format!("{}: {}", address, e),
+    }
+  }
+  Err(last_error)
+}
+
+/// `&wait-for [--timeout 30s] [--interval 1s] [--status 200] <condition...>` waits until every condition holds:
+/// `tcp://host:port` accepts connections, an `http://` or `https://` url answers with the status (by default any
+/// 2xx), or a path exists. The options may also come after the conditions.
+fn builtin_wait_for(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let with_values = ["--timeout", "--interval", "--status"];
+  let (mut option_args, mut conditions) = (Vec::new(), Vec::new());
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    if !arg.starts_with("--") {
+      conditions.push(arg.clone());
+      continue;
+    }
+    option_args.push(arg.clone());
+    if with_values.contains(&arg.as_str()) {
+      option_args.extend(args.next().cloned());
+    }
+  }
+  let parsed = parse_builtin_args("wait-for", &option_args, &[], &with_values)?;
+  let setting = |name: &str, default: &str| -> Result<Duration, DoitError> {
+    let value = parsed.values.get(name).copied().unwrap_or(default);
+    parse_duration(value).map_err(|e| DoitError::Builtin(format!("&wait-for: {}: {}", name, e)))
+  };
+  let (timeout, interval) = (setting("--timeout", "30s")?, setting("--interval", "1s")?);
+  let status = match parsed.values.get("--status") {
+    Some(status) => {
+      Some(status.parse::<u16>().map_err(|_| DoitError::Builtin(format!("&wait-for: bad --status {}", status)))?)
+    }
+    None => None,
+  };
+  if conditions.is_empty() {
+    return Err(DoitError::Builtin("&wait-for needs something to wait for".into()));
+  }
+
+  let deadline = Instant::now() + timeout;
+  for condition in &conditions {
+    loop {
+      let attempt = interval.min(deadline.saturating_duration_since(Instant::now())).max(Duration::from_millis(100));
+      let result = if let Some(address) = condition.strip_prefix("tcp://") {
+        connect(address, attempt).map(|_| ())
+      } else if condition.starts_with("http://") || condition.starts_with("https://") {
+        match http_status(condition, attempt) {
+          Ok(code) if status.map_or((200..300).contains(&code), |status| status == code) => Ok(()),
+          Ok(code) => Err(format!("status {}", code)),
+          Err(e) => Err(e),
+        }
+      } else {
+        let path = builtin_path(options, condition);
+        if path.exists() {
+          Ok(())
+        } else {
+          Err(format!("{} does not exist", path.display()))
+        }
+      };
+      match result {
+        Ok(()) => break,
+        Err(e) if Instant::now() + interval > deadline => {
+          return Err(DoitError::Builtin(format!("&wait-for: {}: gave up after {:?}: {}", condition, timeout, e)));
+        }
+        Err(e) => reporter::verbose(&format!("&wait-for: {}: {}", condition, e)),
+      }
+      thread::sleep(interval);
+      check_cancelled()?;
+    }
+  }
+  Ok(())
+}
+
+/// Rewrites a file with what `edit` makes of its contents (`None` when it doesn't exist), leaving it untouched when
+/// that is no change. With `backup` the old contents are kept next to it with a `.bak` suffix.
+fn edit_file(
+  cmd: &str,
+  path: &Path,
+  backup: bool,
+  edit: impl FnOnce(Option<&str>) -> Result<String, DoitError>,
+) -> Result<(), DoitError> {
+  let fail = |e: std::io::Error| DoitError::Builtin(format!("&{}: {}: {}", cmd, path.display(), e));
+  let old = match std::fs::read_to_string(path) {
+    Ok(old) => Some(old),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+    Err(e) => return Err(fail(e)),
+  };
+  let new = edit(old.as_deref())?;
+  if old.as_deref() == Some(new.as_str()) {
+    reporter::verbose(&format!("&{}: {} is already as wanted", cmd, path.display()));
+    return Ok(());
+  }
+  if let (true, Some(old)) = (backup, &old) {
+    write_file(format!("{}.bak", path.display()), old).map_err(fail)?;
+  }
+  write_file(path, new).map_err(fail)
+}
+
+fn builtin_regex(cmd: &str, pattern: &str) -> Result<Regex, DoitError> {
+  Regex::new(pattern).map_err(|e| DoitError::Builtin(format!("&{}: bad pattern {}: {}", cmd, pattern, e)))
+}
+
+/// `&replace-in-file [--backup] [--required] <path> <regex> <replacement>` replaces every match, where the
+/// replacement may refer to groups as `$1` or `${name}`. With --required it is an error for nothing to match.
+fn builtin_replace_in_file(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("replace-in-file", args, &["--backup", "--required"], &[])?;
+  let [path, pattern, replacement] = parsed.rest else {
+    return Err(DoitError::Builtin("&replace-in-file needs a path, a pattern and its replacement".into()));
+  };
+  let (path, regex) = (builtin_path(options, path), builtin_regex("replace-in-file", pattern)?);
+  edit_file("replace-in-file", &path, parsed.flags.contains("--backup"), |old| {
+    let old = old.ok_or_else(|| DoitError::Builtin(format!("&replace-in-file: {}: no such file", path.display())))?;
+    if parsed.flags.contains("--required") && !regex.is_match(old) {
+      return Err(DoitError::Builtin(format!("&replace-in-file: {}: nothing matches {}", path.display(), pattern)));
+    }
+    Ok(regex.replace_all(old, replacement.as_str()).into())
+  })
+}
+
+/// `&line-in-file [--backup] [--regex pattern] [--absent] <path> <line>` makes sure the file has the line: the
+/// first line matching the pattern (or else equal to the line) is replaced by it, or it is added at the end,
+/// creating the file if need be. With --absent the matching lines are removed instead.
+fn builtin_line_in_file(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("line-in-file", args, &["--backup", "--absent"], &["--regex"])?;
+  let [path, line] = parsed.rest else {
+    return Err(DoitError::Builtin("&line-in-file needs a path and a line".into()));
+  };
+  let path = builtin_path(options, path);
+  let regex = parsed.values.get("--regex").map(|pattern| builtin_regex("line-in-file", pattern)).transpose()?;
+  let matches = |candidate: &str| regex.as_ref().map_or(candidate == line, |regex| regex.is_match(candidate));
+  edit_file("line-in-file", &path, parsed.flags.contains("--backup"), |old| {
+    let old = old.unwrap_or_default();
+    let mut lines: Vec<&str> = old.lines().collect();
+    if parsed.flags.contains("--absent") {
+      lines.retain(|candidate| !matches(candidate));
+    } else {
+      match lines.iter().position(|candidate| matches(candidate)) {
+        Some(found) => lines[found] = line,
+        None => lines.push(line),
+      }
+    }
+    Ok(lines.iter().map(|line| format!("{}\n", line)).collect())
+  })
+}
+
+/// `&archive [--directory dir] <archive> <path...>` archives the paths, in the format the archive's name ends with:
+/// .tar, .tar.gz or .tgz, .tar.zst or .tzst, or .zip. The paths are taken relative to, and named relative to, the
+/// --directory when one is given.
+fn builtin_archive(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("archive", args, &[], &["--directory"])?;
+  let Some((archive, paths)) = parsed.rest.split_first().filter(|(_, paths)| !paths.is_empty()) else {
+    return Err(DoitError::Builtin("&archive needs the archive to write and the paths to put in it".into()));
+  };
+  let format = archive::Format::from_name(archive).ok_or_else(|| {
+    DoitError::Builtin(format!("&archive: {}: name it .tar, .tar.gz, .tgz, .tar.zst, .tzst or .zip", archive))
+  })?;
+  let base = builtin_path(options, parsed.values.get("--directory").copied().unwrap_or("."));
+  let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+  archive::create(&builtin_path(options, archive), format, &base, &paths)
+    .map_err(|e| DoitError::Builtin(format!("&archive: {}", e)))
+}
+
+/// `&extract [--strip n] <archive> [dir]` extracts a tar, tar.gz, tar.zst or zip archive into dir (by default the
+/// current one), dropping the first n parts of each path.
+fn builtin_extract(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("extract", args, &[], &["--strip"])?;
+  let (archive, dest) = match parsed.rest {
+    [archive] => (archive, "."),
+    [archive, dest] => (archive, dest.as_str()),
+    _ => return Err(DoitError::Builtin("&extract needs an archive and optionally where to extract it".into())),
+  };
+  let strip = match parsed.values.get("--strip") {
+    Some(strip) => {
+      strip.parse().map_err(|_| DoitError::Builtin(format!("&extract: --strip must be a number, not {}", strip)))?
+    }
+    None => 0,
+  };
+  archive::extract(&builtin_path(options, archive), &builtin_path(options, dest), strip)
+    .map_err(|e| DoitError::Builtin(format!("&extract: {}", e)))
+}
+
+/// What a plugin is told on stdin about the builtin it is run as: the task's name and keys (but not its
+/// subtables), the builtin's name and args, the directory it runs in and, unless it is sandboxed, its environment.
+fn builtin_context(cmd: &str, args: &[String], options: &RunOptions, with_env: bool) -> Vec<u8> {
+  let mut env: BTreeMap<String, String> = if options.env_clear { BTreeMap::new() } else { env::vars().collect() };
+  for (key, value) in &options.env {
+    match value {
+      Some(value) => env.insert(key.clone(), value.clone()),
+      None => env.remove(key),
+    };
+  }
+  let table: serde_json::Map<String, serde_json::Value> = options
+    .table
+    .iter()
+    .filter_map(|(key, item)| item.as_value().map(|value| (key.to_string(), toml_json(value))))
+    .collect();
+  let mut context = serde_json::json!({
+    "task": options.task,
+    "builtin": cmd,
+    "args": args,
+    "table": table,
+    "cwd": builtin_cwd(options).display().to_string(),
+  });
+  if with_env {
+    context["env"] = serde_json::json!(env);
+  }
+  context.to_string().into_bytes()
+}
+
+/// The directory a builtin's step runs in.
+fn builtin_cwd(options: &RunOptions) -> PathBuf {
+  let cwd = env::current_dir().unwrap_or_default();
+  options.cwd.as_ref().map_or(cwd.clone(), |dir| cwd.join(dir))
+}
+
+/// Serializes changes to the state file, as steps in parallel may each set a key.
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// The project's key-value state, `.doit/state.toml` next to the doit.toml, that &state-set writes and
+/// `%state:key%` and &state-get read, for things to remember between runs.
+fn state_file() -> PathBuf {
+  doit_dir().join("state.toml")
+}
+
+fn load_state(path: &Path) -> Result<DocumentMut, String> {
+  match std::fs::read_to_string(path) {
+    Ok(text) => text.parse::<DocumentMut>().map_err(|e| format!("{}: {}", path.display(), e)),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DocumentMut::new()),
+    Err(e) => Err(format!("{}: {}", path.display(), e)),
+  }
+}
+
+/// The value state has for `key`, if any.
+pub fn state_value(key: &str) -> Result<Option<String>, String> {
+  let path = state_file();
+  let state = load_state(&path)?;
+  match state.get(key) {
+    None => Ok(None),
+    Some(value) => match value.as_str() {
+      Some(value) => Ok(Some(value.to_string())),
+      None => Err(format!("{}: {} is not a string", path.display(), key)),
+    },
+  }
+}
+
+/// `&state-set <key> <value>` remembers the value for key in the project's state, and `&state-set --unset <key>`
+/// forgets it.
+fn builtin_state_set(args: &[String]) -> Result<(), DoitError> {
+  let parsed = parse_builtin_args("state-set", args, &["--unset"], &[])?;
+  let unset = parsed.flags.contains("--unset");
+  let (key, value) = match (parsed.rest, unset) {
+    ([key], true) => (key, None),
+    ([key, value], false) => (key, Some(value)),
+    _ => return Err(DoitError::Builtin("&state-set needs a key and a value, or --unset and a key".into())),
+  };
+  let _lock = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+  let path = state_file();
+  let fail = |e: String| DoitError::Builtin(format!("&state-set: {}", e));
+  let mut state = load_state(&path).map_err(fail)?;
+  match value {
+    Some(value) => {
+      state.insert(key, toml_edit::value(value.as_str()));
+    }
+    None => {
+      state.remove(key);
+    }
+  }
+  create_parent(&path).map_err(|e| fail(format!("{}: {}", path.display(), e)))?;
+  write_file(&path, state.to_string()).map_err(|e| fail(format!("{}: {}", path.display(), e)))
+}
+
+/// `&state-get <key> [default]` prints the value remembered for key, or the default, failing when there is
+/// neither. With `capture = "name"` the value is captured rather than printed.
+fn builtin_state_get(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let (key, default) = match args {
+    [key] => (key, None),
+    [key, default] => (key, Some(default)),
+    _ => return Err(DoitError::Builtin("&state-get needs a key, and may be given a default".into())),
+  };
+  let value = state_value(key)
+    .map_err(|e| DoitError::Builtin(format!("&state-get: {}", e)))?
+    .or_else(|| default.cloned())
+    .ok_or_else(|| DoitError::Builtin(format!("&state-get: nothing is remembered for {}", key)))?;
+  match &options.capture {
+    Some(name) => {
+      CAPTURES.with(|captures| captures.borrow_mut().insert(name.clone(), value));
+    }
+    None => println!("{}", value),
+  }
+  Ok(())
+}
+
+/// What `[doit.plugins]` grants the plugin `name`: an array of `"render"`, `"read"` and `"write"`.
+fn plugin_grants(name: &str) -> Result<Vec<String>, DoitError> {
+  let config = loaded_config();
+  let Some(grants) =
+    config.as_ref().and_then(|config| config.settings.get("plugins")).and_then(|plugins| plugins.get(name))
+  else {
+    return Ok(vec![]);
+  };
+  let invalid = || {
+    DoitError::Config(format!(
+      "[{}.plugins] {} must be an array of \"render\", \"read\" and \"write\"",
+      SETTINGS_SECTION, name
+    ))
+  };
+  let grants = grants.as_array().ok_or_else(invalid)?;
+  grants
+    .iter()
+    .map(|grant| grant.as_str().filter(|grant| ["render", "read", "write"].contains(grant)).map(String::from))
+    .collect::<Option<_>>()
+    .ok_or_else(invalid)
+}
+
+/// Runs `.doit/plugins/name.wasm` in doit's own WASI host (see the wasm module), with what `[doit.plugins]` grants it.
+fn run_wasm_builtin(cmd: &str, plugin: &Path, args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let sink = |redirect: &Option<(PathBuf, bool)>, default: wasm::Sink| -> Result<wasm::Sink, DoitError> {
+    Ok(redirect.as_ref().map(open_redirect).transpose()?.map_or(default, wasm::Sink::File))
+  };
+  let stdout = match options.capture {
+    Some(_) => wasm::Sink::Buffer(vec![]),
+    None => sink(&options.stdout, wasm::Sink::Stdout)?,
+  };
+  let table = options.table.clone();
+  let plugin_args = wasm::Plugin {
+    name: cmd.to_string(),
+    args: args.to_vec(),
+    stdin: builtin_context(cmd, args, options, false),
+    cwd: builtin_cwd(options),
+    grants: plugin_grants(cmd)?,
+    render: Box::new(move |template| render_value(&table, template).map_err(|e| e.to_string())),
+    stdout,
+    stderr: sink(&options.stderr, wasm::Sink::Stderr)?,
+    timeout: options.timeout,
+    cancelled: || CANCELLED.load(Ordering::SeqCst),
+  };
+  let (status, captured) =
+    wasm::run(plugin, plugin_args).map_err(|e| DoitError::Builtin(format!("&{}: {}", cmd, e)))?;
+  STEP_STATUS.with(|step| step.borrow_mut().exit_code = Some(status));
+  if status != 0 {
+    return Err(DoitError::Child { message: format!("&{} failed with exit status: {}", cmd, status), code: status });
+  }
+  if let Some(name) = &options.capture {
+    let captured = String::from_utf8_lossy(&captured).trim_end_matches(['\n', '\r']).to_string();
+    CAPTURES.with(|captures| captures.borrow_mut().insert(name.clone(), captured));
+  }
+  Ok(())
+}
+
+/// Runs an unknown `&name` as a plugin: `.doit/plugins/name.wasm` next to the doit.toml, run sandboxed in doit's
+/// own WASI host, or else the `doit-builtin-name` program on PATH. Either gets the args, and builtin_context on stdin.
+fn run_external_builtin(cmd: &str, args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  let plugin = doit_dir().join("plugins").join(format!("{}.wasm", cmd));
+  if plugin.is_file() {
+    return run_wasm_builtin(cmd, &plugin, args, options);
+  }
+
+  let program = format!("doit-builtin-{}", cmd);
+  let path = find_in_path(&program, options).ok_or_else(|| {
+    DoitError::Builtin(format!("{} is not a known builtin, and there is no {} on PATH.", cmd, program))
+  })?;
+  let argv = [vec![path.display().to_string()], args.to_vec()].concat();
+  run_program(&[&argv], options, Some(builtin_context(cmd, args, options, true)))
+}
+
+pub fn run_builtin(cmd: &str, args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  reporter::verbose(&format!("builtin: {}: {:?}", cmd, args));
+  match cmd {
+    "task" => {
+      let (name, task_args) =
+        args.split_first().ok_or_else(|| DoitError::Builtin("&task needs the name of a command to run".into()))?;
+      let config = loaded_config().ok_or_else(|| DoitError::Builtin("&task: no configuration loaded".into()))?;
+      let (table, actual_cmd) = find_task(&config, name)?;
+      process_cmd(&config, &actual_cmd, table, task_args)
+    }
+    "wait" => builtin_wait(args),
+    "write-file" => builtin_write_file(args, options),
+    "copy" | "move" => builtin_copy_or_move(cmd, args, options),
+    "remove" => builtin_remove(args, options),
+    "mkdir" => builtin_mkdir(args, options),
+    "download" => builtin_download(args, options),
+    "env-require" => builtin_env_require(args, options),
+    "which" => builtin_which(args, options),
+    "template" => builtin_template(args, options),
+    "wait-for" => builtin_wait_for(args, options),
+    "replace-in-file" => builtin_replace_in_file(args, options),
+    "line-in-file" => builtin_line_in_file(args, options),
+    "archive" => builtin_archive(args, options),
+    "extract" => builtin_extract(args, options),
+    "state-set" => builtin_state_set(args),
+    "state-get" => builtin_state_get(args, options),
+    _ => run_external_builtin(cmd, args, options),
+  }
+}
@@ -0,0 +1,528 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Finding what is wrong with the tasks for --check: unknown keys, and placeholders nothing fills in.
+use crate::{
+  config::{all_tasks, get_section, get_string_array, Config, PLATFORM_SECTIONS},
+  docs, settings,
+  show::is_hidden,
+  task::{env_var, get_depends, get_pipeline, load_dotenv, with_dotenv},
+  template::{
+    meta_placeholder_name, template_mode, TemplateMode, ASCII_SUB1, DATE_RE, FILTER_CALL_RE, POSITIONAL_RE, VAR_RE,
+  },
+};
+use std::collections::HashSet;
+use toml_edit::{Array, Item, Table, TableLike, Value};
+
+/// The keys a task table can have, besides the string values its templates use as variables.
+const TASK_KEYS: &[&str] = &[
+  "command",
+  "script",
+  "pipeline",
+  "pre",
+  "post",
+  "finally",
+  "on_success",
+  "on_failure",
+  "description",
+  "aliases",
+  "tags",
+  "help",
+  "examples",
+  "hidden",
+  "args",
+  "params",
+  "depends",
+  "inputs",
+  "outputs",
+  "watch",
+  "confirm",
+  "profile",
+  "dotenv",
+  "lock",
+  "lock_wait",
+  "foreach",
+  "matrix",
+  "template",
+  "only_on",
+  "skip_on",
+];
+
+/// The settings a step can have in its inline table, which the task table can also set for all of its steps.
+const STEP_SETTINGS: &[&str] = &[
+  "cwd",
+  "env",
+  "env_clear",
+  "env_mode",
+  "env_allow",
+  "shell",
+  "shell_cmd",
+  "interpreter",
+  "timeout",
+  "retries",
+  "retry_delay",
+  "retry_backoff",
+  "glob_empty",
+  "stdout",
+  "stderr",
+  "stdin",
+  "ok_codes",
+  "pty",
+  "nice",
+  "rlimit_as",
+  "rlimit_nofile",
+  "pipefail",
+  "quiet",
+];
+
+/// The keys only a step's inline table has.
+const STEP_KEYS: &[&str] = &["command", "script", "pipeline", "capture", "background", "name", "only_on", "skip_on"];
+
+/// The settings that are templates whether or not they start with `:`.
+const TEMPLATED_SETTINGS: &[&str] = &["cwd", "stdin", "stdout", "stderr"];
+
+/// How many single-character edits, counting swapping two neighbours as one, turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+  let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+  let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+  for i in 1..=a.len() {
+    let mut row = vec![i; b.len() + 1];
+    for j in 1..=b.len() {
+      let cost = usize::from(a[i - 1] != b[j - 1]);
+      row[j] = (rows[i - 1][j] + 1).min(row[j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+      if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+        row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+      }
+    }
+    rows.push(row);
+  }
+  rows[a.len()][b.len()]
+}
+
+/// The candidates closest to `word`, if they are close enough for `word` to be a misspelling of them.
+fn close_matches<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+  let limit = (word.chars().count() / 5).max(1);
+  let mut matches: Vec<_> = candidates
+    .into_iter()
+    .map(|candidate| (edit_distance(word, candidate), candidate))
+    .filter(|(distance, _)| *distance <= limit)
+    .collect();
+  let best = matches.iter().map(|(distance, _)| *distance).min();
+  matches.retain(|(distance, _)| Some(*distance) == best);
+  let mut matches: Vec<_> = matches.into_iter().map(|(_, candidate)| candidate).collect();
+  matches.dedup();
+  matches
+}
+
+fn did_you_mean<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+  close_matches(word, candidates).into_iter().next()
+}
+
+/// "; did you mean ...?" naming the commands and aliases `name` could be a misspelling of, if there are any.
+pub fn task_suggestion(config: &Config, name: &str) -> String {
+  let tasks = all_tasks("", config.doc.as_table());
+  let names = tasks
+    .iter()
+    .filter(|(task, table)| !is_hidden(task.rsplit('.').next().unwrap_or(task), table))
+    .map(|(task, _)| task.as_str())
+    .chain(config.aliases.keys().map(String::as_str));
+  match close_matches(name, names).as_slice() {
+    [] => String::new(),
+    matches => format!("; did you mean {}?", matches.join(" or ")),
+  }
+}
+
+fn suggestion<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+  did_you_mean(word, candidates).map_or_else(String::new, |known| format!(" (did you mean {}?)", known))
+}
+
+fn task_key_names() -> impl Iterator<Item = &'static str> {
+  TASK_KEYS.iter().chain(STEP_SETTINGS).chain(PLATFORM_SECTIONS).copied()
+}
+
+fn step_key_names() -> impl Iterator<Item = &'static str> {
+  STEP_KEYS.iter().chain(STEP_SETTINGS).copied()
+}
+
+/// The keys of a task, and of its steps' inline tables, that doit doesn't know, as --check finds them.
+pub fn unknown_keys(config: &Config, table: &Table) -> Vec<String> {
+  let mut check = Check::new(config, table);
+  check.task();
+  check.unknown
+}
+
+/// The problems --check found in one task.
+struct Check<'a> {
+  config: &'a Config,
+  table: &'a Table,
+  /// The keys templates can refer to: the task's string values and the ones doit adds while it runs.
+  available: HashSet<String>,
+  /// The keys some template refers to, so values nothing uses can be pointed out.
+  used: HashSet<String>,
+  lenient: bool,
+  errors: Vec<String>,
+  warnings: Vec<String>,
+  /// The keys doit doesn't know, but for strings some template uses or that aren't close to a key it does, which
+  /// are taken to be the task's template values. Running the task warns about these too.
+  unknown: Vec<String>,
+}
+
+impl<'a> Check<'a> {
+  fn new(config: &'a Config, table: &'a Table) -> Self {
+    let mut available: HashSet<String> =
+      table.iter().filter(|(_, value)| value.is_str()).map(|(key, _)| key.to_string()).collect();
+    available.extend(["item", "failed_step", "exit_code"].map(String::from));
+    available.extend(settings().overrides.iter().map(|(key, _)| key.clone()));
+    if let Some(params) = table.get("params").and_then(Item::as_table_like) {
+      available.extend(params.iter().map(|(name, _)| format!("arg:{}", name)));
+    }
+    if let Some(matrix) = table.get("matrix").and_then(Item::as_table_like) {
+      available.extend(matrix.iter().map(|(key, _)| format!("matrix:{}", key)));
+    }
+    // A key only some profiles give a value is there whenever the profile is.
+    let profiles = table.get("profile").and_then(Item::as_table_like).into_iter().flat_map(|profiles| profiles.iter());
+    for profile in profiles.filter_map(|(_, profile)| profile.as_table_like()) {
+      available.extend(profile.iter().filter(|(_, value)| value.is_str()).map(|(key, _)| key.to_string()));
+    }
+    let lenient = matches!(template_mode(table), Ok(TemplateMode::Lenient) | Ok(TemplateMode::Off));
+    Check { config, table, available, used: HashSet::new(), lenient, errors: vec![], warnings: vec![], unknown: vec![] }
+  }
+
+  /// Checks the placeholders of a template: a string starting with `:`, or any string when `always` is set.
+  fn template(&mut self, place: &str, text: &str, always: bool, stack: &mut Vec<String>) {
+    let text = match text.strip_prefix(':') {
+      Some(text) => text,
+      None if always => text,
+      None => return,
+    };
+    if matches!(template_mode(self.table), Ok(TemplateMode::Off)) {
+      return;
+    }
+    let masked = text.replace("%%", ASCII_SUB1);
+    let masked = DATE_RE.replace_all(&masked, "");
+    for caps in VAR_RE.captures_iter(&masked) {
+      let name = &caps[1];
+      if let Some(var) = name.strip_prefix("env:") {
+        if !var.contains(':') && env_var(var).is_none() {
+          self.warnings.push(format!("{}: %env:{}% is not set here", place, var));
+        }
+        continue;
+      }
+      if ["sh:", "glob:", "capture:", "state:"].iter().any(|prefix| name.starts_with(prefix))
+        || POSITIONAL_RE.is_match(&caps[0])
+        || name == "args"
+        || name.contains(":-")
+      {
+        continue;
+      }
+      let key = match FILTER_CALL_RE.captures(name) {
+        Some(call) => call.get(2).map_or("", |key| key.as_str()).to_string(),
+        None => name.split('|').next().unwrap_or_default().to_string(),
+      };
+      if meta_placeholder_name(&key) {
+        continue;
+      }
+      if !self.available.contains(&key) {
+        let problem = format!("{}: {} refers to {}, which the task doesn't define", place, &caps[0], key);
+        if self.lenient {
+          self.warnings.push(problem);
+        } else {
+          self.errors.push(problem);
+        }
+        continue;
+      }
+      self.used.insert(key.clone());
+      let value = self.table.get(&key).and_then(Item::as_str).unwrap_or_default();
+      if value.contains('%') && !stack.contains(&key) {
+        stack.push(key.clone());
+        self.template(&format!("{} (in {})", place, key), value, true, stack);
+        stack.pop();
+      }
+    }
+  }
+
+  /// Checks a command: an argv array, an array of them piped together, or (with shell = true) a string.
+  fn command(&mut self, place: &str, command: &Item, shell: bool) {
+    match (command.as_array(), command.as_str()) {
+      (Some(stages), _) if stages.iter().next().is_some_and(Value::is_array) => {
+        for (i, stage) in stages.iter().enumerate() {
+          match stage.as_array() {
+            Some(argv) => self.argv(&format!("{}[{}]", place, i), argv),
+            None => self.errors.push(format!("{}[{}]: every pipeline stage must be an argv array", place, i)),
+          }
+        }
+      }
+      (Some(argv), _) => self.argv(place, argv),
+      (None, Some(script)) if shell => self.template(place, script, false, &mut vec![]),
+      (None, Some(_)) => {
+        self.errors.push(format!("{}: command is a string; set shell = true to run it through the shell", place))
+      }
+      _ => self.errors.push(format!("{}: command is not an array or string", place)),
+    }
+  }
+
+  fn argv(&mut self, place: &str, argv: &Array) {
+    if argv.is_empty() {
+      self.errors.push(format!("{}: arg vector is empty", place));
+    }
+    for (i, arg) in argv.iter().enumerate() {
+      let raw = arg.as_inline_table().filter(|raw| raw.len() == 1).and_then(|raw| raw.get("raw"));
+      match (arg.as_str(), raw.and_then(Value::as_str)) {
+        (Some(arg), _) if arg.starts_with("::") => {}
+        (Some(arg), _) => self.template(&format!("{}[{}]", place, i), arg, false, &mut vec![]),
+        (None, Some(_)) => {}
+        _ => self.errors.push(format!("{}[{}]: {} is not a string", place, i, arg)),
+      }
+    }
+  }
+
+  /// Checks the step settings of a task or step table.
+  fn settings(&mut self, place: &str, settings: &dyn TableLike) {
+    for key in TEMPLATED_SETTINGS {
+      match settings.get(key).map(|value| value.as_str()) {
+        None => {}
+        Some(Some(value)) => self.template(&format!("{}{}", place, key), value, true, &mut vec![]),
+        Some(None) => self.errors.push(format!("{}{} must be a string", place, key)),
+      }
+    }
+    if let Some(env) = settings.get("env") {
+      match env.as_table_like() {
+        Some(env) => {
+          for (name, value) in env.iter() {
+            if let Some(value) = value.as_str() {
+              self.template(&format!("{}env.{}", place, name), value, true, &mut vec![]);
+            }
+          }
+        }
+        None => self.errors.push(format!("{}env is not a table", place)),
+      }
+    }
+  }
+
+  fn steps(&mut self, which: &str) {
+    let Some(steps) = self.table.get(which) else {
+      return;
+    };
+    let Some(steps) = steps.as_array() else {
+      return self.errors.push(format!("{} is not an array", which));
+    };
+    let shell = self.table.get("shell").and_then(Item::as_bool).unwrap_or(false);
+    for (index, step) in steps.iter().enumerate() {
+      let place = format!("{}[{}]", which, index);
+      let Some(step) = step.as_inline_table() else {
+        self.command(&place, &Item::Value(step.clone()), shell);
+        continue;
+      };
+      for (key, _) in step.iter() {
+        if !STEP_KEYS.contains(&key) && !STEP_SETTINGS.contains(&key) {
+          self.unknown.push(format!("{}: unknown step key {}{}", place, key, suggestion(key, step_key_names())));
+        }
+      }
+      let shell = step.get("shell").and_then(Value::as_bool).unwrap_or(shell);
+      match (step.get("command"), step.get("script"), step.get("pipeline")) {
+        (Some(command), _, _) => self.command(&place, &Item::Value(command.clone()), shell),
+        (None, Some(script), _) => self.script(&place, script.as_str()),
+        (None, None, Some(pipeline)) => match get_pipeline(Some(pipeline)) {
+          Ok(pipeline) => self.command(&place, &Item::Value(pipeline.clone()), shell),
+          Err(e) => self.errors.push(format!("{}: {}", place, e)),
+        },
+        (None, None, None) => self.errors.push(format!("{}: missing command", place)),
+      }
+      self.settings(&format!("{}.", place), step);
+    }
+  }
+
+  fn script(&mut self, place: &str, script: Option<&str>) {
+    match script {
+      Some(script) => self.template(place, script, false, &mut vec![]),
+      None => self.errors.push(format!("{}: script must be a string", place)),
+    }
+  }
+
+  fn task(&mut self) {
+    let table = self.table;
+    let shell = table.get("shell").and_then(Item::as_bool).unwrap_or(false);
+    match (table.get("command"), table.get("script"), table.get("pipeline")) {
+      (Some(command), _, _) => self.command("command", command, shell),
+      (None, Some(script), _) => self.script("script", script.as_str()),
+      (None, None, Some(pipeline)) => match get_pipeline(pipeline.as_value()) {
+        Ok(pipeline) => self.command("pipeline", &Item::Value(pipeline.clone()), shell),
+        Err(e) => self.errors.push(e),
+      },
+      (None, None, None) => self.errors.push("missing command".into()),
+    }
+    for which in ["pre", "post", "finally", "on_success", "on_failure"] {
+      self.steps(which);
+    }
+    self.settings("", table);
+    if let Some(foreach) = table.get("foreach").and_then(Item::as_str) {
+      self.template("foreach", foreach, false, &mut vec![]);
+    }
+    for e in [get_string_array("", table, "tags").err(), docs::get_examples("", table).err()].into_iter().flatten() {
+      self.errors.push(e.trim_start_matches(": ").into());
+    }
+    match get_depends("", table) {
+      Ok(depends) => {
+        for dep in depends {
+          if !matches!(get_section(self.config, dep), Ok((Some(_), _))) {
+            self.errors.push(format!("depends on {}, which doesn't exist", dep));
+          }
+        }
+      }
+      Err(e) => self.errors.push(e.trim_start_matches(": ").into()),
+    }
+
+    for (key, value) in table.iter() {
+      if PLATFORM_SECTIONS.contains(&key) {
+        match value.as_table_like() {
+          Some(overlay) => {
+            for (name, _) in overlay.iter().filter(|(name, _)| !task_key_names().any(|known| known == *name)) {
+              self.unknown.push(format!("{}: unknown key {}{}", key, name, suggestion(name, task_key_names())));
+            }
+          }
+          None => self.errors.push(format!("{} must be a table of settings for that platform", key)),
+        }
+        continue;
+      }
+      if TASK_KEYS.contains(&key) || STEP_SETTINGS.contains(&key) {
+        continue;
+      }
+      if self.used.contains(key) {
+        continue;
+      }
+      let known = did_you_mean(key, task_key_names());
+      if !value.is_str() || known.is_some() {
+        self.unknown.push(format!("unknown key {}{}", key, suggestion(key, known)));
+      } else {
+        self.warnings.push(format!("{} is not a setting, and no template uses it", key));
+      }
+    }
+  }
+}
+
+/// Lints every task for --check, printing what is wrong with each, and returns how many errors there were, counting
+/// warnings as errors when `strict` is set.
+pub fn check_config(config: &Config, strict: bool) -> usize {
+  let tasks = all_tasks("", config.doc.as_table());
+  let (mut errors, mut warnings) = (0, 0);
+  for (name, table) in &tasks {
+    let mut check = Check::new(config, table);
+    match load_dotenv(name, table) {
+      Ok(vars) => with_dotenv(vars, || check.task()),
+      Err(e) => check.errors.push(e.to_string()),
+    }
+    check.errors.append(&mut check.unknown);
+    for error in &check.errors {
+      println!("{}: error: {}", name, error);
+    }
+    for warning in &check.warnings {
+      println!("{}: warning: {}", name, warning);
+    }
+    errors += check.errors.len();
+    warnings += check.warnings.len();
+  }
+  println!("{}: checked {} tasks: {} errors, {} warnings", config.file, tasks.len(), errors, warnings);
+  if strict {
+    errors + warnings
+  } else {
+    errors
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use regex::Regex;
+  use std::collections::HashMap;
+
+  fn test_config(text: &str) -> Config {
+    Config {
+      doc: text.parse().unwrap(),
+      file: "doit.toml".into(),
+      aliases: HashMap::new(),
+      settings: Table::new(),
+      origins: HashMap::new(),
+      shadowed: vec![],
+    }
+  }
+
+  fn unknown(text: &str) -> Vec<String> {
+    let config = test_config(text);
+    unknown_keys(&config, config.doc["task"].as_table().unwrap())
+  }
+
+  #[test]
+  fn edit_distances() {
+    assert_eq!(edit_distance("command", "command"), 0);
+    assert_eq!(edit_distance("comand", "command"), 1);
+    assert_eq!(edit_distance("cmomand", "command"), 1);
+    assert_eq!(edit_distance("", "pre"), 3);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(did_you_mean("depnds", task_key_names()), Some("depends"));
+    assert_eq!(did_you_mean("frobnicate", task_key_names()), None);
+  }
+
+  #[test]
+  fn unknown_task_keys() {
+    let task = "[task]\ncommand = [\"true\"]\n";
+    assert_eq!(unknown(&format!("{}comand = [\"true\"]", task)), ["unknown key comand (did you mean command?)"]);
+    assert_eq!(unknown(&format!("{}retires = 3", task)), ["unknown key retires (did you mean retries?)"]);
+    assert_eq!(unknown(&format!("{}host = \"dev\"", task)), ["unknown key host (did you mean post?)"]);
+    assert_eq!(
+      unknown(&format!("{}pre = [{{ command = [\"true\"], captrue = \"v\" }}]", task)),
+      ["pre[0]: unknown step key captrue (did you mean capture?)"]
+    );
+    assert_eq!(
+      unknown(&format!("{}linux = {{ comand = [\"x\"] }}", task)),
+      ["linux: unknown key comand (did you mean command?)"]
+    );
+  }
+
+  #[test]
+  fn template_values_are_not_unknown_keys() {
+    assert!(unknown("[task]\nhost = \"dev\"\ncommand = [\"ssh\", \":%host%\"]").is_empty());
+    assert!(unknown("[task]\nhost = \"dev\"\nurl = \"%host%:80\"\ncommand = [\":curl %url%\"]").is_empty());
+    // Nothing uses it, but it is too far from any key to be a misspelling of one.
+    assert!(unknown("[task]\nsite = \"dev\"\ncommand = [\"true\"]").is_empty());
+  }
+
+  /// Every key the engine reads from a task or step table, found in the source so the key lists can't fall behind.
+  #[test]
+  fn every_key_read_is_known() {
+    let source = [
+      include_str!("lib.rs"),
+      include_str!("config.rs"),
+      include_str!("template.rs"),
+      include_str!("builtins.rs"),
+      include_str!("exec.rs"),
+      include_str!("task.rs"),
+      include_str!("check.rs"),
+      include_str!("show.rs"),
+      include_str!("cli.rs"),
+      include_str!("reporter.rs"),
+      include_str!("docs.rs"),
+    ]
+    .map(|source| source.split("\n#[cfg(test)]").next().unwrap_or_default());
+    let source = source.concat();
+    let reads = |pattern: &str| -> Vec<String> {
+      Regex::new(pattern).unwrap().captures_iter(&source).map(|caps| caps[1].to_string()).collect()
+    };
+    let task_keys =
+      reads(r#"(?:\btable\.(?:get|contains_key)\(|get_string_array\([^,()]+, table, |\bsetting\()"([a-z_]+)"\)"#);
+    let step_keys = reads(r#"\bstep\.(?:get|contains_key)\("([a-z_]+)"\)"#);
+    let settings = reads(r#"(?:step_setting\(table, step, |\b(?:redirect|listed)\()"([a-z_]+)"\)"#);
+    assert!(task_keys.iter().any(|key| key == "quiet") && step_keys.iter().any(|key| key == "capture"));
+    assert!(settings.iter().any(|key| key == "cwd"));
+
+    for key in task_keys.iter().chain(&settings) {
+      let mut config = test_config("[task]\ncommand = [\"true\"]");
+      config.doc["task"][key.as_str()] = toml_edit::value(1);
+      assert_eq!(unknown_keys(&config, config.doc["task"].as_table().unwrap()), Vec::<String>::new(), "{}", key);
+    }
+    for key in step_keys.iter().chain(&settings) {
+      let mut config = test_config("[task]\ncommand = [\"true\"]\npre = [{ command = [\"true\"] }]");
+      let step =
+        config.doc["task"]["pre"].as_array_mut().and_then(|pre| pre.get_mut(0)).and_then(Value::as_inline_table_mut);
+      step.unwrap().insert(key, 1.into());
+      assert_eq!(unknown_keys(&config, config.doc["task"].as_table().unwrap()), Vec::<String>::new(), "{}", key);
+    }
+  }
+}
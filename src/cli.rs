@@ -0,0 +1,703 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! The doit command line program: its options, subcommands, --init, --watch and the other modes that aren't
+//! running a task.
+use crate::{
+  check::{check_config, unknown_keys},
+  config::{
+    all_tasks, find_doit_file, find_task, get_section, get_string_array, global_doit_file, read_doit_file,
+    source_location, Config, DOIT_FILE,
+  },
+  docs,
+  exec::{cancel_run, check_cancelled, handle_signals, CANCELLED, SIGNALLED},
+  history, reporter, settings,
+  show::{explain_task, list_cmds, pick_cmd, print_config, show_all, show_details, tagged_tasks, Format},
+  task::{command_chain, load_progress, primary, print_timings, run_chain, save_progress, Progress, PROGRESS},
+  template::{render_template, META_PLACEHOLDERS, PLACEHOLDER_FORMS, START_TIME},
+  Context, DoitError, Settings,
+};
+use getopts::{Matches, Options};
+use std::{
+  collections::HashSet,
+  env,
+  fs::write as write_file,
+  path::{Path, PathBuf},
+  process::Command,
+  sync::{atomic::Ordering, mpsc, Arc},
+  thread,
+  time::{Duration, Instant},
+};
+
+/// The words that, given first, are doit subcommands rather than commands to run; `run` is handled on its own.
+const SUBCOMMANDS: &[&str] = &["list", "show", "check"];
+
+/// What --init writes, before the commands for the kind of project it finds.
+const STARTER_CONFIG: &str = include_str!("../starter.toml");
+
+/// Commands --init adds, as names and table bodies, when the directory has the file the first item names. A
+/// command already added for another kind of project isn't added again.
+const PROJECT_COMMANDS: &[(&str, &[(&str, &str)])] = &[
+  (
+    "Cargo.toml",
+    &[
+      ("build", "description = \"Build with cargo\"\ncommand = [\"cargo\", \"build\", \":%args%\"]\n"),
+      ("test", "description = \"Run the tests\"\ncommand = [\"cargo\", \"test\", \":%args%\"]\n"),
+      (
+        "lint",
+        "description = \"Check formatting and run clippy\"\npre = [[\"cargo\", \"fmt\", \"--check\"]]\n\
+         command = [\"cargo\", \"clippy\", \"--all-targets\", \"--\", \"-D\", \"warnings\"]\n",
+      ),
+    ],
+  ),
+  (
+    "package.json",
+    &[
+      ("install", "description = \"Install the dependencies\"\ncommand = [\"npm\", \"install\"]\n"),
+      ("build", "description = \"Run the build script\"\ncommand = [\"npm\", \"run\", \"build\"]\n"),
+      ("test", "description = \"Run the tests\"\ndepends = [\"install\"]\ncommand = [\"npm\", \"test\"]\n"),
+    ],
+  ),
+];
+
+/// Shell completion scripts for --completions; they complete command names through `--cmds --format plain`.
+const BASH_COMPLETION: &str = include_str!("../completions/doit.bash");
+const ZSH_COMPLETION: &str = include_str!("../completions/doit.zsh");
+const FISH_COMPLETION: &str = include_str!("../completions/doit.fish");
+
+/// The directory a watch pattern is rooted at: its leading components up to the first one with a glob
+/// character.
+fn watch_root(pattern: &str) -> PathBuf {
+  let mut root = PathBuf::new();
+  for component in Path::new(pattern).components() {
+    if component.as_os_str().to_string_lossy().contains(['*', '?', '[', '{']) {
+      break;
+    }
+    root.push(component);
+  }
+  // A pattern naming a single file is watched through its directory.
+  if !root.is_dir() {
+    root.pop();
+  }
+  if root.as_os_str().is_empty() {
+    root.push(".");
+  }
+  root
+}
+
+/// Blocks until a watched file changes, then keeps reading events until none arrive for WATCH_DEBOUNCE.
+/// Returns false instead if doit is signalled first.
+fn wait_for_change(events: &mpsc::Receiver<notify::Result<notify::Event>>, matches: &dyn Fn(&Path) -> bool) -> bool {
+  let relevant = |event: &notify::Result<notify::Event>| {
+    event.as_ref().is_ok_and(|event| !event.kind.is_access() && event.paths.iter().any(|path| matches(path)))
+  };
+  loop {
+    if SIGNALLED.load(Ordering::SeqCst) != 0 {
+      return false;
+    }
+    match events.recv_timeout(WATCH_DEBOUNCE) {
+      Err(mpsc::RecvTimeoutError::Disconnected) => return true,
+      Err(mpsc::RecvTimeoutError::Timeout) => {}
+      Ok(event) if relevant(&event) => break,
+      Ok(_) => {}
+    }
+  }
+  while events.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+  true
+}
+
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Runs a task, then reruns it whenever a file matching its `watch` patterns (or `inputs`, or anything
+/// outside hidden directories) changes, cancelling a run still in progress.
+fn watch_cmd(config: &Config, cmd_name: &str, args: &[String], clear: bool) -> Result<(), DoitError> {
+  let (table, actual_cmd) = find_task(config, cmd_name)?;
+  let mut patterns = get_string_array(&actual_cmd, table, "watch")?;
+  if patterns.is_empty() {
+    patterns = get_string_array(&actual_cmd, table, "inputs")?;
+  }
+  let patterns = patterns.iter().map(|pattern| render_template(table, pattern)).collect::<Result<Vec<_>, _>>()?;
+  let globs = patterns
+    .iter()
+    .map(|pattern| glob::Pattern::new(pattern).map_err(|e| format!("{}: {}: {}", actual_cmd, pattern, e)))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let cwd = env::current_dir().map_err(|e| e.to_string())?;
+  let matches = |path: &Path| {
+    let path = path.strip_prefix(&cwd).unwrap_or(path);
+    if globs.is_empty() {
+      !path.components().any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+    } else {
+      globs.iter().any(|glob| glob.matches_path(path))
+    }
+  };
+
+  let (sender, events) = mpsc::channel();
+  let mut watcher = notify::recommended_watcher(sender).map_err(|e| e.to_string())?;
+  let roots: HashSet<PathBuf> =
+    if patterns.is_empty() { [PathBuf::from(".")].into() } else { patterns.iter().map(|p| watch_root(p)).collect() };
+  for root in &roots {
+    notify::Watcher::watch(&mut watcher, root, notify::RecursiveMode::Recursive)
+      .map_err(|e| format!("{}: {}", root.display(), e))?;
+  }
+
+  let context = Context::current();
+  thread::scope(|scope| loop {
+    CANCELLED.store(false, Ordering::SeqCst);
+    if clear {
+      print!("\x1b[2J\x1b[H");
+    }
+    let run = scope.spawn(|| {
+      context.clone().enter(|| {
+        match primary(config, cmd_name, args) {
+          Err(e) if !CANCELLED.load(Ordering::SeqCst) => reporter::error(&e.to_string()),
+          _ => {}
+        }
+        reporter::info(&format!(
+          "Watching for changes to {} ...",
+          if patterns.is_empty() { ".".into() } else { patterns.join(" ") }
+        ));
+      })
+    });
+
+    if !wait_for_change(&events, &matches) {
+      let _ = run.join();
+      return check_cancelled();
+    }
+    if !run.is_finished() {
+      reporter::warning(&format!("Change detected, restarting {}", actual_cmd));
+      cancel_run(Some(libc::SIGTERM));
+    }
+    let _ = run.join();
+  })
+}
+
+fn print_usage(program: &str, opts: &Options) -> Result<(), String> {
+  let brief = format!(
+    "Usage: {0} [options] [run] <command> [args...] [+ <command> [args...]...]\n       {0} [options] <command> <command>...\n       {0} [options] list | show [<command>] | check | init",
+    program
+  );
+  println!("{}", opts.usage(&brief));
+  println!("Subcommands do what --cmds, --show, --check and --init do. A command with a subcommand's name is run");
+  println!("by it, and `run` always names a command.\n");
+  println!("Commands are read from the nearest {} in this or a parent directory by default (see --file).", DOIT_FILE);
+  if let Some(global) = global_doit_file() {
+    println!("Commands from {} are also available unless overridden.", global.display());
+  }
+  println!(
+    "\nExit status:\n  1    other errors\n  2    usage errors\n  3    command not found\n  4    configuration errors\n  \
+     5    template errors\n  6    builtin errors\n  124  a command timed out\n  otherwise the failing command's own status"
+  );
+  Ok(())
+}
+
+fn print_placeholders() -> Result<(), String> {
+  println!("Placeholders available in every command:");
+  for (name, description) in META_PLACEHOLDERS {
+    println!("  {:18}{}", format!("%{}%", name), description);
+  }
+  println!("\nOther forms:");
+  for (form, description) in PLACEHOLDER_FORMS {
+    println!("  {:18}{}", form, description);
+  }
+  Ok(())
+}
+
+/// Writes a starter config to `path` for --init, with commands for the kind of project its directory holds.
+fn init_doit_file(path: &Path, force: bool) -> Result<(), DoitError> {
+  if path.exists() && !force {
+    return Err(DoitError::Usage(format!("{} already exists; use --force to overwrite it", path.display())));
+  }
+  let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+  let mut contents = STARTER_CONFIG.to_string();
+  let mut added = HashSet::new();
+  for (marker, commands) in PROJECT_COMMANDS.iter().filter(|(marker, _)| dir.join(marker).is_file()) {
+    contents.push_str(&format!("\n# Commands for the project's {}.\n", marker));
+    for (name, body) in commands.iter().filter(|(name, _)| added.insert(*name)) {
+      contents.push_str(&format!("\n[{}]\n{}", name, body));
+    }
+  }
+  write_file(path, contents).map_err(|e| DoitError::Other(format!("{}: {}", path.display(), e)))?;
+  reporter::info(&format!("Wrote {}", path.display()));
+  Ok(())
+}
+
+/// Editors that take `+LINE` before the file to open it at that line, and ones that take `FILE:LINE`.
+const PLUS_LINE_EDITORS: &[&str] =
+  &["vi", "vim", "nvim", "gvim", "nano", "emacs", "emacsclient", "micro", "kak", "joe"];
+const COLON_LINE_EDITORS: &[&str] = &["subl", "hx", "zed"];
+
+/// Opens the config in $VISUAL or $EDITOR for --edit: the file `task` comes from, at its header, or without a task
+/// the project file.
+fn edit_config(config: &Config, task: Option<&str>) -> Result<(), DoitError> {
+  let (file, line) = match task {
+    None if Path::new(&config.file).is_file() => (PathBuf::from(&config.file), None),
+    None => return Err(DoitError::Usage(format!("{} doesn't exist; --init writes a starter one", config.file))),
+    Some(task) => {
+      let (_, actual) = find_task(config, task)?;
+      let Some(file) = config.task_origin(&actual) else {
+        return Err(DoitError::Usage(format!(
+          "{} is a bundled default command; define it in {} to change it",
+          actual, config.file
+        )));
+      };
+      // Without the line, the editor still opens the file the task is in.
+      (file.clone(), source_location(config, &actual, None).map(|(_, line, _)| line + 1))
+    }
+  };
+  let editor = ["VISUAL", "EDITOR"]
+    .iter()
+    .filter_map(|var| env::var(var).ok())
+    .find(|editor| !editor.trim().is_empty())
+    .unwrap_or_else(|| "vi".into());
+  let mut argv = editor.split_whitespace().map(String::from).collect::<Vec<_>>();
+  let name = Path::new(&argv[0]).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+  match line {
+    Some(line) if PLUS_LINE_EDITORS.contains(&name.as_str()) => {
+      argv.extend([format!("+{}", line), file.display().to_string()])
+    }
+    Some(line) if name == "code" || name == "codium" => {
+      argv.extend(["--goto".into(), format!("{}:{}", file.display(), line)])
+    }
+    Some(line) if COLON_LINE_EDITORS.contains(&name.as_str()) => argv.push(format!("{}:{}", file.display(), line)),
+    _ => argv.push(file.display().to_string()),
+  }
+  reporter::verbose(&format!("Running {}", argv.join(" ")));
+  let status = Command::new(&argv[0]).args(&argv[1..]).status().map_err(|e| format!("{}: {}", argv[0], e))?;
+  match status.code() {
+    Some(0) => Ok(()),
+    code => Err(DoitError::Child { message: format!("{} exited with {}", argv[0], status), code: code.unwrap_or(1) }),
+  }
+}
+
+fn print_about(program: &str) -> Result<(), String> {
+  println!(
+    "program: {}\nversion: {}\nauthor: {}\nabout: {}",
+    program,
+    env!("CARGO_PKG_VERSION"),
+    env!("CARGO_PKG_AUTHORS"),
+    env!("CARGO_PKG_DESCRIPTION")
+  );
+  Ok(())
+}
+
+/// Adds a run to the history for --last and --history.
+fn record_run(cwd: PathBuf, commands: &[(String, Vec<String>)], result: &Result<(), DoitError>, started: Instant) {
+  let run = history::Run {
+    time: *START_TIME,
+    cwd,
+    commands: commands.to_vec(),
+    status: result.as_ref().err().map_or(0, DoitError::exit_code),
+    duration: started.elapsed().as_secs_f64(),
+  };
+  if let Err(e) = history::record(&run) {
+    reporter::verbose(&format!("couldn't record the run: {}", e));
+  }
+}
+
+/// How the doit command line program stops short of succeeding.
+enum Stop {
+  /// With an error, printed along with the usage; with none, for not being given a command to run.
+  Usage(Option<DoitError>),
+  /// With this exit status, having already said why.
+  Status(i32),
+}
+
+impl From<DoitError> for Stop {
+  fn from(e: DoitError) -> Self {
+    Stop::Usage(Some(e))
+  }
+}
+
+/// What printing something for an option came to, printing the error if it couldn't.
+fn printed(result: Result<(), String>) -> Result<(), Stop> {
+  result.map_err(|e| {
+    reporter::error(&e);
+    Stop::Status(1)
+  })
+}
+
+/// The doit command line program: parses `env::args()` and runs what it says to, returning the exit status for
+/// main to exit with.
+pub fn run_cli() -> i32 {
+  let (program, args) = {
+    let args0: Vec<_> = env::args().collect();
+    let remove = ["doit", "do", "--"];
+    let args: Vec<_> = args0[1..].iter().skip_while(|x| remove.contains(&x.as_str())).cloned().collect();
+    (args0[0].clone(), args)
+  };
+
+  let opts = {
+    let mut opt = Options::new();
+    opt.optflag("", "help", "print this help menu");
+    opt.optflag("", "cmds", "list all available commands");
+    opt.optflag("", "all", "with --cmds, include hidden commands; with --tag, run every command it picks");
+    opt.optmulti("", "tag", "with --cmds, list only the commands with this tag; may be given more than once", "tag");
+    opt.optflag("", "about", "about this program");
+    opt.optflag("", "placeholders", "list the %...% placeholders commands can use");
+    opt.optflagopt("", "show", "show details for command, or every command", "command");
+    opt.optflag("", "check", "check every command's configuration for mistakes, exiting nonzero if there are any");
+    opt.optflag("", "strict", "treat unknown keys in command tables (and, with --check, any warning) as errors");
+    opt.optopt("", "why", "explain which file a command comes from and which definitions it overrides", "command");
+    opt.optopt("", "docs", "print a reference of every command as markdown or html", "format");
+    opt.optflag("", "print-config", "print the configuration as doit sees it, once every file is merged");
+    opt.optflagopt("", "edit", "open the config in $VISUAL or $EDITOR, at this command if one is given", "command");
+    opt.optflag("", "init", "write a starter doit.toml (or --file) here, with commands for the project it finds");
+    opt.optflag("", "force", "with --init, overwrite a file that is already there");
+    opt.optopt("", "format", "how --cmds and --show print: text (the default), json or plain", "format");
+    opt.optopt("", "completions", "print a completion script for bash, zsh or fish", "shell");
+    opt.optopt("C", "chdir", "change to this directory before looking for the config or running anything", "dir");
+    opt.optopt("f", "file", &format!("read commands from this file instead of {}", DOIT_FILE), "path");
+    opt.optflag("", "no-defaults", "leave out the bundled default commands (or set defaults = false in [doit])");
+    opt.optmulti("", "set", "give every command's templates this value for key, over the command's own", "key=value");
+    opt.optmulti("", "env", "set this environment variable for every command and %env:...%", "KEY=VALUE");
+    opt.optopt("", "profile", "merge each command's profile.NAME table over it (or set DOIT_PROFILE)", "NAME");
+    opt.optopt("j", "jobs", "run up to N independent dependencies in parallel", "N");
+    opt.optflag("k", "keep-going", "when running several commands, go on to the next after one fails");
+    opt.optflag("", "parallel", "run several commands at once rather than one after another");
+    opt.optflag("y", "yes", "answer yes to confirmation prompts");
+    opt.optflag("q", "quiet", "don't print the \"Running command\" banners");
+    opt.optflagmulti("v", "verbose", "print more about what is being done; twice to trace template rendering");
+    opt.optflag("n", "dry-run", "print the commands that would be run, fully rendered, without running them");
+    opt.optflag("", "timings", "print how long each step took at the end (or set DOIT_TIMINGS=1)");
+    opt.optopt("", "color", "color doit's own output: auto (the default), always or never", "when");
+    opt.optflag("", "no-interactive", "with no command, print this usage instead of offering a list to pick from");
+    opt.optflag("", "no-sh", "refuse to run the commands of %sh:...% placeholders (or set DOIT_NO_SH=1)");
+    opt.optflag("", "last", "run again what was last run in this project");
+    opt.optopt("", "resume", "run a command again from the step its last run failed at", "command");
+    opt.optopt("", "from-step", "start at the Nth of a command's pre, main and post steps, skipping those before", "N");
+    opt.optflagopt("", "history", "list the last N runs (20 by default), as --last would pick from", "N");
+    opt.optflag("w", "watch", "rerun the command whenever its watched files change");
+    opt.optflag("", "clear", "with --watch, clear the screen before each run");
+    opt
+  };
+
+  let result = match opts.parse(&args) {
+    Ok(matches) => cli(&program, &opts, &matches),
+    Err(e) => Err(DoitError::Usage(e.to_string()).into()),
+  };
+  match result {
+    Ok(()) => 0,
+    Err(Stop::Status(status)) => status,
+    // Without an error there was no command to run, which is a usage error too.
+    Err(Stop::Usage(e)) => {
+      if let Some(e) = &e {
+        reporter::error(&e.to_string());
+      }
+      let _ = print_usage(&program, &opts);
+      e.map_or(2, |e| e.exit_code())
+    }
+  }
+}
+
+fn cli(program: &str, opts: &Options, matches: &Matches) -> Result<(), Stop> {
+  if matches.opt_present("help") {
+    return printed(print_usage(program, opts));
+  }
+
+  if let Err(e) = reporter::set_color(matches.opt_str("color").as_deref().unwrap_or("auto")) {
+    return Err(DoitError::Usage(e).into());
+  }
+
+  let jobs = match matches.opt_str("jobs").map(|jobs| jobs.parse::<usize>()) {
+    None => 1,
+    Some(Ok(jobs)) if jobs > 0 => jobs,
+    Some(_) => return Err(DoitError::Usage("--jobs must be a positive number".into()).into()),
+  };
+  let overrides = matches
+    .opt_strs("set")
+    .into_iter()
+    .map(|set| match set.split_once('=') {
+      Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+      _ => Err(DoitError::Usage(format!("--set {}: expected key=value", set))),
+    })
+    .collect::<Result<_, _>>()?;
+  let given_env: Vec<(String, String)> = matches
+    .opt_strs("env")
+    .into_iter()
+    .map(|set| match set.split_once('=') {
+      Some((key, value)) if !key.is_empty() && !key.contains('\0') && !value.contains('\0') => {
+        Ok((key.to_string(), value.to_string()))
+      }
+      _ => Err(DoitError::Usage(format!("--env {}: expected KEY=VALUE", set))),
+    })
+    .collect::<Result<_, _>>()?;
+  // Before any thread is started, as changing the environment isn't safe alongside other threads.
+  for (key, value) in &given_env {
+    env::set_var(key, value);
+  }
+  let run_settings = Settings {
+    jobs,
+    yes: matches.opt_present("yes"),
+    verbose: matches.opt_count("verbose"),
+    dry_run: matches.opt_present("dry-run"),
+    quiet: matches.opt_present("quiet"),
+    timings: matches.opt_present("timings")
+      || env::var("DOIT_TIMINGS").is_ok_and(|timings| !timings.is_empty() && timings != "0"),
+    sh_substitution: !matches.opt_present("no-sh")
+      && !env::var("DOIT_NO_SH").is_ok_and(|no_sh| !no_sh.is_empty() && no_sh != "0"),
+    overrides,
+    env: given_env,
+    profile: matches.opt_str("profile").or_else(|| env::var("DOIT_PROFILE").ok()).filter(|profile| !profile.is_empty()),
+  };
+  Context { config: None, settings: Arc::new(run_settings) }.install();
+  handle_signals();
+
+  if matches.opt_present("about") {
+    return printed(print_about(program));
+  }
+
+  if matches.opt_present("placeholders") {
+    return printed(print_placeholders());
+  }
+
+  if let Some(shell) = matches.opt_str("completions") {
+    match shell.as_str() {
+      "bash" => print!("{}", BASH_COMPLETION),
+      "zsh" => print!("{}", ZSH_COMPLETION),
+      "fish" => print!("{}", FISH_COMPLETION),
+      _ => return Err(DoitError::Usage(format!("--completions must be bash, zsh or fish, not {:?}", shell)).into()),
+    }
+    return Ok(());
+  }
+
+  if let Some(dir) = matches.opt_str("chdir") {
+    if let Err(e) = env::set_current_dir(&dir) {
+      return Err(DoitError::Usage(format!("{}: {}", dir, e)).into());
+    }
+  }
+
+  // `doit init` is only --init when there isn't a command called init to run instead, in the config doit would
+  // load: --file, or the doit.toml found above, along with the user-level one.
+  let init_path = PathBuf::from(matches.opt_str("file").unwrap_or_else(|| DOIT_FILE.into()));
+  let defines_init = || {
+    let file = matches.opt_str("file").map(PathBuf::from).or_else(find_doit_file).unwrap_or_else(|| init_path.clone());
+    read_doit_file(&file.display().to_string(), !matches.opt_present("no-defaults"))
+      .is_ok_and(|config| matches!(get_section(&config, "init"), Ok((Some(_), _))))
+  };
+  if matches.opt_present("init") || matches.free.first().is_some_and(|word| word == "init") && !defines_init() {
+    return Ok(init_doit_file(&init_path, matches.opt_present("force"))?);
+  }
+
+  if matches.opt_present("history") {
+    let count = match matches.opt_str("history").or_else(|| matches.free.first().cloned()) {
+      None => 20,
+      Some(count) => {
+        count.parse().map_err(|_| DoitError::Usage(format!("--history {}: expected a number of runs", count)))?
+      }
+    };
+    return Ok(history::print(count).map_err(DoitError::from)?);
+  }
+
+  let doit_file = match matches.opt_str("file") {
+    Some(file) if !Path::new(&file).exists() => return Err(DoitError::Usage(format!("{}: no such file", file)).into()),
+    Some(file) => file,
+    None => match find_doit_file() {
+      Some(path) => {
+        if let Some(dir) = path.parent() {
+          if let Err(e) = env::set_current_dir(dir) {
+            return Err(DoitError::from(format!("{}: {}", dir.display(), e)).into());
+          }
+        }
+        path.display().to_string()
+      }
+      None => DOIT_FILE.into(),
+    },
+  };
+
+  let config = Arc::new(read_doit_file(&doit_file, !matches.opt_present("no-defaults"))?);
+  Context { config: Some(config.clone()), ..Context::current() }.install();
+  let config = &*config;
+
+  let format = match matches.opt_str("format").as_deref() {
+    None | Some("text") => Format::Text,
+    Some("json") => Format::Json,
+    Some("plain") => Format::Plain,
+    Some(format) => {
+      return Err(DoitError::Usage(format!("--format must be text, json or plain, not {:?}", format)).into())
+    }
+  };
+
+  // A command named like a subcommand is still run by its bare name, and `run` always names a command.
+  let mut free = matches.free.clone();
+  let subcommand = match free.first().map(String::as_str) {
+    Some(word)
+      if word == "run" || SUBCOMMANDS.contains(&word) && !matches!(get_section(config, word), Ok((Some(_), _))) =>
+    {
+      Some(free.remove(0))
+    }
+    _ => None,
+  };
+  let subcommand = subcommand.as_deref();
+
+  let strict = matches.opt_present("strict");
+  if matches.opt_present("check") || subcommand == Some("check") {
+    if check_config(config, strict) > 0 {
+      return Err(Stop::Status(DoitError::Config(String::new()).exit_code()));
+    }
+    return Ok(());
+  }
+
+  let unknown: Vec<_> = all_tasks("", config.doc.as_table())
+    .into_iter()
+    .flat_map(|(name, table)| {
+      unknown_keys(config, table).into_iter().map(move |problem| format!("{}: {}", name, problem))
+    })
+    .collect();
+  if strict && !unknown.is_empty() {
+    return Err(DoitError::Config(unknown.join("\n")).into());
+  }
+  for problem in &unknown {
+    reporter::warning(problem);
+  }
+
+  if let Some(name) = matches.opt_str("why") {
+    return Ok(explain_task(config, &name)?);
+  }
+
+  if let Some(format) = matches.opt_str("docs") {
+    let docs = match format.as_str() {
+      "markdown" | "md" => docs::markdown(config),
+      "html" => docs::html(config),
+      _ => return Err(DoitError::Usage(format!("--docs must be markdown or html, not {:?}", format)).into()),
+    };
+    print!("{}", docs.map_err(DoitError::Config)?);
+    return Ok(());
+  }
+
+  if matches.opt_present("print-config") {
+    print_config(config);
+    return Ok(());
+  }
+
+  if matches.opt_present("edit") {
+    let task = matches.opt_str("edit").or_else(|| free.first().cloned());
+    return Ok(edit_config(config, task.as_deref())?);
+  }
+
+  if subcommand == Some("show") || matches.opt_present("show") {
+    let shown = match matches.opt_str("show").or_else(|| free.first().cloned()) {
+      Some(cmd_name) => show_details(config, &cmd_name, format),
+      None => show_all(config, matches.opt_present("all"), format, &matches.opt_strs("tag")),
+    };
+    match shown {
+      Ok(()) => return Ok(()),
+      Err(e) => return Err(e.into()),
+    };
+  }
+
+  if matches.opt_present("cmds") || subcommand == Some("list") {
+    match list_cmds(config, matches.opt_present("all"), format, &matches.opt_strs("tag")) {
+      Ok(()) => return Ok(()),
+      Err(e) => return Err(DoitError::from(e).into()),
+    };
+  }
+  let tags = matches.opt_strs("tag");
+  let cwd = env::current_dir().unwrap_or_default();
+  let chain = if matches.opt_present("last") {
+    if !free.is_empty() {
+      return Err(DoitError::Usage("--last runs what was last run, so it takes no commands".into()).into());
+    }
+    match history::last_in(&cwd) {
+      Ok(Some(run)) => {
+        reporter::info(&format!("Running again: {}", history::command_line(&run.commands)));
+        run.commands
+      }
+      Ok(None) => return Err(DoitError::NotFound(format!("nothing has been run in {} yet", cwd.display())).into()),
+      Err(e) => return Err(DoitError::from(e).into()),
+    }
+  } else if let Some(task) = matches.opt_str("resume") {
+    vec![(task, free.clone())]
+  } else if !tags.is_empty() {
+    if !matches.opt_present("all") || !free.is_empty() {
+      return Err(DoitError::Usage("--tag lists commands with --cmds, or runs all of them with --all".into()).into());
+    }
+    let chain: Vec<_> = tagged_tasks(config, &tags).into_iter().map(|name| (name, vec![])).collect();
+    if chain.is_empty() {
+      return Err(DoitError::NotFound(format!("no command has the tags {}", tags.join(", "))).into());
+    }
+    chain
+  } else {
+    let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) != 0 && libc::isatty(libc::STDERR_FILENO) != 0 };
+    let cmd_name = match (free.first(), config.setting_str("default")) {
+      (Some(cmd_name), _) => cmd_name.clone(),
+      (None, Ok(Some(default))) => default.to_string(),
+      (None, Ok(None)) if interactive && !matches.opt_present("no-interactive") => match pick_cmd(config) {
+        Ok(Some(cmd_name)) => cmd_name,
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(DoitError::from(e).into()),
+      },
+      (None, Ok(None)) => return Err(Stop::Usage(None)),
+      (None, Err(e)) => return Err(DoitError::Config(e).into()),
+    };
+
+    match free.is_empty() {
+      true => vec![(cmd_name, vec![])],
+      false => command_chain(config, &free, subcommand == Some("run"))?,
+    }
+  };
+  let keep_history = !settings().dry_run && config.setting_bool("history").map_err(DoitError::Config)? != Some(false);
+  let from_step = match matches.opt_str("from-step") {
+    None => None,
+    Some(step) => match step.parse::<usize>() {
+      Ok(step) if step > 0 => Some(step),
+      _ => return Err(DoitError::Usage(format!("--from-step {}: expected a step number from 1", step)).into()),
+    },
+  };
+  if chain.len() > 1 {
+    if matches.opt_present("watch") {
+      return Err(DoitError::Usage("--watch can only watch one command".into()).into());
+    }
+    if from_step.is_some() {
+      return Err(DoitError::Usage("--from-step can only start one command part way".into()).into());
+    }
+    let started = Instant::now();
+    let result = run_chain(config, &chain, matches.opt_present("keep-going"), matches.opt_present("parallel"));
+    if keep_history {
+      record_run(cwd, &chain, &result, started);
+    }
+    print_timings();
+    return Ok(result?);
+  }
+  let Some((cmd_name, mut args)) = chain.into_iter().next() else {
+    return Err(Stop::Usage(None));
+  };
+  if matches.opt_present("watch") {
+    return Ok(watch_cmd(config, &cmd_name, &args, matches.opt_present("clear"))?);
+  }
+  // The task's own steps are counted, so a failure can be resumed from, and --resume skips those already done.
+  let task = find_task(config, &cmd_name).map(|(_, task)| task)?;
+  let mut skip = from_step.map_or(0, |step| step - 1);
+  if matches.opt_present("resume") {
+    match load_progress(config, &task)? {
+      Some((recorded, done)) => {
+        if args.is_empty() {
+          args = recorded;
+        }
+        skip = from_step.map_or(done, |step| step - 1);
+      }
+      None => reporter::info(&format!("{} has no failed run to resume, so it runs from the start", task)),
+    }
+  }
+  PROGRESS.with(|progress| progress.replace(Some(Progress { task: task.clone(), skip, done: 0, failed: None })));
+  let started = Instant::now();
+  let result = primary(config, &cmd_name, &args);
+  // Steps are counted as they come, foreach and matrix runs repeating them, so only now is it known whether
+  // --from-step named one the task has rather than skipping every step.
+  let steps = PROGRESS.with(|progress| progress.borrow().as_ref().map_or(0, |progress| progress.done));
+  if let (Ok(()), Some(step)) = (&result, from_step.filter(|step| *step > steps)) {
+    let steps = if steps == 1 { "1 step".to_string() } else { format!("{} steps", steps) };
+    return Err(DoitError::Usage(format!("--from-step {}: {} only has {}", step, task, steps)).into());
+  }
+  if !settings().dry_run {
+    if let Err(e) = save_progress(config, &task, &args, &result) {
+      reporter::warning(&format!("couldn't keep the progress for --resume: {}", e));
+    }
+  }
+  if keep_history {
+    record_run(cwd, &[(cmd_name, args)], &result, started);
+  }
+  print_timings();
+  if let Err(e) = result {
+    return Err(e.into());
+  }
+  Ok(())
+}
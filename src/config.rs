@@ -0,0 +1,451 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Finding, loading and merging doit.toml files, and looking up the commands and sections in them.
+use crate::{check::task_suggestion, loaded_config, reporter, show::is_hidden, DoitError};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{
+  collections::HashMap,
+  env,
+  fs::File,
+  io::Read,
+  path::{Path, PathBuf},
+};
+use toml_edit::{DocumentMut, ImDocument, Item, Key, Table, TableLike};
+
+static SECTION_KEY_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^@(-?\d+)$").unwrap());
+pub static SECTION_RANGE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^@(-?\d+)\.\.@?(-?\d+)$").unwrap());
+
+pub const DOIT_FILE: &str = "doit.toml";
+
+/// The reserved top-level table holding doit's own settings rather than a command.
+pub const SETTINGS_SECTION: &str = "doit";
+
+const DEFAULT_COMMANDS: &str = include_str!("../default_commands.toml");
+
+/// Walks up from the current directory looking for a DOIT_FILE, stopping at a `.git` boundary or `/`.
+pub fn find_doit_file() -> Option<PathBuf> {
+  let mut dir = env::current_dir().ok()?;
+  loop {
+    let candidate = dir.join(DOIT_FILE);
+    if candidate.is_file() {
+      return Some(candidate);
+    }
+    if dir.join(".git").exists() || !dir.pop() {
+      return None;
+    }
+  }
+}
+
+/// The user-level config, `$XDG_CONFIG_HOME/doit/doit.toml` falling back to `~/.config/doit/doit.toml`.
+pub fn global_doit_file() -> Option<PathBuf> {
+  let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+    Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+    _ => dirs::home_dir()?.join(".config"),
+  };
+  Some(config_dir.join("doit").join(DOIT_FILE))
+}
+
+fn parse_doit_file(path: &Path) -> Result<DocumentMut, DoitError> {
+  let mut contents = String::default();
+
+  let config_error = |e: &dyn std::fmt::Display| DoitError::Config(format!("{}: {}", path.display(), e));
+  File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).map_err(|e| config_error(&e))?;
+
+  contents.parse::<DocumentMut>().map_err(|e| config_error(&e))
+}
+
+/// Layers `other` on top of `doc`; a section defined in both is replaced wholesale by `other`'s, except for
+/// the SETTINGS_SECTION, which is merged key by key.
+fn merge_doit_file(doc: &mut DocumentMut, other: &DocumentMut) {
+  for (key, item) in other.iter() {
+    match (key == SETTINGS_SECTION, doc.get_mut(key).and_then(Item::as_table_mut), item.as_table()) {
+      (true, Some(settings), Some(other_settings)) => {
+        for (setting, value) in other_settings.iter() {
+          settings.insert(setting, value.clone());
+        }
+      }
+      _ => {
+        doc.insert(key, item.clone());
+      }
+    }
+  }
+}
+
+/// Merges `path` into `doc`, first resolving its top-level `include` array relative to the file's directory.
+/// Included files are merged before the including file so its own sections win.
+fn load_doit_file(
+  doc: &mut DocumentMut,
+  path: &Path,
+  including: &mut Vec<PathBuf>,
+  origins: &mut HashMap<String, Vec<PathBuf>>,
+) -> Result<(), DoitError> {
+  let canonical = path.canonicalize().map_err(|e| DoitError::Config(format!("{}: {}", path.display(), e)))?;
+  if including.contains(&canonical) {
+    return Err(DoitError::Config(format!("{}: include cycle detected", path.display())));
+  }
+
+  let mut file_doc = parse_doit_file(path)?;
+  if let Some(include) = file_doc.remove("include") {
+    let paths = include
+      .as_array()
+      .ok_or_else(|| DoitError::Config(format!("{}: include must be an array of paths", path.display())))?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+    including.push(canonical);
+    for included in paths {
+      let included = included
+        .as_str()
+        .ok_or_else(|| DoitError::Config(format!("{}: include entry {} is not a string", path.display(), included)))?;
+      load_doit_file(doc, &base.join(included), including, origins)?;
+    }
+    including.pop();
+  }
+  merge_doit_file(doc, &file_doc);
+  for (key, _) in file_doc.iter() {
+    origins.entry(key.to_string()).or_default().push(path.to_path_buf());
+  }
+  Ok(())
+}
+
+/// The merged configuration along with what is derived from it at load time.
+pub struct Config {
+  pub doc: DocumentMut,
+  /// The project file, for messages.
+  pub file: String,
+  /// Maps each alias to the section it names.
+  pub aliases: HashMap<String, String>,
+  /// The SETTINGS_SECTION, taken out of `doc` so it is not listed or run as a command.
+  pub settings: Table,
+  /// The files each top-level section was defined in, in the order they were loaded, so the last is the one in
+  /// effect. The bundled default commands aren't in any.
+  pub origins: HashMap<String, Vec<PathBuf>>,
+  /// The bundled default commands that a file defines again, which its definition replaces.
+  pub shadowed: Vec<String>,
+}
+
+impl Config {
+  /// The file the top-level section `key` is in effect from, for pointing at it in errors.
+  pub fn origin(&self, key: &str) -> Option<&PathBuf> {
+    self.origins.get(key).and_then(|paths| paths.last())
+  }
+
+  /// The file `task`, which may be a member of a group, is in effect from; none for a bundled default command.
+  pub fn task_origin(&self, task: &str) -> Option<&PathBuf> {
+    self.origin(key_path(self.doc.as_table(), task)?.first()?.0.get())
+  }
+
+  pub fn setting_str(&self, key: &str) -> Result<Option<&str>, String> {
+    match self.settings.get(key) {
+      None => Ok(None),
+      Some(value) => value.as_str().map(Some).ok_or_else(|| format!("[{}] {} must be a string", SETTINGS_SECTION, key)),
+    }
+  }
+
+  pub fn setting_bool(&self, key: &str) -> Result<Option<bool>, String> {
+    match self.settings.get(key) {
+      None => Ok(None),
+      Some(value) => {
+        value.as_bool().map(Some).ok_or_else(|| format!("[{}] {} must be true or false", SETTINGS_SECTION, key))
+      }
+    }
+  }
+}
+
+pub fn get_aliases(table: &Table) -> Vec<&str> {
+  table
+    .get("aliases")
+    .and_then(|aliases| aliases.as_array())
+    .map_or(vec![], |aliases| aliases.iter().filter_map(|alias| alias.as_str()).collect())
+}
+
+/// The subtables of a task that override its keys on one OS, like `[build.windows]`.
+pub const PLATFORM_SECTIONS: &[&str] = &["linux", "macos", "windows", "freebsd", "netbsd", "openbsd"];
+
+/// Whether the subtable `key` of a task overrides the task's keys, for one OS or a profile, rather than being a
+/// command of its own.
+fn is_overlay(key: &str) -> bool {
+  PLATFORM_SECTIONS.contains(&key) || key == "profile"
+}
+
+/// A table with no `command` or `script` of its own whose subtables are commands, like `[db.migrate]`. A task
+/// with only `[plat.linux]` and `[plat.profile.ci]` isn't one.
+pub fn is_group(table: &Table) -> bool {
+  !table.contains_key("command") && !table.contains_key("script") && group_members(table).next().is_some()
+}
+
+pub fn group_members(table: &Table) -> impl Iterator<Item = (&str, &Table)> {
+  table.iter().filter(|(key, _)| !is_overlay(key)).filter_map(|(key, item)| item.as_table().map(|member| (key, member)))
+}
+
+/// Every command in `table`, with group members named by their dotted path.
+pub fn all_tasks<'a>(prefix: &str, table: &'a Table) -> Vec<(String, &'a Table)> {
+  let mut tasks = Vec::new();
+  // Every top-level table is a command, even one called linux; below that, only the members of groups are.
+  let sections: Vec<(&str, &Table)> = match prefix {
+    "" => table.iter().filter_map(|(key, item)| item.as_table().map(|section| (key, section))).collect(),
+    _ => group_members(table).collect(),
+  };
+  for (key, section) in sections {
+    let name = if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+    if is_group(section) {
+      tasks.extend(all_tasks(&name, section));
+    } else {
+      tasks.push((name, section));
+    }
+  }
+  tasks
+}
+
+fn build_alias_index(doc: &DocumentMut) -> Result<HashMap<String, String>, String> {
+  let tasks = all_tasks("", doc.as_table());
+  let mut aliases = HashMap::new();
+  for (name, table) in &tasks {
+    for alias in get_string_array(name, table, "aliases")? {
+      if doc.contains_key(alias) || tasks.iter().any(|(task, _)| task == alias) {
+        return Err(format!("{}: alias {} is already the name of a command", name, alias));
+      }
+      if let Some(other) = aliases.insert(alias.to_string(), name.to_string()) {
+        return Err(format!("{}: alias {} is already an alias of {}", name, alias, other));
+      }
+    }
+  }
+  Ok(aliases)
+}
+
+/// Loads DEFAULT_COMMANDS (unless `defaults` is false or the files set `[doit] defaults = false`), then the global
+/// config, then `doit_file`, each overriding the previous. A default command the files define again is dropped
+/// rather than replaced in place, so the files' definition gets the @N index it has among their own commands.
+pub fn read_doit_file(doit_file: &str, defaults: bool) -> Result<Config, DoitError> {
+  let mut loaded = DocumentMut::new();
+  let mut origins = HashMap::new();
+  for path in [global_doit_file(), Some(PathBuf::from(doit_file))].into_iter().flatten() {
+    if path.is_file() {
+      load_doit_file(&mut loaded, &path, &mut Vec::new(), &mut origins)?;
+    }
+  }
+
+  let defaults = match loaded.get(SETTINGS_SECTION).and_then(|settings| settings.get("defaults")) {
+    None => defaults,
+    Some(setting) => {
+      let setting = setting
+        .as_bool()
+        .ok_or_else(|| DoitError::Config(format!("[{}] defaults must be true or false", SETTINGS_SECTION)))?;
+      defaults && setting
+    }
+  };
+  let mut doc = DocumentMut::new();
+  let mut shadowed = Vec::new();
+  if defaults {
+    doc = DEFAULT_COMMANDS.parse::<DocumentMut>().map_err(|e| DoitError::Config(e.to_string()))?;
+    shadowed = doc.iter().map(|(key, _)| key.to_string()).filter(|key| loaded.contains_key(key)).collect();
+    for key in &shadowed {
+      doc.remove(key);
+      reporter::verbose(&format!("{} replaces the bundled default command of the same name", key));
+    }
+  }
+  merge_doit_file(&mut doc, &loaded);
+  let settings = match doc.remove(SETTINGS_SECTION) {
+    None => Table::new(),
+    Some(settings) => {
+      settings.into_table().map_err(|_| DoitError::Config(format!("[{}] must be a table", SETTINGS_SECTION)))?
+    }
+  };
+  let aliases = build_alias_index(&doc).map_err(DoitError::Config)?;
+  Ok(Config { doc, file: doit_file.into(), aliases, settings, origins, shadowed })
+}
+
+/// The keys leading to `name` in `table`, and what they hold: `name` may be a dotted path into subtables, such as
+/// `db.migrate`, or a key that has dots in it, as `["a.b"]` does.
+fn key_path<'a>(table: &'a dyn TableLike, name: &str) -> Option<Vec<(&'a Key, &'a Item)>> {
+  if let Some(found) = table.get_key_value(name) {
+    return Some(vec![found]);
+  }
+  name.match_indices('.').find_map(|(at, _)| {
+    let (key, item) = table.get_key_value(&name[..at])?;
+    let mut path = key_path(item.as_table_like()?, &name[at + 1..])?;
+    path.insert(0, (key, item));
+    Some(path)
+  })
+}
+
+/// Where `key` of a task is set (or, without a key, where the task starts): the file the task came from, none
+/// for the bundled default commands, and the index and text of the line, from where parsing the file put them.
+pub fn source_location(config: &Config, task: &str, key: Option<&str>) -> Option<(Option<PathBuf>, usize, String)> {
+  key_path(config.doc.as_table(), task)?;
+  let (file, text) = match config.task_origin(task) {
+    Some(path) => (Some(path.clone()), std::fs::read_to_string(path).ok()?),
+    None => (None, DEFAULT_COMMANDS.to_string()),
+  };
+  let doc = ImDocument::parse(text.as_str()).ok()?;
+  let path = key_path(doc.as_table(), task)?;
+  let &(task_key, task_item) = path.last()?;
+  let span = key
+    .and_then(|key| task_item.as_table_like()?.get_key_value(key))
+    .and_then(|(key, item)| key.span().or_else(|| item.span()))
+    .or_else(|| task_key.span().or_else(|| task_item.span()))?;
+  let line = text[..span.start].matches('\n').count();
+  Some((file, line, text.lines().nth(line)?.to_string()))
+}
+
+/// Where `key` of a task is set, as file:line and the line itself.
+fn source_context(config: &Config, task: &str, key: &str) -> Option<String> {
+  let (file, line, text) = source_location(config, task, Some(key))?;
+  let file = file.map_or_else(|| "(default commands)".to_string(), |file| file.display().to_string());
+  Some(format!("  --> {}:{}\n   |\n{:>3} | {}", file, line + 1, line + 1, text))
+}
+
+/// Adds where a task's `key` is set to a configuration or template error from it. The failures of commands
+/// and builtins are about what they did rather than how they were written, so they are left alone.
+pub fn with_source_context(cmd_name: &str, key: &str, e: DoitError) -> DoitError {
+  if matches!(e, DoitError::Child { .. } | DoitError::Builtin(_)) || e.to_string().contains("\n  --> ") {
+    return e;
+  }
+  match loaded_config().and_then(|config| source_context(&config, cmd_name, key)) {
+    Some(context) => e.map_message(|e| format!("{}\n{}", e, context)),
+    None => e,
+  }
+}
+
+/// The position among the top-level sections that the number of an `@N` selector names: counting from 1, or
+/// back from the last for a negative one, so `@-1` is the last. Hidden commands keep their numbers, so the ones
+/// --cmds shows stay the same whether or not it shows hidden ones too.
+pub fn section_index(doc: &DocumentMut, number: &str) -> Option<usize> {
+  let number = number.parse::<i64>().ok()?;
+  let len = doc.as_table().len() as i64;
+  let index = if number < 0 { len + number } else { number - 1 };
+  (0..len).contains(&index).then_some(index as usize)
+}
+
+pub fn get_section<'a>(config: &'a Config, name: &'a str) -> Result<(Option<&'a Table>, String), DoitError> {
+  let doc = &config.doc;
+  if let Some(caps) = SECTION_KEY_RE.captures(name) {
+    Ok({
+      let mut actual_key = String::default();
+      (
+        section_index(doc, caps.get(1).ok_or("RE failed")?.as_str())
+          .and_then(|index| doc.as_table().iter().nth(index))
+          .and_then(|(key, section)| {
+            actual_key = key.into();
+            section.as_table()
+          }),
+        actual_key,
+      )
+    })
+  } else {
+    let name = config.aliases.get(name).map_or(name, |name| name.as_str());
+    if doc.contains_key(name) {
+      return Ok((doc[name].as_table(), name.into()));
+    }
+
+    // A dotted name walks into groups, so db.migrate is the migrate member of the db group.
+    let mut table = doc.as_table();
+    let mut walked = Vec::new();
+    for part in name.split('.') {
+      match table.get(part).and_then(|item| item.as_table()) {
+        Some(member) if walked.is_empty() || is_group(table) && !is_overlay(part) => table = member,
+        _ if !walked.is_empty() && is_group(table) => {
+          let members: Vec<_> =
+            group_members(table).filter(|(key, member)| !is_hidden(key, member)).map(|(key, _)| key).collect();
+          return Err(DoitError::NotFound(format!(
+            "{} not found in the {}; {} contains: {}{}",
+            name,
+            config.file,
+            walked.join("."),
+            members.join(", "),
+            task_suggestion(config, name)
+          )));
+        }
+        _ => {
+          return Err(DoitError::NotFound(format!(
+            "{} not found in the {}{}",
+            name,
+            config.file,
+            task_suggestion(config, name)
+          )))
+        }
+      }
+      walked.push(part);
+    }
+    Ok((Some(table), name.into()))
+  }
+}
+
+/// The `.doit` directory next to the doit.toml, where doit keeps what it needs between runs.
+pub fn doit_dir() -> PathBuf {
+  config_dir().join(".doit")
+}
+
+/// The directory of the doit.toml.
+pub fn config_dir() -> PathBuf {
+  loaded_config().and_then(|config| Path::new(&config.file).parent().map(Path::to_path_buf)).unwrap_or_default()
+}
+
+pub fn get_string_array<'a>(cmd_name: &str, table: &'a Table, key: &str) -> Result<Vec<&'a str>, String> {
+  match table.get(key) {
+    None => Ok(vec![]),
+    Some(values) => values
+      .as_array()
+      .ok_or_else(|| format!("{}: {} is not an array", cmd_name, key))?
+      .iter()
+      .map(|value| value.as_str().ok_or_else(|| format!("{}: {} entry {} is not a string", cmd_name, key, value)))
+      .collect(),
+  }
+}
+
+pub fn find_task<'a>(config: &'a Config, name: &'a str) -> Result<(&'a Table, String), DoitError> {
+  match get_section(config, name) {
+    Ok((Some(table), actual_cmd)) => Ok((table, actual_cmd)),
+    Err(e) => Err(e.map_message(|e| format!("{} not found: {}", name, e))),
+    Ok((None, _)) => Err(DoitError::NotFound(format!("{} not found", name))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn section_numbers() {
+    let doc: DocumentMut = "[a]\n[_hidden]\n[b]\n[c]\n".parse().unwrap();
+    let index = |number| section_index(&doc, number);
+    assert_eq!((index("1"), index("2"), index("4")), (Some(0), Some(1), Some(3)));
+    assert_eq!((index("-1"), index("-4")), (Some(3), Some(0)));
+    assert_eq!((index("0"), index("5"), index("-5")), (None, None, None));
+    assert_eq!((index(""), index("x"), index("+1"), index("99999999999999999999")), (None, None, Some(0), None));
+    assert_eq!(section_index(&DocumentMut::new(), "1"), None);
+  }
+
+  #[test]
+  fn overlays_are_not_group_members() {
+    let doc: DocumentMut = "[plat.linux]\ncommand = [\"a\"]\n[plat.profile.ci]\ncommand = [\"b\"]\n\
+      [db.migrate]\ncommand = [\"c\"]\n[db.linux]\ncwd = \"x\"\n[linux]\ncommand = [\"d\"]"
+      .parse()
+      .unwrap();
+    let table = |name: &str| doc[name].as_table().unwrap();
+    assert!(!is_group(table("plat")) && is_group(table("db")));
+    assert_eq!(group_members(table("db")).map(|(key, _)| key).collect::<Vec<_>>(), ["migrate"]);
+    let names: Vec<String> = all_tasks("", doc.as_table()).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, ["plat", "db.migrate", "linux"]);
+  }
+
+  #[test]
+  fn source_locations_come_from_the_parse() {
+    let path = env::temp_dir().join(format!("doit-source-test-{}.toml", std::process::id()));
+    let text = "dotted.command = [\"d\"]\ninline = { command = [\"i\"] }\n\n[hello]  # greet\ndescription = \"hi\"\n\
+      command = [\"echo\"]\n\n[ spaced ]\ncommand = [\"s\"]\n\n[\"a.b\"]\n  command  = [\"q\"]\n\n[db.migrate]\ncommand = [\"m\"]\n";
+    std::fs::write(&path, text).unwrap();
+    let config = read_doit_file(&path.display().to_string(), false).unwrap();
+    let line = |task, key| source_location(&config, task, key).map(|(file, line, text)| (file.is_some(), line, text));
+    assert_eq!(line("hello", None), Some((true, 3, "[hello]  # greet".into())));
+    assert_eq!(line("hello", Some("command")), Some((true, 5, "command = [\"echo\"]".into())));
+    assert_eq!(line("hello", Some("missing")), Some((true, 3, "[hello]  # greet".into())));
+    assert_eq!(line("spaced", Some("command")), Some((true, 8, "command = [\"s\"]".into())));
+    assert_eq!(line("a.b", Some("command")), Some((true, 11, "  command  = [\"q\"]".into())));
+    assert_eq!(line("db.migrate", None), Some((true, 13, "[db.migrate]".into())));
+    assert_eq!(line("dotted", Some("command")), Some((true, 0, "dotted.command = [\"d\"]".into())));
+    assert_eq!(line("inline", Some("command")), Some((true, 1, "inline = { command = [\"i\"] }".into())));
+    assert_eq!(line("nothing", None), None);
+    assert_eq!((config.task_origin("db.migrate"), config.task_origin("a.b")), (Some(&path), Some(&path)));
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,393 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Deflate (RFC 1951) and CRC-32, for the gzip and zip support of &archive and &extract. Compressing only uses the
+//! fixed Huffman codes, which is simple and still does well on the text and binaries builds produce, falling back to
+//! stored blocks for data that doesn't compress.
+
+const LENGTH_BASE: [u16; 29] =
+  [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+  8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] =
+  [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// The order the code length code lengths of a dynamic block come in.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW: usize = 32 * 1024;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+
+static CRC_TABLE: once_cell::sync::Lazy<[u32; 256]> = once_cell::sync::Lazy::new(|| {
+  let mut table = [0u32; 256];
+  for (n, entry) in table.iter_mut().enumerate() {
+    let mut c = n as u32;
+    for _ in 0..8 {
+      c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+    }
+    *entry = c;
+  }
+  table
+});
+
+/// The CRC-32 gzip and zip use.
+pub fn crc32(data: &[u8]) -> u32 {
+  !data.iter().fold(!0u32, |crc, byte| CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+struct BitWriter {
+  out: Vec<u8>,
+  bits: u64,
+  count: u32,
+}
+
+impl BitWriter {
+  fn put(&mut self, value: u32, count: u32) {
+    self.bits |= (value as u64) << self.count;
+    self.count += count;
+    while self.count >= 8 {
+      self.out.push(self.bits as u8);
+      self.bits >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  /// Huffman codes are packed starting from their most significant bit.
+  fn put_code(&mut self, code: u32, count: u32) {
+    self.put(code.reverse_bits() >> (32 - count), count);
+  }
+
+  fn fixed_symbol(&mut self, symbol: u16) {
+    match symbol {
+      0..=143 => self.put_code(0x30 + symbol as u32, 8),
+      144..=255 => self.put_code(0x190 + (symbol as u32 - 144), 9),
+      256..=279 => self.put_code(symbol as u32 - 256, 7),
+      _ => self.put_code(0xc0 + (symbol as u32 - 280), 8),
+    }
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.out.push(self.bits as u8);
+    }
+    self.out
+  }
+}
+
+/// The index of the largest base that is at most value.
+fn code_for(bases: &[u16], value: usize) -> usize {
+  bases.iter().rposition(|base| *base as usize <= value).unwrap_or(0)
+}
+
+/// Compresses data as a single fixed Huffman block, or as stored blocks when that would come out smaller.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+  let fixed = compress_fixed(data);
+  let blocks = data.len().div_ceil(u16::MAX as usize).max(1);
+  if fixed.len() <= data.len() + 5 * blocks {
+    return fixed;
+  }
+  let mut out = Vec::with_capacity(data.len() + 5 * blocks);
+  let mut chunks = data.chunks(u16::MAX as usize).peekable();
+  while let Some(chunk) = chunks.next() {
+    out.push(chunks.peek().is_none() as u8);
+    out.extend((chunk.len() as u16).to_le_bytes());
+    out.extend((!(chunk.len() as u16)).to_le_bytes());
+    out.extend(chunk);
+  }
+  out
+}
+
+fn compress_fixed(data: &[u8]) -> Vec<u8> {
+  let mut writer = BitWriter { out: Vec::with_capacity(data.len() / 2 + 16), bits: 0, count: 0 };
+  writer.put(1, 1);
+  writer.put(1, 2);
+  let hash = |i: usize| ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7fff;
+  let mut head = vec![usize::MAX; 0x8000];
+  let mut prev = vec![usize::MAX; WINDOW];
+  let insert = |i: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+    if i + 2 < data.len() {
+      let h = hash(i);
+      prev[i % WINDOW] = head[h];
+      head[h] = i;
+    }
+  };
+
+  let mut i = 0;
+  while i < data.len() {
+    let (mut best_len, mut best_dist) = (0, 0);
+    if i + 2 < data.len() {
+      let mut candidate = head[hash(i)];
+      let max_len = MAX_MATCH.min(data.len() - i);
+      for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || i - candidate > WINDOW - 1 || candidate >= i {
+          break;
+        }
+        let len = data[candidate..].iter().zip(&data[i..i + max_len]).take_while(|(a, b)| a == b).count();
+        if len > best_len {
+          (best_len, best_dist) = (len, i - candidate);
+          if len == max_len {
+            break;
+          }
+        }
+        candidate = prev[candidate % WINDOW];
+      }
+    }
+    if best_len >= 3 {
+      let code = code_for(&LENGTH_BASE, best_len);
+      writer.fixed_symbol(257 + code as u16);
+      writer.put((best_len - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+      let code = code_for(&DIST_BASE, best_dist);
+      writer.put_code(code as u32, 5);
+      writer.put((best_dist - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code] as u32);
+      for j in i..i + best_len {
+        insert(j, &mut head, &mut prev);
+      }
+      i += best_len;
+    } else {
+      writer.fixed_symbol(data[i] as u16);
+      insert(i, &mut head, &mut prev);
+      i += 1;
+    }
+  }
+  writer.fixed_symbol(256);
+  writer.finish()
+}
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  pos: usize,
+  bits: u32,
+  count: u32,
+}
+
+impl BitReader<'_> {
+  fn bits(&mut self, count: u32) -> Result<u32, String> {
+    while self.count < count {
+      let byte = *self.data.get(self.pos).ok_or("compressed data is cut short")?;
+      self.pos += 1;
+      self.bits |= (byte as u32) << self.count;
+      self.count += 8;
+    }
+    let value = self.bits & ((1u64 << count) - 1) as u32;
+    self.bits >>= count;
+    self.count -= count;
+    Ok(value)
+  }
+
+  fn align(&mut self) {
+    self.bits = 0;
+    self.count = 0;
+  }
+}
+
+/// A canonical Huffman code, decoded a bit at a time.
+struct Huffman {
+  counts: [u16; 16],
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Self {
+    let mut counts = [0u16; 16];
+    for length in lengths {
+      counts[*length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut offsets = [0u16; 16];
+    for len in 1..16 {
+      offsets[len] = offsets[len - 1] + counts[len - 1];
+    }
+    let mut symbols = vec![0u16; lengths.len()];
+    for (symbol, length) in lengths.iter().enumerate() {
+      if *length != 0 {
+        symbols[offsets[*length as usize] as usize] = symbol as u16;
+        offsets[*length as usize] += 1;
+      }
+    }
+    Huffman { counts, symbols }
+  }
+
+  fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+    let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+    for len in 1..16 {
+      code |= reader.bits(1)? as i32;
+      let count = self.counts[len] as i32;
+      if code - first < count {
+        return Ok(self.symbols[(index + code - first) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err("bad Huffman code in compressed data".into())
+  }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+  let mut lengths = [0u8; 288];
+  lengths[..144].fill(8);
+  lengths[144..256].fill(9);
+  lengths[256..280].fill(7);
+  lengths[280..].fill(8);
+  (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+  let literals = reader.bits(5)? as usize + 257;
+  let distances = reader.bits(5)? as usize + 1;
+  let code_lengths = reader.bits(4)? as usize + 4;
+  let mut lengths = [0u8; 19];
+  for index in CLEN_ORDER.iter().take(code_lengths) {
+    lengths[*index] = reader.bits(3)? as u8;
+  }
+  let lengths_code = Huffman::new(&lengths);
+  let mut lengths = Vec::with_capacity(literals + distances);
+  while lengths.len() < literals + distances {
+    let (value, repeat) = match lengths_code.decode(reader)? {
+      symbol @ 0..=15 => (symbol as u8, 1),
+      16 => (*lengths.last().ok_or("bad code lengths in compressed data")?, 3 + reader.bits(2)?),
+      17 => (0, 3 + reader.bits(3)?),
+      _ => (0, 11 + reader.bits(7)?),
+    };
+    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+  }
+  if lengths.len() != literals + distances {
+    return Err("bad code lengths in compressed data".into());
+  }
+  Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+/// Decompresses deflate data, returning it and how many bytes of `data` it took up.
+pub fn decompress(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+  let mut reader = BitReader { data, pos: 0, bits: 0, count: 0 };
+  let mut out = Vec::with_capacity(data.len() * 3);
+  loop {
+    let last = reader.bits(1)? == 1;
+    let (literals, distances) = match reader.bits(2)? {
+      0 => {
+        reader.align();
+        let header = data.get(reader.pos..reader.pos + 4).ok_or("compressed data is cut short")?;
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        if u16::from_le_bytes([header[2], header[3]]) != !len {
+          return Err("bad stored block length in compressed data".into());
+        }
+        let len = len as usize;
+        let stored = data.get(reader.pos + 4..reader.pos + 4 + len).ok_or("compressed data is cut short")?;
+        out.extend_from_slice(stored);
+        reader.pos += 4 + len;
+        if last {
+          break;
+        }
+        continue;
+      }
+      1 => fixed_codes(),
+      2 => dynamic_codes(&mut reader)?,
+      _ => return Err("bad block type in compressed data".into()),
+    };
+    loop {
+      let symbol = literals.decode(&mut reader)? as usize;
+      if symbol < 256 {
+        out.push(symbol as u8);
+        continue;
+      }
+      if symbol == 256 {
+        break;
+      }
+      let code = symbol - 257;
+      let len = *LENGTH_BASE.get(code).ok_or("bad length in compressed data")? as usize
+        + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+      let code = distances.decode(&mut reader)? as usize;
+      let dist = *DIST_BASE.get(code).ok_or("bad distance in compressed data")? as usize
+        + reader.bits(DIST_EXTRA[code] as u32)? as usize;
+      if dist > out.len() {
+        return Err("bad distance in compressed data".into());
+      }
+      let start = out.len() - dist;
+      for i in 0..len {
+        out.push(out[start + i]);
+      }
+    }
+    if last {
+      break;
+    }
+  }
+  Ok((out, reader.pos))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(text: &str) -> Vec<u8> {
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+  }
+
+  fn round_trip(data: &[u8]) -> Vec<u8> {
+    let compressed = compress(data);
+    assert_eq!(decompress(&compressed), Ok((data.to_vec(), compressed.len())));
+    compressed
+  }
+
+  #[test]
+  fn crc32_known_answers() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+  }
+
+  #[test]
+  fn empty_input() {
+    assert_eq!(round_trip(b""), [0x03, 0x00]);
+  }
+
+  #[test]
+  fn fixed_block_round_trips() {
+    let text: Vec<u8> =
+      (0..2000).flat_map(|i| format!("line {} of some build output\n", i % 13).into_bytes()).collect();
+    let compressed = round_trip(&text);
+    assert_eq!(compressed[0] & 0b111, 0b011);
+    assert!(compressed.len() < text.len() / 4);
+    round_trip(&vec![b'a'; 100_000]);
+    round_trip(b"a");
+    round_trip(b"abcabcabcabc");
+  }
+
+  #[test]
+  fn incompressible_data_is_stored() {
+    let mut state = 0x2545f491u32;
+    let noise: Vec<u8> = (0..150_000)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+      })
+      .collect();
+    let compressed = round_trip(&noise);
+    assert_eq!(compressed[0] & 0b110, 0);
+    assert_eq!(compressed.len(), noise.len() + 5 * 3);
+  }
+
+  #[test]
+  fn inflates_what_zlib_makes() {
+    assert_eq!(decompress(&hex("cb48cdc9c957c8409000")), Ok((b"hello hello hello".to_vec(), 10)));
+    assert_eq!(decompress(&hex("010500faff68656c6c6f")), Ok((b"hello".to_vec(), 10)));
+    let dynamic = hex(
+      "75cbdb0980300c46e1553280b88763f4f2d7066a234da4b8bdf44984fafc9db31975a76419e4a14692c8f8802ec4af74697fe4760ce8ac\
+       518e992491c29a2bf43be2949047e05118696e5c4343bc0adbbd3e",
+    );
+    let text = b"It was the best of times, it was the worst of times, it was the age of wisdom, it was the age of \
+      foolishness, it was the epoch of belief, it was the epoch of incredulity.";
+    assert_eq!(dynamic[0] >> 1 & 0b11, 2);
+    assert_eq!(decompress(&dynamic), Ok((text.to_vec(), dynamic.len())));
+  }
+
+  #[test]
+  fn bad_data() {
+    assert!(decompress(b"").is_err());
+    assert!(decompress(&hex("010500faff6865")).is_err());
+    assert!(decompress(&hex("01050000ff68656c6c6f")).is_err());
+    assert_eq!(decompress(&[0x07]), Err("bad block type in compressed data".into()));
+  }
+}
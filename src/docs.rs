@@ -0,0 +1,249 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! A reference of every command for --docs, in Markdown or HTML, for a project's contributing docs: what
+//! --show says about one command, for all of them.
+use crate::{
+  config::{all_tasks, get_aliases, Config},
+  show::{get_tags, is_hidden},
+  task::{get_depends, get_params, Param},
+  template::ENV1_RE,
+};
+use std::collections::BTreeSet;
+use toml_edit::{Item, Table, Value};
+
+/// What the docs say about one command.
+pub struct TaskDoc<'a> {
+  pub name: String,
+  pub description: Option<&'a str>,
+  /// The long-form `help` text.
+  pub help: Option<&'a str>,
+  pub aliases: Vec<&'a str>,
+  pub tags: Vec<&'a str>,
+  pub params: Vec<Param>,
+  pub depends: Vec<&'a str>,
+  /// The `%env:NAME%` variables its templates use without a default, so they have to be set.
+  pub needs_env: Vec<String>,
+  /// The variables its `env` table sets.
+  pub sets_env: Vec<&'a str>,
+  /// The command, script or pipeline as written.
+  pub command: Option<String>,
+  /// The `examples` as command lines and what each does.
+  pub examples: Vec<(&'a str, Option<&'a str>)>,
+}
+
+/// Every string anywhere in `item`, table keys aside.
+fn strings<'a>(item: &'a Item, found: &mut Vec<&'a str>) {
+  fn value_strings<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+    match value {
+      Value::String(text) => found.push(text.value()),
+      Value::Array(array) => array.iter().for_each(|value| value_strings(value, found)),
+      Value::InlineTable(table) => table.iter().for_each(|(_, value)| value_strings(value, found)),
+      _ => {}
+    }
+  }
+  match item {
+    Item::Value(value) => value_strings(value, found),
+    Item::Table(table) => table.iter().for_each(|(_, item)| strings(item, found)),
+    Item::ArrayOfTables(tables) => {
+      tables.iter().for_each(|table| table.iter().for_each(|(_, item)| strings(item, found)))
+    }
+    Item::None => {}
+  }
+}
+
+/// A command's `examples`: an array of `["doit build", "what it does"]` pairs, the description being optional.
+pub fn get_examples<'a>(cmd_name: &str, table: &'a Table) -> Result<Vec<(&'a str, Option<&'a str>)>, String> {
+  let Some(examples) = table.get("examples") else {
+    return Ok(vec![]);
+  };
+  let malformed = || format!("{}: examples must be an array of [\"command line\", \"description\"] arrays", cmd_name);
+  let mut found = Vec::new();
+  for example in examples.as_array().ok_or_else(malformed)? {
+    let parts: Vec<&str> = example
+      .as_array()
+      .ok_or_else(malformed)?
+      .iter()
+      .map(Value::as_str)
+      .collect::<Option<_>>()
+      .ok_or_else(malformed)?;
+    match parts.as_slice() {
+      [command] => found.push((*command, None)),
+      [command, description] => found.push((*command, Some(*description))),
+      _ => return Err(malformed()),
+    }
+  }
+  Ok(found)
+}
+
+pub fn task_doc<'a>(name: &str, table: &'a Table) -> Result<TaskDoc<'a>, String> {
+  let mut found = Vec::new();
+  table.iter().for_each(|(_, item)| strings(item, &mut found));
+  let needs_env: BTreeSet<String> = found
+    .iter()
+    .flat_map(|text| ENV1_RE.captures_iter(text).map(|caps| caps[1].to_string()).collect::<Vec<_>>())
+    .filter(|var| !var.contains(':'))
+    .collect();
+  let command =
+    ["command", "script", "pipeline"].iter().find_map(|key| table.get(key)).map(|command| match command.as_str() {
+      Some(script) => script.to_string(),
+      None => command.to_string().trim().to_string(),
+    });
+  Ok(TaskDoc {
+    name: name.to_string(),
+    description: table.get("description").and_then(Item::as_str),
+    help: table.get("help").and_then(Item::as_str),
+    aliases: get_aliases(table),
+    tags: get_tags(table),
+    params: get_params(name, table)?,
+    depends: get_depends(name, table)?,
+    needs_env: needs_env.into_iter().collect(),
+    sets_env: table
+      .get("env")
+      .and_then(Item::as_table_like)
+      .map_or(vec![], |env| env.iter().map(|(key, _)| key).collect()),
+    command,
+    examples: get_examples(name, table)?,
+  })
+}
+
+/// The documented commands: every one that isn't hidden, in the order --cmds lists them.
+fn task_docs(config: &Config) -> Result<Vec<TaskDoc<'_>>, String> {
+  let mut docs = Vec::new();
+  for (name, table) in all_tasks("", config.doc.as_table()) {
+    if !is_hidden(name.rsplit('.').next().unwrap_or(&name), table) {
+      docs.push(task_doc(&name, table)?);
+    }
+  }
+  Ok(docs)
+}
+
+fn code_list(items: &[&str]) -> String {
+  items.iter().map(|item| format!("`{}`", item)).collect::<Vec<_>>().join(", ")
+}
+
+fn param_text(param: &Param, code: fn(&str) -> String) -> String {
+  match &param.default {
+    None => "required".to_string(),
+    Some(default) => format!("default {}", code(default)),
+  }
+}
+
+pub fn markdown(config: &Config) -> Result<String, String> {
+  let mut out = format!("# Commands\n\nThe commands in {}, run with `doit <command> [args...]`.\n", config.file);
+  for task in task_docs(config)? {
+    out.push_str(&format!("\n## {}\n\n", task.name));
+    if let Some(description) = task.description {
+      out.push_str(&format!("{}\n\n", description));
+    }
+    if let Some(help) = task.help {
+      out.push_str(&format!("{}\n\n", help.trim()));
+    }
+    if !task.aliases.is_empty() {
+      out.push_str(&format!("- Aliases: {}\n", code_list(&task.aliases)));
+    }
+    if !task.tags.is_empty() {
+      out.push_str(&format!("- Tags: {}\n", task.tags.join(", ")));
+    }
+    if !task.depends.is_empty() {
+      out.push_str(&format!("- Depends on: {}\n", code_list(&task.depends)));
+    }
+    if !task.needs_env.is_empty() {
+      let needs: Vec<&str> = task.needs_env.iter().map(String::as_str).collect();
+      out.push_str(&format!("- Needs in the environment: {}\n", code_list(&needs)));
+    }
+    if !task.sets_env.is_empty() {
+      out.push_str(&format!("- Sets in the environment: {}\n", code_list(&task.sets_env)));
+    }
+    if let Some(command) = &task.command {
+      out.push_str(&format!("- Runs: `{}`\n", command.replace('`', "'")));
+    }
+    if !task.params.is_empty() {
+      out.push_str("\nParameters, given as `name=value`:\n\n");
+      for param in &task.params {
+        out.push_str(&format!("- `{}` ({})", param.name, param_text(param, |text| format!("`{}`", text))));
+        if let Some(description) = &param.description {
+          out.push_str(&format!(": {}", description));
+        }
+        out.push('\n');
+      }
+    }
+    if !task.examples.is_empty() {
+      out.push_str("\nExamples:\n\n");
+      for (command, description) in &task.examples {
+        match description {
+          Some(description) => out.push_str(&format!("- `{}`: {}\n", command, description)),
+          None => out.push_str(&format!("- `{}`\n", command)),
+        }
+      }
+    }
+  }
+  Ok(out)
+}
+
+fn escape(text: &str) -> String {
+  text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn code_html(items: &[&str]) -> String {
+  items.iter().map(|item| format!("<code>{}</code>", escape(item))).collect::<Vec<_>>().join(", ")
+}
+
+pub fn html(config: &Config) -> Result<String, String> {
+  let mut out = format!(
+    "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Commands</title></head>\n<body>\n<h1>Commands</h1>\n\
+     <p>The commands in {}, run with <code>doit &lt;command&gt; [args...]</code>.</p>\n",
+    escape(&config.file)
+  );
+  for task in task_docs(config)? {
+    out.push_str(&format!("<h2 id=\"{0}\">{0}</h2>\n", escape(&task.name)));
+    if let Some(description) = task.description {
+      out.push_str(&format!("<p>{}</p>\n", escape(description)));
+    }
+    if let Some(help) = task.help {
+      for paragraph in help.trim().split("\n\n") {
+        out.push_str(&format!("<p>{}</p>\n", escape(paragraph)));
+      }
+    }
+    let mut facts = Vec::new();
+    if !task.aliases.is_empty() {
+      facts.push(format!("Aliases: {}", code_html(&task.aliases)));
+    }
+    if !task.tags.is_empty() {
+      facts.push(format!("Tags: {}", escape(&task.tags.join(", "))));
+    }
+    if !task.depends.is_empty() {
+      facts.push(format!("Depends on: {}", code_html(&task.depends)));
+    }
+    if !task.needs_env.is_empty() {
+      let needs: Vec<&str> = task.needs_env.iter().map(String::as_str).collect();
+      facts.push(format!("Needs in the environment: {}", code_html(&needs)));
+    }
+    if !task.sets_env.is_empty() {
+      facts.push(format!("Sets in the environment: {}", code_html(&task.sets_env)));
+    }
+    if let Some(command) = &task.command {
+      facts.push(format!("Runs: <code>{}</code>", escape(command)));
+    }
+    if !facts.is_empty() {
+      out.push_str(&format!("<ul>\n<li>{}</li>\n</ul>\n", facts.join("</li>\n<li>")));
+    }
+    if !task.params.is_empty() {
+      out.push_str("<p>Parameters, given as <code>name=value</code>:</p>\n<ul>\n");
+      for param in &task.params {
+        let default = param_text(param, |text| format!("<code>{}</code>", escape(text)));
+        let description = param.description.as_deref().map_or_else(String::new, |text| format!(": {}", escape(text)));
+        out.push_str(&format!("<li><code>{}</code> ({}){}</li>\n", escape(&param.name), default, description));
+      }
+      out.push_str("</ul>\n");
+    }
+    if !task.examples.is_empty() {
+      out.push_str("<p>Examples:</p>\n<ul>\n");
+      for (command, description) in &task.examples {
+        let description = description.map_or_else(String::new, |text| format!(": {}", escape(text)));
+        out.push_str(&format!("<li><code>{}</code>{}</li>\n", escape(command), description));
+      }
+      out.push_str("</ul>\n");
+    }
+  }
+  out.push_str("</body>\n</html>\n");
+  Ok(out)
+}
@@ -0,0 +1,118 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! `.env` files: `KEY=value` lines, optionally starting with `export`, with `#` comments. Values may be quoted:
+//! in single quotes they are taken as they are, in double quotes `\n`, `\t`, `\"` and `\\` are unescaped and they
+//! may span lines. Unquoted values are trimmed and end at a ` #` comment.
+use std::path::Path;
+
+fn valid_name(name: &str) -> bool {
+  name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The variables `text` sets, in order.
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, String> {
+  let mut vars = Vec::new();
+  let mut lines = text.lines().enumerate();
+  while let Some((number, line)) = lines.next() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+    let (name, value) = line.split_once('=').ok_or_else(|| format!("line {}: expected KEY=value", number + 1))?;
+    let name = name.trim();
+    if !valid_name(name) {
+      return Err(format!("line {}: {:?} is not a variable name", number + 1, name));
+    }
+    let value = value.trim_start();
+    let value = match value.chars().next() {
+      Some('\'') => match value[1..].split_once('\'') {
+        Some((quoted, _)) => quoted.to_string(),
+        None => return Err(format!("line {}: unterminated '", number + 1)),
+      },
+      Some('"') => {
+        let mut quoted = String::new();
+        let mut rest = value[1..].to_string();
+        loop {
+          let mut chars = rest.chars();
+          let mut closed = false;
+          while let Some(c) = chars.next() {
+            match c {
+              '"' => {
+                closed = true;
+                break;
+              }
+              '\\' => match chars.next() {
+                Some('n') => quoted.push('\n'),
+                Some('t') => quoted.push('\t'),
+                Some(c @ ('"' | '\\' | '$')) => quoted.push(c),
+                Some(c) => quoted.extend(['\\', c]),
+                None => quoted.push('\\'),
+              },
+              c => quoted.push(c),
+            }
+          }
+          if closed {
+            break;
+          }
+          // Without the closing quote the value goes on to the next line.
+          match lines.next() {
+            Some((_, next)) => {
+              quoted.push('\n');
+              rest = next.to_string();
+            }
+            None => return Err(format!("line {}: unterminated \"", number + 1)),
+          }
+        }
+        quoted
+      }
+      _ => value.split(" #").next().unwrap_or_default().trim_end().to_string(),
+    };
+    vars.push((name.to_string(), value));
+  }
+  Ok(vars)
+}
+
+pub fn load(path: &Path) -> Result<Vec<(String, String)>, String> {
+  let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+  parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars(text: &str) -> Vec<(String, String)> {
+    parse(text).unwrap()
+  }
+
+  fn var(name: &str, value: &str) -> (String, String) {
+    (name.to_string(), value.to_string())
+  }
+
+  #[test]
+  fn unquoted_values() {
+    assert_eq!(vars("A=1\n\n# a comment\n  B = two words  \n"), [var("A", "1"), var("B", "two words")]);
+    assert_eq!(
+      vars("export PATH_EXTRA=/opt/bin\nC=x # trailing\nD=a#b\nE=\n"),
+      [var("PATH_EXTRA", "/opt/bin"), var("C", "x"), var("D", "a#b"), var("E", "")]
+    );
+    assert_eq!(vars("A=1\nA=2"), [var("A", "1"), var("A", "2")]);
+  }
+
+  #[test]
+  fn quoted_values() {
+    assert_eq!(vars(r#"A='$HOME \n # kept' # comment"#), [var("A", r"$HOME \n # kept")]);
+    assert_eq!(vars(r#"B="tab\there \"q\" \\ \$ \x""#), [var("B", "tab\there \"q\" \\ $ \\x")]);
+    assert_eq!(vars("C=\"first\n  second\"\nD=after"), [var("C", "first\n  second"), var("D", "after")]);
+  }
+
+  #[test]
+  fn bad_lines() {
+    assert_eq!(parse("A=1\nnot a variable"), Err("line 2: expected KEY=value".into()));
+    assert_eq!(parse("1A=x"), Err("line 1: \"1A\" is not a variable name".into()));
+    assert_eq!(parse("A-B=x"), Err("line 1: \"A-B\" is not a variable name".into()));
+    assert_eq!(parse("A='open"), Err("line 1: unterminated '".into()));
+    assert_eq!(parse("A=\"open\nstill open"), Err("line 1: unterminated \"".into()));
+  }
+}
@@ -0,0 +1,1122 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Running a step's commands: the settings they are spawned with, the child processes, background steps and
+//! cancelling them all on a signal.
+use crate::{
+  builtins::{builtin_path, run_builtin},
+  pty, reporter, settings,
+  task::{glob_paths, run_inline_step, with_dotenv, CAPTURES, DOTENV, STEP_STATUS},
+  template::{expand_positional, render_template, render_value, ASCII_SUB1, POSITIONAL_RE},
+  Context, DoitError,
+};
+use std::{
+  cell::{Cell, RefCell},
+  collections::{BTreeMap, HashSet},
+  env,
+  fs::{remove_file, write as write_file, File},
+  io::{Read, Write},
+  os::unix::{
+    fs::MetadataExt,
+    process::{CommandExt, ExitStatusExt},
+  },
+  path::PathBuf,
+  process::{exit, Child, Command, ExitStatus, Stdio},
+  sync::{
+    atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+use toml_edit::{Array, Item, Table, TableLike, Value};
+
+/// Spawn settings for one command, taken from its step's inline table or else from the task table.
+#[derive(Debug, Default)]
+pub struct RunOptions {
+  pub cwd: Option<PathBuf>,
+  /// Variables to set, or to remove when `None`.
+  pub env: BTreeMap<String, Option<String>>,
+  pub env_clear: bool,
+  /// The `shell_cmd` prefix a string command is passed to, when `shell = true`.
+  pub shell: Option<Vec<String>>,
+  pub timeout: Option<Duration>,
+  retries: u32,
+  retry_delay: Duration,
+  retry_backoff: f64,
+  /// Whether a `%glob:...%` that matches nothing is allowed, from `glob_empty = "empty"` (or `"error"`).
+  glob_empty: bool,
+  /// The name of the `%capture:name%` the command's stdout is kept in, instead of being shown.
+  pub capture: Option<String>,
+  /// The files stdout and stderr go to, from `stdout = "path"` and `stderr = "path"`, and whether they're
+  /// appended to (a path starting with `+`) rather than truncated.
+  pub stdout: Option<(PathBuf, bool)>,
+  pub stderr: Option<(PathBuf, bool)>,
+  /// What the command reads on stdin, from `stdin = "text"` or the file of `stdin = "@path"`, rendered.
+  stdin: Option<String>,
+  /// The exit codes that count as success, from `ok_codes = [0, 1]`, when not just 0.
+  ok_codes: Option<Vec<i32>>,
+  /// Whether the command runs with a pseudo-terminal as its stdin, stdout and stderr, from `pty = true`.
+  pty: bool,
+  /// How much to lower the command's priority by, from `nice = 10`.
+  nice: Option<i32>,
+  /// The resource limits to run the command under, from `rlimit_as` (address space) and `rlimit_nofile`.
+  rlimits: Vec<(libc::c_int, libc::rlim_t)>,
+  /// Whether a pipeline fails when any stage does (`pipefail = true`, the default) or only when the last one does.
+  pipefail: bool,
+  /// The task's name and table, which &template renders with and doit-builtin-* programs are told about.
+  pub task: String,
+  pub table: Table,
+}
+
+/// Parses durations like `90s`, `500ms`, `2m` or `1h`; a bare number is taken as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+  let value = value.trim();
+  let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+  let (number, unit) = value.split_at(split);
+  let number = number.parse::<f64>().map_err(|_| format!("invalid duration: {:?}", value))?;
+  let seconds = match unit.trim() {
+    "" | "s" => number,
+    "ms" => number / 1000.0,
+    "m" => number * 60.0,
+    "h" => number * 3600.0,
+    _ => return Err(format!("invalid duration unit in {:?} (use ms, s, m or h)", value)),
+  };
+  Ok(Duration::from_secs_f64(seconds))
+}
+
+pub fn get_duration_setting(item: &Item, key: &str) -> Result<Duration, String> {
+  match (item.as_str(), item.as_integer(), item.as_float()) {
+    (Some(value), _, _) => parse_duration(value).map_err(|e| format!("{}: {}", key, e)),
+    (None, Some(seconds), _) if seconds >= 0 => Ok(Duration::from_secs(seconds as u64)),
+    (None, None, Some(seconds)) if seconds >= 0.0 => Ok(Duration::from_secs_f64(seconds)),
+    _ => Err(format!("{} must be a duration such as \"2s\" or a number of seconds", key)),
+  }
+}
+
+/// Reads a size setting: a number of bytes or a string such as "512K", "2G" (powers of 1024).
+fn get_size_setting(item: &Item, key: &str) -> Result<u64, String> {
+  let invalid = || format!("{} must be a size such as \"2G\" or a number of bytes", key);
+  match (item.as_str(), item.as_integer()) {
+    (Some(value), _) => {
+      let value = value.trim();
+      let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+      let scale: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(invalid()),
+      };
+      number.parse::<u64>().ok().and_then(|number| number.checked_mul(scale)).ok_or_else(invalid)
+    }
+    (None, Some(bytes)) if bytes >= 0 => Ok(bytes as u64),
+    _ => Err(invalid()),
+  }
+}
+
+/// Adds the entries of an `env` table to `env`: values are templated, and `"-"` removes the variable.
+fn collect_env(table: &Table, env_table: &Item, env: &mut BTreeMap<String, Option<String>>) -> Result<(), DoitError> {
+  let env_table = env_table.as_table_like().ok_or("env is not a table")?;
+  for (key, value) in env_table.iter() {
+    let value = match (value.as_str(), value.as_integer(), value.as_bool()) {
+      (Some("-"), _, _) => None,
+      (Some(value), _, _) => Some(render_value(table, value)?),
+      (None, Some(value), _) => Some(value.to_string()),
+      (None, None, Some(value)) => Some(value.to_string()),
+      _ => return Err(format!("env.{} must be a string, integer or boolean", key).into()),
+    };
+    env.insert(key.into(), value);
+  }
+  Ok(())
+}
+
+fn step_setting<'a>(table: &'a Table, step: Option<&'a dyn TableLike>, key: &str) -> Option<&'a Item> {
+  step.and_then(|step| step.get(key)).or_else(|| table.get(key))
+}
+
+/// Reads a setting given either as an argv array or as a string split on whitespace.
+fn get_argv_setting(item: &Item, key: &str) -> Result<Vec<String>, String> {
+  match (item.as_str(), item.as_array()) {
+    (Some(value), _) => Ok(value.split_whitespace().map(String::from).collect()),
+    (None, Some(values)) => values
+      .iter()
+      .map(|arg| arg.as_str().map(String::from).ok_or_else(|| format!("{} entries must be strings", key)))
+      .collect(),
+    _ => Err(format!("{} must be a string or an array", key)),
+  }
+}
+
+pub fn run_options(cmd_name: &str, table: &Table, step: Option<&dyn TableLike>) -> Result<RunOptions, DoitError> {
+  // Only a step can capture, or every command of the task would write over the same value.
+  let capture = match step.and_then(|step| step.get("capture")) {
+    None => None,
+    Some(capture) => Some(capture.as_str().ok_or("capture must be a name")?.to_string()),
+  };
+  let cwd = match step_setting(table, step, "cwd") {
+    None => None,
+    Some(cwd) => Some(PathBuf::from(render_value(table, cwd.as_str().ok_or("cwd must be a string")?)?)),
+  };
+
+  // Step-level env entries are layered over the task's rather than replacing them.
+  let mut env = BTreeMap::new();
+  for env_table in [table.get("env"), step.and_then(|step| step.get("env"))].into_iter().flatten() {
+    collect_env(table, env_table, &mut env)?;
+  }
+
+  let mut env_clear = match step_setting(table, step, "env_clear") {
+    None => false,
+    Some(env_clear) => env_clear.as_bool().ok_or("env_clear must be a boolean")?,
+  };
+
+  // `env_mode = "clean"` starts from nothing but the variables `env_allow` names (PATH and HOME unless it's
+  // given), so the task's own `env` is all that is added to them.
+  match step_setting(table, step, "env_mode").map(|env_mode| env_mode.as_str()) {
+    None | Some(Some("inherit")) => {}
+    Some(Some("clean")) => {
+      let allow = match step_setting(table, step, "env_allow") {
+        None => vec!["PATH", "HOME"],
+        Some(allow) => allow
+          .as_array()
+          .and_then(|allow| allow.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+          .ok_or("env_allow must be an array of variable names")?,
+      };
+      // A trailing `*` allows every variable starting with what comes before it, as in "LC_*".
+      let allowed = |name: &str| {
+        allow.iter().any(|allow| match allow.strip_suffix('*') {
+          Some(prefix) => name.starts_with(prefix),
+          None => name == *allow,
+        })
+      };
+      env_clear = true;
+      let given = |name: &str| settings().env.iter().any(|(given, _)| given == name);
+      for (name, value) in env::vars().filter(|(name, _)| allowed(name) || given(name)) {
+        env.entry(name).or_insert(Some(value));
+      }
+    }
+    Some(_) => return Err("env_mode must be \"inherit\" or \"clean\"".into()),
+  }
+  // What the .env files set goes under the environment doit was run in, as well as the task's own env. The
+  // later files' values are the ones that count.
+  for (name, value) in DOTENV.with(|dotenv| dotenv.borrow().clone()).into_iter().rev() {
+    if !env.contains_key(&name) && (env_clear || env::var_os(&name).is_none()) {
+      env.insert(name, Some(value));
+    }
+  }
+
+  let shell = match step_setting(table, step, "shell") {
+    None => None,
+    Some(shell) if !shell.as_bool().ok_or("shell must be a boolean")? => None,
+    Some(_) => Some(match step_setting(table, step, "shell_cmd") {
+      None => vec!["sh".into(), "-c".into()],
+      Some(shell_cmd) => get_argv_setting(shell_cmd, "shell_cmd")?,
+    }),
+  };
+
+  let timeout = match step_setting(table, step, "timeout") {
+    None => None,
+    Some(timeout) => Some(get_duration_setting(timeout, "timeout")?),
+  };
+  let retries = match step_setting(table, step, "retries") {
+    None => 0,
+    Some(retries) => {
+      retries.as_integer().and_then(|n| u32::try_from(n).ok()).ok_or("retries must be a non-negative integer")?
+    }
+  };
+  let retry_delay = match step_setting(table, step, "retry_delay") {
+    None => Duration::from_secs(1),
+    Some(delay) => get_duration_setting(delay, "retry_delay")?,
+  };
+  let retry_backoff = match step_setting(table, step, "retry_backoff") {
+    None => 1.0,
+    Some(backoff) => match (backoff.as_float(), backoff.as_integer()) {
+      (Some(backoff), _) if backoff >= 1.0 => backoff,
+      (None, Some(backoff)) if backoff >= 1 => backoff as f64,
+      _ => return Err("retry_backoff must be a number of at least 1".into()),
+    },
+  };
+  let redirect = |name: &str| -> Result<Option<(PathBuf, bool)>, DoitError> {
+    let Some(path) = step_setting(table, step, name) else {
+      return Ok(None);
+    };
+    let path = render_value(table, path.as_str().ok_or(format!("{} must be a path", name))?)?;
+    let (path, append) = match path.strip_prefix('+') {
+      Some(path) => (path, true),
+      None => (path.as_str(), false),
+    };
+    Ok(Some((cwd.as_ref().map_or_else(|| PathBuf::from(path), |cwd| cwd.join(path)), append)))
+  };
+  let (stdout, stderr) = (redirect("stdout")?, redirect("stderr")?);
+  if capture.is_some() && stdout.is_some() {
+    return Err("capture and stdout can't both be set".into());
+  }
+  let stdin = match step_setting(table, step, "stdin") {
+    None => None,
+    Some(stdin) => Some(render_value(table, stdin.as_str().ok_or("stdin must be a string")?)?),
+  };
+  let ok_codes = match step_setting(table, step, "ok_codes") {
+    None => None,
+    Some(ok_codes) => {
+      let invalid = "ok_codes must be an array of exit codes";
+      let codes = ok_codes.as_array().ok_or(invalid)?.iter();
+      Some(
+        codes
+          .map(|code| code.as_integer().and_then(|code| i32::try_from(code).ok()))
+          .collect::<Option<_>>()
+          .ok_or(invalid)?,
+      )
+    }
+  };
+  let pty = match step_setting(table, step, "pty") {
+    None => false,
+    Some(pty) => pty.as_bool().ok_or("pty must be a boolean")?,
+  };
+  let nice = match step_setting(table, step, "nice") {
+    None => None,
+    Some(nice) => Some(nice.as_integer().and_then(|nice| i32::try_from(nice).ok()).ok_or("nice must be an integer")?),
+  };
+  let mut rlimits = Vec::new();
+  if let Some(limit) = step_setting(table, step, "rlimit_as") {
+    rlimits.push((libc::RLIMIT_AS as _, get_size_setting(limit, "rlimit_as")? as libc::rlim_t));
+  }
+  if let Some(limit) = step_setting(table, step, "rlimit_nofile") {
+    let limit = limit.as_integer().and_then(|limit| u64::try_from(limit).ok());
+    rlimits.push((libc::RLIMIT_NOFILE as _, limit.ok_or("rlimit_nofile must be a number of files")? as libc::rlim_t));
+  }
+  let pipefail = match step_setting(table, step, "pipefail") {
+    None => true,
+    Some(pipefail) => pipefail.as_bool().ok_or("pipefail must be a boolean")?,
+  };
+  let glob_empty = match step_setting(table, step, "glob_empty").map(|glob_empty| glob_empty.as_str()) {
+    None | Some(Some("error")) => false,
+    Some(Some("empty")) => true,
+    Some(_) => return Err("glob_empty must be \"error\" or \"empty\"".into()),
+  };
+  Ok(RunOptions {
+    cwd,
+    env,
+    env_clear,
+    shell,
+    timeout,
+    retries,
+    retry_delay,
+    retry_backoff,
+    glob_empty,
+    capture,
+    stdout,
+    stderr,
+    stdin,
+    ok_codes,
+    pty,
+    nice,
+    rlimits,
+    pipefail,
+    task: cmd_name.into(),
+    table: table.clone(),
+  })
+}
+
+/// Runs `args`, retrying up to `options.retries` more times on failure with a delay that grows by
+/// `retry_backoff` after each attempt.
+fn run_cmd(args: Vec<String>, options: &RunOptions) -> Result<(), DoitError> {
+  with_retries(options, || run_cmd_once(&args, options))
+}
+
+/// Runs a pipeline's stages, with retries as for run_cmd.
+fn run_pipeline(stages: Vec<Vec<String>>, options: &RunOptions) -> Result<(), DoitError> {
+  with_retries(options, || {
+    check_cancelled()?;
+    if settings().dry_run {
+      let stages: Vec<String> = stages.iter().map(|stage| format!("{:?}", stage)).collect();
+      reporter::info(&stages.join(" | "));
+      print_dry_run_options(options);
+      return Ok(());
+    }
+    if let Some(stage) =
+      stages.iter().find(|stage| stage.iter().find(|arg| *arg != "-rc").is_some_and(|cmd| cmd.starts_with('&')))
+    {
+      return Err(format!("{:?}: builtins can't be part of a pipeline", stage).into());
+    }
+    let stages: Vec<&[String]> = stages.iter().map(Vec::as_slice).collect();
+    run_program(&stages, options, stdin_input(options)?)
+  })
+}
+
+fn with_retries(options: &RunOptions, mut run: impl FnMut() -> Result<(), DoitError>) -> Result<(), DoitError> {
+  let mut delay = options.retry_delay;
+  for attempt in 1..=options.retries {
+    match run() {
+      Ok(()) => return Ok(()),
+      Err(e) => {
+        check_cancelled()?;
+        reporter::warning(&format!(
+          "{}\nattempt {}/{} failed, retrying in {:?}",
+          e,
+          attempt,
+          options.retries + 1,
+          delay
+        ));
+        thread::sleep(delay);
+        delay = delay.mul_f64(options.retry_backoff);
+      }
+    }
+  }
+  run()
+}
+
+/// The exit status of a run whose command was killed for running past its `timeout`, as with timeout(1).
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// How long a timed out child gets to exit after SIGTERM before it is sent SIGKILL.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Waits for `child`, returning `None` if it had to be killed for running past `timeout`.
+fn wait_child(child: &mut Child, timeout: Option<Duration>) -> std::io::Result<Option<ExitStatus>> {
+  let Some(timeout) = timeout else {
+    return child.wait().map(Some);
+  };
+
+  let poll = |child: &mut Child, limit: Duration| -> std::io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + limit;
+    while Instant::now() < deadline {
+      if let Some(exit_status) = child.try_wait()? {
+        return Ok(Some(exit_status));
+      }
+      thread::sleep(Duration::from_millis(20));
+    }
+    child.try_wait()
+  };
+
+  if let Some(exit_status) = poll(child, timeout)? {
+    return Ok(Some(exit_status));
+  }
+  unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+  if poll(child, TIMEOUT_GRACE)?.is_none() {
+    child.kill()?;
+    child.wait()?;
+  }
+  Ok(None)
+}
+
+/// Set to stop the current run: no new commands are started and the running ones are sent a signal.
+pub static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Process ids of the children currently running, so a cancelled run can terminate them.
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// The SIGINT or SIGTERM doit was sent, or 0.
+pub static SIGNALLED: AtomicI32 = AtomicI32::new(0);
+
+/// Set while a `confirm` prompt waits for an answer, when a signal has to exit doit right away.
+pub static PROMPTING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+  /// Set while `finally` steps run, which are started even when the run has been cancelled.
+  pub static IN_FINALLY: Cell<bool> = const { Cell::new(false) };
+  /// On the thread of a background step, where the process ids of its children go too.
+  static JOB_PIDS: RefCell<Option<Arc<Mutex<Vec<u32>>>>> = const { RefCell::new(None) };
+  /// The background steps the task being run on this thread has started and not yet waited for.
+  pub static BACKGROUND: RefCell<Vec<Job>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A step started with `background = true`, until `&wait` or the end of its task.
+pub struct Job {
+  name: String,
+  handle: thread::JoinHandle<Result<(), DoitError>>,
+  pids: Arc<Mutex<Vec<u32>>>,
+}
+
+/// Records a child as started or finished.
+fn track_child(pid: u32, running: bool) {
+  let track = |pids: &Mutex<Vec<u32>>| match running {
+    true => pids.lock().unwrap().push(pid),
+    false => pids.lock().unwrap().retain(|running| *running != pid),
+  };
+  track(&CHILDREN);
+  JOB_PIDS.with(|pids| pids.borrow().as_deref().map(track));
+}
+
+/// Starts a `background = true` step on its own thread, to be waited for with `&wait <name>`. The name is the
+/// step's `name`, or else where it is, such as `pre:2`.
+pub fn start_background(
+  which: &str,
+  cmd_name: &str,
+  table: &Table,
+  index: usize,
+  step: &toml_edit::InlineTable,
+) -> Result<(), DoitError> {
+  let name = match step.get("name") {
+    None => format!("{}:{}", which, index + 1),
+    Some(name) => name.as_str().ok_or_else(|| format!("{}[{}]: name must be a string", which, index))?.into(),
+  };
+  if step.contains_key("capture") {
+    return Err(format!("{}[{}]: a background step can't capture", which, index).into());
+  }
+  if BACKGROUND.with(|jobs| jobs.borrow().iter().any(|job| job.name == name)) {
+    return Err(format!("{}[{}]: there is already a background step named {}", which, index, name).into());
+  }
+
+  let (which, cmd_name, table, step) = (which.to_string(), cmd_name.to_string(), table.clone(), step.clone());
+  let prefix = reporter::task_prefix();
+  let pids = Arc::new(Mutex::new(Vec::new()));
+  let job_pids = pids.clone();
+  // The step sees what the task captured before it started and what its .env files set, as it would in the
+  // foreground.
+  let captures = CAPTURES.with(|captures| captures.borrow().clone());
+  let dotenv = DOTENV.with(|dotenv| dotenv.borrow().clone());
+  let context = Context::current();
+  let handle = thread::spawn(move || {
+    reporter::set_task_prefix(prefix);
+    JOB_PIDS.with(|pids| pids.replace(Some(job_pids)));
+    CAPTURES.with(|outer| outer.replace(captures));
+    with_dotenv(dotenv, || context.enter(|| run_inline_step(&step, &which, &cmd_name, &table, index)))
+  });
+  BACKGROUND.with(|jobs| jobs.borrow_mut().push(Job { name, handle, pids }));
+  Ok(())
+}
+
+/// Stops the background steps that are still running, SIGTERM first and then SIGKILL, and waits for them.
+pub fn stop_background(jobs: Vec<Job>) {
+  let signal = |job: &Job, signal| {
+    for pid in job.pids.lock().unwrap().iter() {
+      unsafe { libc::kill(*pid as libc::pid_t, signal) };
+    }
+  };
+  let running: Vec<&Job> = jobs.iter().filter(|job| !job.handle.is_finished()).collect();
+  for job in &running {
+    reporter::verbose(&format!("Stopping background step {}", job.name));
+    signal(job, libc::SIGTERM);
+  }
+  let deadline = Instant::now() + TIMEOUT_GRACE;
+  while running.iter().any(|job| !job.handle.is_finished()) && Instant::now() < deadline {
+    thread::sleep(Duration::from_millis(20));
+  }
+  for job in running.iter().filter(|job| !job.handle.is_finished()) {
+    signal(job, libc::SIGKILL);
+  }
+  for job in jobs {
+    let _ = job.handle.join();
+  }
+}
+
+/// `&wait [name...]` waits for the named background steps of the task, or all of them, failing if any did.
+pub fn builtin_wait(args: &[String]) -> Result<(), DoitError> {
+  let jobs = BACKGROUND.with(|jobs| {
+    let mut jobs = jobs.borrow_mut();
+    if let Some(unknown) = args.iter().find(|name| !jobs.iter().any(|job| job.name == **name)) {
+      let names: Vec<&str> = jobs.iter().map(|job| job.name.as_str()).collect();
+      return Err(DoitError::Builtin(format!(
+        "&wait: there is no background step {} to wait for (running: {})",
+        unknown,
+        if names.is_empty() { "none".into() } else { names.join(", ") }
+      )));
+    }
+    let (wanted, rest) = jobs.drain(..).partition(|job| args.is_empty() || args.contains(&job.name));
+    *jobs = rest;
+    Ok::<Vec<Job>, DoitError>(wanted)
+  })?;
+
+  let mut failed: Option<DoitError> = None;
+  for job in jobs {
+    let result = job.handle.join().unwrap_or_else(|_| Err("panicked".into()));
+    if let Err(e) = result {
+      let e = e.map_message(|e| format!("&wait: background step {} failed: {}", job.name, e));
+      failed = Some(match failed {
+        None => e,
+        Some(first) => first.map_message(|first| format!("{}\n{}", first, e)),
+      });
+    }
+  }
+  failed.map_or(Ok(()), Err)
+}
+
+/// Cancels the run, sending `signal` to the running commands unless they have already been sent it.
+pub fn cancel_run(signal: Option<libc::c_int>) {
+  CANCELLED.store(true, Ordering::SeqCst);
+  if let Some(signal) = signal {
+    for pid in CHILDREN.lock().unwrap().iter() {
+      unsafe { libc::kill(*pid as libc::pid_t, signal) };
+    }
+  }
+}
+
+pub fn check_cancelled() -> Result<(), DoitError> {
+  if !CANCELLED.load(Ordering::SeqCst) || IN_FINALLY.with(Cell::get) {
+    return Ok(());
+  }
+  match SIGNALLED.load(Ordering::SeqCst) {
+    0 => Err("cancelled".into()),
+    signal => Err(DoitError::Child { message: format!("interrupted by signal {}", signal), code: 128 + signal }),
+  }
+}
+
+/// Waits for one of the signals in `set`, returning it and whether it came from the terminal, which sends it to
+/// the whole foreground process group and so to the running commands too.
+#[cfg(target_os = "linux")]
+fn wait_signal(set: &libc::sigset_t) -> (libc::c_int, bool) {
+  let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+  let signal = unsafe { libc::sigwaitinfo(set, &mut info) };
+  (signal, info.si_code == libc::SI_KERNEL)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wait_signal(set: &libc::sigset_t) -> (libc::c_int, bool) {
+  let mut signal = 0;
+  unsafe { libc::sigwait(set, &mut signal) };
+  (signal, signal == libc::SIGINT)
+}
+
+/// Takes SIGINT and SIGTERM on a thread of their own. The first cancels the run, passing the signal on to the
+/// running commands and leaving `finally` steps to run before doit exits with 128 + the signal; a second one, or
+/// one at a `confirm` prompt, exits at once. Must be called before any other thread is started so that they all
+/// inherit the blocked signal mask.
+pub fn handle_signals() {
+  let mut set: libc::sigset_t = unsafe { std::mem::zeroed() };
+  unsafe {
+    libc::sigemptyset(&mut set);
+    libc::sigaddset(&mut set, libc::SIGINT);
+    libc::sigaddset(&mut set, libc::SIGTERM);
+    libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+  }
+  thread::spawn(move || loop {
+    let (signal, from_terminal) = wait_signal(&set);
+    if signal <= 0 {
+      continue;
+    }
+    let forward = if from_terminal { None } else { Some(signal) };
+    if SIGNALLED.swap(signal, Ordering::SeqCst) != 0 || PROMPTING.load(Ordering::SeqCst) {
+      cancel_run(forward);
+      exit(128 + signal);
+    }
+    cancel_run(forward);
+  });
+}
+
+/// Prints `args` as --dry-run shows them, followed by the cwd and environment changes they would run with.
+fn print_dry_run(args: &[String], options: &RunOptions) {
+  reporter::info(&format!("{:?}", args));
+  print_dry_run_options(options);
+}
+
+/// The input `stdin` gives a command: the file named after an `@`, or else the text itself (`@@` for a leading `@`).
+fn stdin_input(options: &RunOptions) -> Result<Option<Vec<u8>>, DoitError> {
+  match options.stdin.as_deref() {
+    None => Ok(None),
+    Some(text) if text.starts_with("@@") => Ok(Some(text[1..].into())),
+    Some(text) => match text.strip_prefix('@') {
+      Some(path) => {
+        let path = builtin_path(options, path);
+        Ok(Some(std::fs::read(&path).map_err(|e| format!("stdin: {}: {}", path.display(), e))?))
+      }
+      None => Ok(Some(text.into())),
+    },
+  }
+}
+
+fn print_dry_run_options(options: &RunOptions) {
+  if let Some(cwd) = &options.cwd {
+    reporter::detail(&format!("  cwd: {}", cwd.display()));
+  }
+  if options.env_clear {
+    reporter::detail("  env: cleared");
+  }
+  if let Some(stdin) = &options.stdin {
+    reporter::detail(&format!("  stdin: {}", stdin));
+  }
+  for (key, value) in &options.env {
+    match value {
+      Some(value) => reporter::detail(&format!("  env: {}={}", key, value)),
+      None => reporter::detail(&format!("  env: unset {}", key)),
+    }
+  }
+}
+
+fn run_cmd_once(args: &[String], options: &RunOptions) -> Result<(), DoitError> {
+  if args.is_empty() || &args[0] == "#" {
+    return Ok(());
+  }
+  check_cancelled()?;
+
+  let ignore_rc = args[0] == "-rc";
+  if ignore_rc && (args.len() == 1) {
+    return Ok(());
+  }
+
+  let (cmd, argv) = if ignore_rc { (&args[1], &args[2..]) } else { (&args[0], &args[1..]) };
+
+  // `&task` still runs under --dry-run so the commands of the task it names are printed too.
+  if settings().dry_run && cmd != "&task" {
+    print_dry_run(args, options);
+    return Ok(());
+  }
+
+  match cmd {
+    _builtin if cmd.starts_with("&") => run_builtin(&cmd[1..], argv, options),
+    _ => run_program(&[args], options, stdin_input(options)?),
+  }
+}
+
+/// Opens a file a step's stdout or stderr is redirected to, making its directory first.
+pub fn open_redirect((path, append): &(PathBuf, bool)) -> Result<File, DoitError> {
+  let open = || {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+      std::fs::create_dir_all(parent)?;
+    }
+    std::fs::OpenOptions::new().create(true).write(true).append(*append).truncate(!*append).open(path)
+  };
+  Ok(open().map_err(|e| format!("{}: {}", path.display(), e))?)
+}
+
+/// Spawns the stages of a pipeline, each one's stdout going to the next one's stdin, and waits for them. A single
+/// command is a pipeline of one. The first stage gets `input` on stdin when there is some; a stage starting with
+/// `-rc` can fail without failing the pipeline.
+pub fn run_program(stages: &[&[String]], options: &RunOptions, input: Option<Vec<u8>>) -> Result<(), DoitError> {
+  let prefix = reporter::task_prefix();
+  let stdout = options.stdout.as_ref().map(open_redirect).transpose()?;
+  let mut stderr = options.stderr.as_ref().map(open_redirect).transpose()?;
+  if let (Some(out), Some(err)) = (&stdout, &stderr) {
+    // Both going to the same file share one handle, so neither writes over the other.
+    let id = |file: &File| file.metadata().map(|meta| (meta.dev(), meta.ino())).ok();
+    if id(out).is_some() && id(out) == id(err) {
+      stderr = Some(out.try_clone().map_err(|e| e.to_string())?);
+    }
+  }
+  let mut stdout = stdout;
+  let mut pty = match options.pty {
+    false => None,
+    true if stages.len() > 1 => return Err("pty = true can't be used with a pipeline".into()),
+    true => Some(pty::Pty::open()?),
+  };
+
+  let mut children: Vec<(&[String], bool, Child)> = Vec::new();
+  let mut piped = None;
+  let mut spawn = |children: &mut Vec<_>| -> Result<(), DoitError> {
+    for (i, args) in stages.iter().enumerate() {
+      let ignore_rc = args.first().is_some_and(|arg| arg == "-rc");
+      let program = if ignore_rc { &args[1..] } else { args };
+      let last = i + 1 == stages.len();
+      let mut command = Command::new(&program[0]);
+      command.args(&program[1..]);
+      // Commands get the signals handle_signals blocks back, or SIGTERM from a timeout or from stopping a
+      // background step would do nothing.
+      unsafe {
+        command.pre_exec(|| {
+          let mut set: libc::sigset_t = std::mem::zeroed();
+          libc::sigemptyset(&mut set);
+          libc::pthread_sigmask(libc::SIG_SETMASK, &set, std::ptr::null_mut());
+          Ok(())
+        });
+      }
+      let (nice, rlimits) = (options.nice, options.rlimits.clone());
+      if nice.is_some() || !rlimits.is_empty() {
+        unsafe {
+          command.pre_exec(move || {
+            if let Some(nice) = nice {
+              let current = libc::getpriority(libc::PRIO_PROCESS as _, 0);
+              if libc::setpriority(libc::PRIO_PROCESS as _, 0, current + nice) != 0 {
+                return Err(std::io::Error::last_os_error());
+              }
+            }
+            for (resource, limit) in &rlimits {
+              if libc::setrlimit(*resource as _, &libc::rlimit { rlim_cur: *limit, rlim_max: *limit }) != 0 {
+                return Err(std::io::Error::last_os_error());
+              }
+            }
+            Ok(())
+          });
+        }
+      }
+      if let Some(cwd) = &options.cwd {
+        command.current_dir(cwd);
+      }
+      if options.env_clear {
+        command.env_clear();
+      }
+      for (key, value) in &options.env {
+        match value {
+          Some(value) => command.env(key, value),
+          None => command.env_remove(key),
+        };
+      }
+
+      if let Some(pty) = pty.as_mut() {
+        pty.attach(&mut command, stderr.is_none())?;
+        if let Some(file) = &stderr {
+          command.stderr(file.try_clone().map_err(|e| e.to_string())?);
+        }
+      } else {
+        match piped.take() {
+          Some(from) => command.stdin(Stdio::from(from)),
+          None if input.is_some() => command.stdin(Stdio::piped()),
+          None => &mut command,
+        };
+        match stdout.take() {
+          Some(file) if last => command.stdout(file),
+          _ if !last || prefix.is_some() || options.capture.is_some() => command.stdout(Stdio::piped()),
+          _ => &mut command,
+        };
+        match &stderr {
+          Some(file) => command.stderr(file.try_clone().map_err(|e| e.to_string())?),
+          None if prefix.is_some() => command.stderr(Stdio::piped()),
+          None => &mut command,
+        };
+      }
+      let mut child = command.spawn().map_err(|e| match &options.cwd {
+        Some(cwd) => format!("{} (in {}): {}", program[0], cwd.display(), e),
+        None => format!("{}: {}", program[0], e),
+      })?;
+      track_child(child.id(), true);
+      if !last {
+        piped = child.stdout.take();
+      }
+      children.push((*args, ignore_rc, child));
+    }
+    Ok(())
+  };
+  if let Err(e) = spawn(&mut children) {
+    // The stages already started get EOF or SIGPIPE once the pipe they were writing to is gone.
+    for (_, _, child) in &mut children {
+      let _ = child.wait();
+      track_child(child.id(), false);
+    }
+    return Err(e);
+  }
+
+  // The terminal's output goes wherever the command's stdout would have.
+  let master = match &pty {
+    Some(pty) => Some(pty.master.try_clone().map_err(|e| format!("pty: {}", e))?),
+    None => None,
+  };
+  let mut output: Option<Box<dyn Read + Send>> = match &master {
+    Some(master) => Some(Box::new(master.try_clone().map_err(|e| format!("pty: {}", e))?)),
+    None => children.last_mut().and_then(|(_, _, child)| child.stdout.take()).map(|stdout| Box::new(stdout) as _),
+  };
+  let stdin = match &master {
+    Some(master) => Some(Box::new(master.try_clone().map_err(|e| format!("pty: {}", e))?) as Box<dyn Write + Send>),
+    None => children[0].2.stdin.take().map(|stdin| Box::new(stdin) as _),
+  };
+  let proxy = master.filter(|_| input.is_none());
+  if let (Some(input), Some(mut stdin)) = (input, stdin) {
+    // Written from its own thread so a child that doesn't read it all can't hold everything up. At a terminal,
+    // the end of the input is the end-of-file character.
+    let eof: &[u8] = if pty.is_some() { b"\x04" } else { b"" };
+    thread::spawn(move || stdin.write_all(&input).and_then(|_| stdin.write_all(eof)));
+  }
+
+  let stderrs: Vec<_> = children.iter_mut().filter_map(|(_, _, child)| child.stderr.take()).collect();
+  let done = AtomicBool::new(false);
+  let (exit_statuses, captured) = thread::scope(|scope| {
+    if let Some(master) = proxy {
+      scope.spawn(|| pty::proxy_input(master, &done));
+    }
+    let captured = options.capture.as_ref().and_then(|_| output.take()).map(|mut stdout| {
+      scope.spawn(move || {
+        let mut captured = Vec::new();
+        let _ = stdout.read_to_end(&mut captured);
+        captured
+      })
+    });
+    if let Some(prefix) = &prefix {
+      if let Some(stdout) = output.take() {
+        scope.spawn(|| reporter::prefix_lines(stdout, prefix, false));
+      }
+      for stderr in stderrs {
+        scope.spawn(|| reporter::prefix_lines(stderr, prefix, true));
+      }
+    }
+    if let Some(master) = output.take().filter(|_| pty.is_some()) {
+      scope.spawn(|| match stdout.take() {
+        Some(mut file) => pty::copy_output(master, &mut file),
+        None => pty::copy_output(master, &mut std::io::stdout()),
+      });
+    }
+    let exit_statuses: Vec<_> = children.iter_mut().map(|(_, _, child)| wait_child(child, options.timeout)).collect();
+    done.store(true, Ordering::SeqCst);
+    (exit_statuses, captured.map(|captured| captured.join().unwrap_or_default()))
+  });
+  for (_, _, child) in &children {
+    track_child(child.id(), false);
+  }
+  check_cancelled()?;
+  if let (Some(name), Some(captured), Some(Ok(Some(_)))) = (&options.capture, captured, exit_statuses.last()) {
+    let captured = String::from_utf8_lossy(&captured).trim_end_matches(['\n', '\r']).to_string();
+    CAPTURES.with(|captures| captures.borrow_mut().insert(name.clone(), captured));
+  }
+
+  // With pipefail the last stage to fail decides, as in bash; otherwise only the last stage counts.
+  let mut failed = None;
+  let count = exit_statuses.len();
+  for (i, ((args, ignore_rc, _), exit_status)) in children.iter().zip(exit_statuses).enumerate() {
+    let exit_status = match exit_status {
+      Ok(None) => {
+        let message = format!("{:?}\ntimed out after {:?}", args, options.timeout.unwrap_or_default());
+        return Err(DoitError::Child { message, code: TIMEOUT_EXIT_CODE });
+      }
+      Err(e) => return Err(e.to_string().into()),
+      Ok(Some(exit_status)) => exit_status,
+    };
+    let success = match &options.ok_codes {
+      Some(ok_codes) => exit_status.code().is_some_and(|code| ok_codes.contains(&code)),
+      None => exit_status.success(),
+    };
+    if !success && !ignore_rc && (options.pipefail || i + 1 == count) {
+      failed = Some((args, exit_status));
+    }
+  }
+  let Some((args, exit_status)) = failed else {
+    return Ok(());
+  };
+
+  // A command killed by a signal has no exit code; report it the way shells do, as 128 + the signal.
+  let (rc, failure) = match (exit_status.code(), exit_status.signal()) {
+    (Some(rc), _) => (rc, format!("failed with exit status: {}", rc)),
+    (None, Some(signal)) => (128 + signal, format!("terminated by signal {}", signal)),
+    (None, None) => (1, format!("failed: {}", exit_status)),
+  };
+  STEP_STATUS.with(|status| status.borrow_mut().exit_code = Some(rc));
+  Err(DoitError::Child { message: format!("{:?}\n{}", args, failure), code: rc })
+}
+
+pub fn run_argv(
+  vec_in: &Array,
+  which: &str,
+  table: &Table,
+  index: usize,
+  args: &[String],
+  options: &RunOptions,
+) -> Result<(), DoitError> {
+  run_cmd(build_argv(vec_in, which, table, index, args, true, options)?, options)
+}
+
+/// Runs the argv arrays of `command = [[...], ["|"], [...]]` or `pipeline = [[...], [...]]` as a pipeline; the
+/// `["|"]` separators are optional. Extra args not placed by any stage go to the end of the last one.
+pub fn run_argv_pipeline(
+  vec_in: &Array,
+  which: &str,
+  table: &Table,
+  index: usize,
+  args: &[String],
+  options: &RunOptions,
+) -> Result<(), DoitError> {
+  let mut stages_in: Vec<&Array> = Vec::new();
+  for stage in vec_in {
+    let stage =
+      stage.as_array().ok_or_else(|| format!("{}[{}]: every pipeline stage must be an argv array", which, index))?;
+    if !(stage.len() == 1 && stage.get(0).and_then(Value::as_str) == Some("|")) {
+      stages_in.push(stage);
+    }
+  }
+  // As with a single command, args are only appended when no stage places them itself.
+  let places_args = |template: &str| {
+    template == "%args%"
+      || template == ":%args%"
+      || template.starts_with(':') && POSITIONAL_RE.is_match(&template.replace("%%", ASCII_SUB1))
+  };
+  let placed = stages_in.iter().flat_map(|stage| stage.iter()).filter_map(Value::as_str).any(places_args);
+  let mut stages = Vec::new();
+  for (i, stage) in stages_in.iter().enumerate() {
+    stages.push(build_argv(stage, which, table, index, args, !placed && i + 1 == stages_in.len(), options)?);
+  }
+  run_pipeline(stages, options)
+}
+
+/// Builds a command line from the argv array of a step. Extra args not placed with %N%, %@% or %args% are
+/// appended when `append_args` is set.
+fn build_argv(
+  vec_in: &Array,
+  which: &str,
+  table: &Table,
+  index: usize,
+  args: &[String],
+  append_args: bool,
+  options: &RunOptions,
+) -> Result<Vec<String>, DoitError> {
+  Ok({
+    if vec_in.is_empty() {
+      return Err(DoitError::Config(format!("{}[{}] arg vector is empty", which, index)));
+    }
+    // Elements are templates when they start with `:`, except that `::` escapes a literal leading `:`; a
+    // `{ raw = "..." }` element is always used as is. Templates are kept as Ok and raw elements as Err.
+    let mut templates = Vec::new();
+    for arg in vec_in {
+      let raw = arg.as_inline_table().filter(|raw| raw.len() == 1).and_then(|raw| raw.get("raw"));
+      match (arg.as_str(), raw.and_then(Value::as_str)) {
+        (Some(x), _) if x.starts_with("::") => templates.push(Err(&x[1..])),
+        (Some(x), _) => templates.push(Ok(x)),
+        (None, Some(x)) => templates.push(Err(x)),
+        _ => {
+          return Err(format!("Unable to extract argument {} as a string", arg).into());
+        }
+      }
+    }
+
+    // Extra args are only appended when the command doesn't place them itself with %N%, %@% or an %args%
+    // element, which (with or without the leading `:`) is the same as `:%@%`.
+    let is_args = |template: &str| template == ":%args%" || template == "%args%";
+    let mut positional = templates.iter().flatten().any(|template| is_args(template));
+    let mut used = HashSet::new();
+    for template in templates.iter().flatten().filter(|template| template.starts_with(':')) {
+      for caps in POSITIONAL_RE.captures_iter(&template.replace("%%", ASCII_SUB1)) {
+        positional = true;
+        if let Ok(n) = caps[1].parse::<usize>() {
+          used.insert(n);
+        }
+      }
+    }
+    let rest: Vec<String> =
+      args.iter().enumerate().filter(|(i, _)| !used.contains(&(i + 1))).map(|(_, arg)| arg.clone()).collect();
+
+    let mut vec: Vec<String> = Vec::new();
+    for template in templates {
+      let template = match template {
+        Ok(template) => template,
+        Err(raw) => {
+          vec.push(raw.to_string());
+          continue;
+        }
+      };
+      // A standalone `%glob:...%` becomes one argument per matching path.
+      let glob_pattern = template.strip_prefix(":%glob:").and_then(|pattern| pattern.strip_suffix('%'));
+      if template == ":%@%" || is_args(template) {
+        vec.extend_from_slice(&rest);
+      } else if let Some(pattern) = glob_pattern {
+        let pattern = render_value(table, pattern)?;
+        let paths = glob_paths(&pattern)?;
+        if paths.is_empty() && !options.glob_empty {
+          return Err(
+            format!("{}[{}]: {} matched nothing; set glob_empty = \"empty\" to allow that", which, index, pattern)
+              .into(),
+          );
+        }
+        vec.extend(paths);
+      } else if positional && template.starts_with(':') {
+        vec.push(render_template(table, &expand_positional(template, args, &rest)?)?);
+      } else {
+        vec.push(render_template(table, template)?);
+      }
+    }
+    if !positional && append_args {
+      vec.extend_from_slice(args);
+    }
+    vec
+  })
+}
+
+/// Runs a command string through the configured shell. Extra args follow the task name, which the
+/// shell sees as `$0`, so the script can refer to them as `$1`, `$@` and so on.
+pub fn run_shell(
+  script: &str,
+  cmd_name: &str,
+  table: &Table,
+  args: &[String],
+  shell: &[String],
+  options: &RunOptions,
+) -> Result<(), DoitError> {
+  let mut vec = shell.to_vec();
+  vec.push(render_template(table, script)?);
+  vec.push(cmd_name.into());
+  vec.extend_from_slice(args);
+  run_cmd(vec, options)
+}
+
+static SCRIPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs a `script` body with its `interpreter` (default `sh -c`). When the interpreter argv ends in an
+/// option such as `-c` the script is passed inline, otherwise it is written to a temporary file whose path
+/// is passed instead.
+pub fn run_script(
+  script: &Item,
+  which: &str,
+  cmd_name: &str,
+  table: &Table,
+  index: usize,
+  args: &[String],
+  step: Option<&dyn TableLike>,
+) -> Result<(), DoitError> {
+  let options = run_options(cmd_name, table, step)?;
+  let script =
+    render_template(table, script.as_str().ok_or_else(|| format!("{}[{}]: script must be a string", which, index))?)?;
+  let mut vec = match step_setting(table, step, "interpreter") {
+    None => vec!["sh".into(), "-c".into()],
+    Some(interpreter) => get_argv_setting(interpreter, "interpreter")?,
+  };
+  if vec.is_empty() {
+    return Err(format!("{}[{}]: interpreter is empty", which, index).into());
+  }
+
+  if vec.last().is_some_and(|arg| arg.starts_with('-')) {
+    vec.push(script);
+    vec.push(cmd_name.into());
+    vec.extend_from_slice(args);
+    return run_cmd(vec, &options);
+  }
+  if settings().dry_run {
+    vec.push("<script>".into());
+    vec.extend_from_slice(args);
+    print_dry_run(&vec, &options);
+    for line in script.lines() {
+      reporter::detail(&format!("  | {}", line));
+    }
+    return Ok(());
+  }
+
+  let path = env::temp_dir().join(format!(
+    "doit-{}-{}.script",
+    std::process::id(),
+    SCRIPT_COUNT.fetch_add(1, Ordering::Relaxed)
+  ));
+  write_file(&path, script).map_err(|e| format!("{}: {}", path.display(), e))?;
+  vec.push(path.display().to_string());
+  vec.extend_from_slice(args);
+  let result = run_cmd(vec, &options);
+  let _ = remove_file(&path);
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn durations() {
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration(" 500ms "), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+    assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("3"), Ok(Duration::from_secs(3)));
+    assert_eq!(parse_duration("0.25 s"), Ok(Duration::from_millis(250)));
+  }
+
+  #[test]
+  fn bad_durations() {
+    assert_eq!(parse_duration("soon"), Err("invalid duration: \"soon\"".into()));
+    assert_eq!(parse_duration("5d"), Err("invalid duration unit in \"5d\" (use ms, s, m or h)".into()));
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("-1s").is_err());
+  }
+
+  fn size(value: Item) -> Result<u64, String> {
+    get_size_setting(&value, "rlimit_as")
+  }
+
+  #[test]
+  fn sizes() {
+    assert_eq!(size(toml_edit::value(4096)), Ok(4096));
+    assert_eq!(size(toml_edit::value("512")), Ok(512));
+    assert_eq!(size(toml_edit::value("512K")), Ok(512 << 10));
+    assert_eq!(size(toml_edit::value("2 MiB")), Ok(2 << 20));
+    assert_eq!(size(toml_edit::value("2g")), Ok(2 << 30));
+    assert_eq!(size(toml_edit::value("1TB")), Ok(1 << 40));
+    assert_eq!(size(toml_edit::value("100B")), Ok(100));
+  }
+
+  #[test]
+  fn bad_sizes() {
+    let invalid = Err("rlimit_as must be a size such as \"2G\" or a number of bytes".to_string());
+    assert_eq!(size(toml_edit::value(-1)), invalid);
+    assert_eq!(size(toml_edit::value("2P")), invalid);
+    assert_eq!(size(toml_edit::value("K")), invalid);
+    assert_eq!(size(toml_edit::value("1.5G")), invalid);
+    assert_eq!(size(toml_edit::value("99999999999T")), invalid);
+    assert_eq!(size(toml_edit::value(true)), invalid);
+  }
+}
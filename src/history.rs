@@ -0,0 +1,112 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! What doit has run, for --last to run again and --history to list: one JSON object a line in
+//! `$XDG_STATE_HOME/doit/history.jsonl`, falling back to `~/.local/state/doit/history.jsonl`.
+use serde_json::json;
+use std::{
+  env,
+  fs::{self, OpenOptions},
+  io::Write,
+  path::{Path, PathBuf},
+};
+
+pub struct Run {
+  /// When it started, in seconds since the epoch.
+  pub time: libc::time_t,
+  /// The directory doit ran in, which is the config file's.
+  pub cwd: PathBuf,
+  /// The commands run and their arguments, more than one for a chain.
+  pub commands: Vec<(String, Vec<String>)>,
+  pub status: i32,
+  /// How long it took, in seconds.
+  pub duration: f64,
+}
+
+fn history_file() -> Option<PathBuf> {
+  let state_dir = match env::var_os("XDG_STATE_HOME") {
+    Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+    _ => dirs::home_dir()?.join(".local").join("state"),
+  };
+  Some(state_dir.join("doit").join("history.jsonl"))
+}
+
+fn run_json(run: &Run) -> serde_json::Value {
+  let (task, args) = &run.commands[0];
+  let mut line = json!({
+    "time": run.time,
+    "cwd": run.cwd.display().to_string(),
+    "task": task,
+    "args": args,
+    "status": run.status,
+    "duration": (run.duration * 1000.0).round() / 1000.0,
+  });
+  if run.commands.len() > 1 {
+    line["chain"] = run.commands.iter().map(|(task, args)| json!({"task": task, "args": args})).collect();
+  }
+  line
+}
+
+fn json_run(line: &serde_json::Value) -> Option<Run> {
+  let command = |entry: &serde_json::Value| -> Option<(String, Vec<String>)> {
+    let args = entry["args"].as_array()?.iter().map(|arg| arg.as_str().map(String::from)).collect::<Option<_>>()?;
+    Some((entry["task"].as_str()?.to_string(), args))
+  };
+  let commands = match line.get("chain").and_then(serde_json::Value::as_array) {
+    Some(chain) => chain.iter().map(command).collect::<Option<Vec<_>>>()?,
+    None => vec![command(line)?],
+  };
+  Some(Run {
+    time: line["time"].as_i64()?,
+    cwd: PathBuf::from(line["cwd"].as_str()?),
+    commands,
+    status: line["status"].as_i64()? as i32,
+    duration: line["duration"].as_f64().unwrap_or_default(),
+  })
+}
+
+/// Adds `run` to the end of the history.
+pub fn record(run: &Run) -> Result<(), String> {
+  let path = history_file().ok_or("history: no home directory")?;
+  let fail = |e: std::io::Error| format!("{}: {}", path.display(), e);
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir).map_err(fail)?;
+  }
+  let mut file = OpenOptions::new().create(true).append(true).open(&path).map_err(fail)?;
+  writeln!(file, "{}", run_json(run)).map_err(fail)
+}
+
+/// Every run recorded, oldest first. Lines that can't be read, as from a newer doit, are skipped.
+pub fn read() -> Result<Vec<Run>, String> {
+  let Some(path) = history_file().filter(|path| path.is_file()) else {
+    return Ok(vec![]);
+  };
+  let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+  Ok(text.lines().filter_map(|line| serde_json::from_str(line).ok()).filter_map(|line| json_run(&line)).collect())
+}
+
+/// The most recent run in `cwd`.
+pub fn last_in(cwd: &Path) -> Result<Option<Run>, String> {
+  Ok(read()?.into_iter().rev().find(|run| run.cwd == cwd))
+}
+
+/// The command line that would run `commands` again.
+pub fn command_line(commands: &[(String, Vec<String>)]) -> String {
+  commands
+    .iter()
+    .map(|(task, args)| std::iter::once(task).chain(args).map(String::as_str).collect::<Vec<_>>().join(" "))
+    .collect::<Vec<_>>()
+    .join(" + ")
+}
+
+/// Prints the last `count` runs, oldest first, with when, where and how each went.
+pub fn print(count: usize) -> Result<(), String> {
+  let runs = read()?;
+  for run in &runs[runs.len().saturating_sub(count)..] {
+    let when = crate::template::format_time_at(run.time, "%Y-%m-%d %H:%M:%S")?;
+    let status = match run.status {
+      0 => "ok".to_string(),
+      status => format!("exit {}", status),
+    };
+    println!("{}  {:<7} {:>8.1}s  {}  {}", when, status, run.duration, run.cwd.display(), command_line(&run.commands));
+  }
+  Ok(())
+}
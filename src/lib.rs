@@ -0,0 +1,301 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! doit's configuration loading, templates and runner, for the doit binary and for other programs that want to
+//! run doit commands themselves through [`Runner`].
+mod archive;
+mod builtins;
+mod check;
+mod cli;
+mod config;
+mod deflate;
+mod docs;
+mod dotenv;
+mod exec;
+mod history;
+mod picker;
+mod pty;
+mod reporter;
+mod sha256;
+mod show;
+mod task;
+mod template;
+#[cfg(test)]
+mod test_util;
+mod wasm;
+
+use crate::{
+  config::{all_tasks, find_task, read_doit_file, Config},
+  show::is_hidden,
+  task::primary,
+  template::render_template,
+};
+pub use cli::run_cli;
+use std::{cell::RefCell, path::Path, sync::Arc};
+use toml_edit::Item;
+
+/// Run-wide options taken from the command line, set once before anything runs.
+#[derive(Clone, Debug)]
+pub struct Settings {
+  /// How many independent dependencies may run at once.
+  pub jobs: usize,
+  /// Answer yes to every `confirm` prompt.
+  pub yes: bool,
+  /// How many times -v was given.
+  pub verbose: usize,
+  /// Print the commands that would be run instead of running them.
+  pub dry_run: bool,
+  /// Leave out the "Running command" banners.
+  pub quiet: bool,
+  /// Print how long each step took once the run is over.
+  pub timings: bool,
+  /// Whether `%sh:...%` runs its command; off with --no-sh or DOIT_NO_SH, for configs that aren't trusted.
+  pub sh_substitution: bool,
+  /// The `key=value` pairs given with --set, which templates see in place of every table's own values.
+  pub overrides: Vec<(String, String)>,
+  /// The `KEY=VALUE` pairs given with --env. They are set in doit's own environment, and kept by commands with
+  /// `env_mode = "clean"` as well.
+  pub env: Vec<(String, String)>,
+  /// The profile from --profile or DOIT_PROFILE, whose `profile.NAME` table each task merges over its own keys.
+  pub profile: Option<String>,
+}
+
+impl Default for Settings {
+  fn default() -> Self {
+    Settings {
+      jobs: 1,
+      yes: false,
+      verbose: 0,
+      dry_run: false,
+      quiet: false,
+      timings: false,
+      sh_substitution: true,
+      overrides: Vec::new(),
+      env: Vec::new(),
+      profile: None,
+    }
+  }
+}
+
+/// What a run works with: the configuration, once one is loaded, and the run-wide settings. Each [`Runner`] has
+/// its own, which it makes current on its thread while it runs, and every thread a run starts is given it too.
+#[derive(Clone, Default)]
+struct Context {
+  config: Option<Arc<Config>>,
+  settings: Arc<Settings>,
+}
+
+thread_local! {
+  static CONTEXT: RefCell<Context> = RefCell::default();
+}
+
+impl Context {
+  /// The context of the run on this thread, to hand to a thread it starts.
+  fn current() -> Context {
+    CONTEXT.with(|context| context.borrow().clone())
+  }
+
+  /// Makes this the current context for the rest of the thread, as the doit program does once it has it.
+  fn install(self) {
+    CONTEXT.with(|context| context.replace(self));
+  }
+
+  /// Runs `f` with this as the current context.
+  fn enter<T>(self, f: impl FnOnce() -> T) -> T {
+    let outer = CONTEXT.with(|context| context.replace(self));
+    let result = f();
+    CONTEXT.with(|context| context.replace(outer));
+    result
+  }
+}
+
+fn settings() -> Arc<Settings> {
+  CONTEXT.with(|context| context.borrow().settings.clone())
+}
+
+/// The configuration of the run on this thread, for builtins that run other commands and settings read from [doit].
+fn loaded_config() -> Option<Arc<Config>> {
+  CONTEXT.with(|context| context.borrow().config.clone())
+}
+
+/// Why a run failed, which also decides doit's exit status (see print_usage).
+#[derive(Debug)]
+pub enum DoitError {
+  /// Bad command line options.
+  Usage(String),
+  /// The requested command is not in the configuration.
+  NotFound(String),
+  /// A configuration file could not be read or parsed.
+  Config(String),
+  /// A template refers to something that can't be resolved.
+  Template(String),
+  /// A `&builtin` failed.
+  Builtin(String),
+  /// A command exited nonzero or timed out; `code` becomes doit's exit status.
+  Child {
+    message: String,
+    code: i32,
+  },
+  Other(String),
+}
+
+impl DoitError {
+  /// The status doit exits with for this error.
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      DoitError::Other(_) => 1,
+      DoitError::Usage(_) => 2,
+      DoitError::NotFound(_) => 3,
+      DoitError::Config(_) => 4,
+      DoitError::Template(_) => 5,
+      DoitError::Builtin(_) => 6,
+      DoitError::Child { code, .. } => *code,
+    }
+  }
+
+  /// Rewrites the message, keeping the kind of error and so the exit status.
+  fn map_message(self, f: impl FnOnce(String) -> String) -> Self {
+    match self {
+      DoitError::Usage(message) => DoitError::Usage(f(message)),
+      DoitError::NotFound(message) => DoitError::NotFound(f(message)),
+      DoitError::Config(message) => DoitError::Config(f(message)),
+      DoitError::Template(message) => DoitError::Template(f(message)),
+      DoitError::Builtin(message) => DoitError::Builtin(f(message)),
+      DoitError::Child { message, code } => DoitError::Child { message: f(message), code },
+      DoitError::Other(message) => DoitError::Other(f(message)),
+    }
+  }
+
+  /// Combines the failures of tasks run in parallel into one error with every message, exiting with the status
+  /// of the first.
+  fn join(errors: Vec<DoitError>) -> Result<(), DoitError> {
+    let mut errors = errors.into_iter();
+    match errors.next() {
+      None => Ok(()),
+      Some(first) => {
+        let rest: Vec<String> = errors.map(|e| e.to_string()).collect();
+        Err(first.map_message(|first| [first].into_iter().chain(rest).collect::<Vec<_>>().join("\n")))
+      }
+    }
+  }
+}
+
+impl std::fmt::Display for DoitError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      DoitError::Usage(message)
+      | DoitError::NotFound(message)
+      | DoitError::Config(message)
+      | DoitError::Template(message)
+      | DoitError::Builtin(message)
+      | DoitError::Child { message, .. }
+      | DoitError::Other(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl std::error::Error for DoitError {}
+
+impl From<String> for DoitError {
+  fn from(message: String) -> Self {
+    DoitError::Other(message)
+  }
+}
+
+impl From<&str> for DoitError {
+  fn from(message: &str) -> Self {
+    DoitError::Other(message.into())
+  }
+}
+
+/// Runs doit commands from another program: loads a configuration the way the doit binary does and runs its
+/// commands, renders templates against them and lists them. Each `Runner` has its own configuration and
+/// [`Settings`], so a program can load as many as it needs.
+pub struct Runner {
+  config: Arc<Config>,
+  settings: Arc<Settings>,
+}
+
+impl Runner {
+  /// Loads `path` on top of the bundled default commands (unless `defaults` is false) and the global config, to run
+  /// with `settings`. Commands run in the current directory, so change to the file's directory first if that is
+  /// what they expect.
+  pub fn load(path: impl AsRef<Path>, defaults: bool, settings: Settings) -> Result<Self, DoitError> {
+    let path = path.as_ref().display().to_string();
+    let settings = Arc::new(settings);
+    let config = Context { config: None, settings: settings.clone() }.enter(|| read_doit_file(&path, defaults))?;
+    Ok(Runner { config: Arc::new(config), settings })
+  }
+
+  /// Runs `f` with this runner's configuration and settings as the current ones.
+  fn enter<T>(&self, f: impl FnOnce(&Config) -> T) -> T {
+    Context { config: Some(self.config.clone()), settings: self.settings.clone() }.enter(|| f(&self.config))
+  }
+
+  /// The names of the commands, group members by their dotted names, leaving out hidden ones unless `all`.
+  pub fn tasks(&self, all: bool) -> Vec<String> {
+    all_tasks("", self.config.doc.as_table())
+      .into_iter()
+      .filter(|(name, table)| all || !is_hidden(name.rsplit('.').next().unwrap_or(name), table))
+      .map(|(name, _)| name)
+      .collect()
+  }
+
+  /// A command's description, if it has one.
+  pub fn description(&self, task: &str) -> Result<Option<String>, DoitError> {
+    let (table, _) = find_task(&self.config, task)?;
+    Ok(table.get("description").and_then(Item::as_str).map(String::from))
+  }
+
+  /// Renders `template` the way a step of `task` would be, so a leading `:` makes it a template.
+  pub fn render(&self, task: &str, template: &str) -> Result<String, DoitError> {
+    self.enter(|config| render_template(find_task(config, task)?.0, template))
+  }
+
+  /// Runs `task` (a name, alias or @N) with `args` as its arguments, along with its dependencies and steps.
+  pub fn run(&self, task: &str, args: &[String]) -> Result<(), DoitError> {
+    self.enter(|config| primary(config, task, args))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::TempDir;
+
+  #[test]
+  fn runners_have_their_own_config_and_settings() {
+    let dir = TempDir::new("runner");
+    let (a, b) = (dir.0.join("a.toml"), dir.0.join("b.toml"));
+    std::fs::write(&a, "[greet]\nwho = \"a\"\ncommand = [\"true\"]\n").unwrap();
+    std::fs::write(&b, "[greet]\nwho = \"b\"\ncommand = [\"true\"]\n\n[other]\ncommand = [\"true\"]\n").unwrap();
+    let overrides = Settings { overrides: vec![("who".into(), "set".into())], ..Settings::default() };
+    let (a, b, c) = (
+      Runner::load(&a, false, Settings::default()).unwrap(),
+      Runner::load(&b, false, Settings::default()).unwrap(),
+      Runner::load(&a, false, overrides).unwrap(),
+    );
+    assert_eq!(a.render("greet", ":hi %who%").unwrap(), "hi a");
+    assert_eq!(b.render("greet", ":hi %who%").unwrap(), "hi b");
+    assert_eq!(c.render("greet", ":hi %who%").unwrap(), "hi set");
+    assert!(b.tasks(false).contains(&"other".to_string()) && !a.tasks(false).contains(&"other".to_string()));
+  }
+
+  #[test]
+  fn bad_templates_and_commands_exit_with_their_codes() {
+    let dir = TempDir::new("exit-code");
+    let path = dir.0.join("doit.toml");
+    std::fs::write(&path, "[missing-arg]\ncommand = [\"echo\", \":%2%\"]\n\n[empty]\ncommand = [[]]\n").unwrap();
+    let runner = Runner::load(&path, false, Settings::default()).unwrap();
+    assert_eq!(runner.run("missing-arg", &["one".into()]).unwrap_err().exit_code(), 5);
+    assert_eq!(runner.run("empty", &[]).unwrap_err().exit_code(), 4);
+  }
+
+  #[test]
+  fn write_file_creates_missing_directories() {
+    let dir = TempDir::new("write-file");
+    let path = dir.0.join("doit.toml");
+    let toml = format!("[write]\ncwd = {:?}\ncommand = [\"&write-file\", \"out/x.txt\", \"hi\"]\n", dir.0);
+    std::fs::write(&path, toml).unwrap();
+    Runner::load(&path, false, Settings::default()).unwrap().run("write", &[]).unwrap();
+    assert_eq!(std::fs::read_to_string(dir.0.join("out/x.txt")).unwrap(), "hi\n");
+  }
+}
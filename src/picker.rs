@@ -0,0 +1,129 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! The interactive list offered when doit is run at a terminal without a command: typing filters the commands
+//! by fuzzy match, the arrow keys (or Ctrl-P/Ctrl-N) move the selection and Enter picks it.
+use std::{
+  fs::{File, OpenOptions},
+  io::{Read, Write},
+  os::unix::io::AsRawFd,
+};
+
+/// How many matches are shown at once.
+const ROWS: usize = 10;
+
+pub struct Entry {
+  pub name: String,
+  pub description: String,
+}
+
+/// Puts the terminal in raw mode for as long as it lives.
+struct RawMode {
+  tty: File,
+  saved: libc::termios,
+}
+
+impl RawMode {
+  fn new(tty: File) -> Result<Self, String> {
+    let fd = tty.as_raw_fd();
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+      return Err(std::io::Error::last_os_error().to_string());
+    }
+    let mut raw = saved;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+      return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(RawMode { tty, saved })
+  }
+}
+
+impl Drop for RawMode {
+  fn drop(&mut self) {
+    unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.saved) };
+  }
+}
+
+/// Scores `text` against `query` when every query character appears in it in order, ignoring case; lower is
+/// better, preferring matches that start early and are close together.
+fn fuzzy_score(query: &str, text: &str) -> Option<(usize, usize)> {
+  let text: Vec<char> = text.to_lowercase().chars().collect();
+  let mut start = None;
+  let mut pos = 0;
+  for wanted in query.to_lowercase().chars() {
+    pos += text[pos..].iter().position(|c| *c == wanted)?;
+    start.get_or_insert(pos);
+    pos += 1;
+  }
+  let start = start.unwrap_or(0);
+  Some((start, pos.saturating_sub(start)))
+}
+
+fn terminal_width(tty: &File) -> usize {
+  let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+  match unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } {
+    0 if size.ws_col > 0 => size.ws_col as usize,
+    _ => 80,
+  }
+}
+
+/// Shows the picker on the controlling terminal and returns the chosen entry's name, or None if it was
+/// dismissed with Esc, Ctrl-C or Ctrl-D.
+pub fn pick(entries: &[Entry]) -> Result<Option<String>, String> {
+  let tty = OpenOptions::new().read(true).write(true).open("/dev/tty").map_err(|e| format!("/dev/tty: {}", e))?;
+  let mut out = tty.try_clone().map_err(|e| e.to_string())?;
+  let mut input = tty.try_clone().map_err(|e| e.to_string())?;
+  let width = terminal_width(&tty);
+  let _raw = RawMode::new(tty)?;
+  let name_width = entries.iter().map(|entry| entry.name.len()).max().unwrap_or(0);
+
+  let mut query = String::new();
+  let mut selected = 0;
+  let mut drawn = 0;
+  let _ = write!(out, "\x1b[?25l");
+  let chosen = loop {
+    let mut matched: Vec<(_, &Entry)> =
+      entries.iter().filter_map(|entry| fuzzy_score(&query, &entry.name).map(|score| (score, entry))).collect();
+    matched.sort_by_key(|(score, _)| *score);
+    selected = selected.min(matched.len().saturating_sub(1));
+
+    // Redraw in place: back up over the previous frame and clear it.
+    let mut frame = String::new();
+    if drawn > 0 {
+      frame.push_str(&format!("\x1b[{}A", drawn));
+    }
+    frame.push_str(&format!("\r\x1b[J> {}  ({}/{})", query, matched.len(), entries.len()));
+    let first = selected.saturating_sub(ROWS - 1);
+    for (i, (_, entry)) in matched.iter().enumerate().skip(first).take(ROWS) {
+      let line: String = format!("  {:name_width$}  {}", entry.name, entry.description).chars().take(width).collect();
+      frame.push_str(&if i == selected { format!("\r\n\x1b[7m{}\x1b[0m", line) } else { format!("\r\n{}", line) });
+    }
+    drawn = matched.len().saturating_sub(first).min(ROWS);
+    let _ = out.write_all(frame.as_bytes());
+    let _ = out.flush();
+
+    let mut buf = [0u8; 16];
+    let n = input.read(&mut buf).map_err(|e| e.to_string())?;
+    match &buf[..n] {
+      [] | [3] | [4] | [27] => break None,
+      [b'\r'] | [b'\n'] => break matched.get(selected).map(|(_, entry)| entry.name.clone()),
+      [27, b'[', b'A'] | [16] => selected = selected.saturating_sub(1),
+      [27, b'[', b'B'] | [14] => selected += 1,
+      [127] | [8] => {
+        query.pop();
+      }
+      [21] => query.clear(),
+      bytes if bytes[0] != 27 => {
+        query.extend(String::from_utf8_lossy(bytes).chars().filter(|c| !c.is_control()));
+        selected = 0;
+      }
+      _ => {}
+    }
+  };
+
+  // Clear the picker away before the command's own output starts.
+  let _ = write!(out, "{}\r\x1b[J\x1b[?25h", if drawn > 0 { format!("\x1b[{}A", drawn) } else { String::new() });
+  let _ = out.flush();
+  Ok(chosen)
+}
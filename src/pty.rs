@@ -0,0 +1,155 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Pseudo-terminals for steps with `pty = true`, so commands that check isatty behave as they would at a
+//! terminal even when doit's own output is redirected. doit copies its stdin to the terminal and the terminal's
+//! output to wherever the command's stdout would have gone, and passes window size changes along.
+use std::{
+  fs::File,
+  io::{Read, Write},
+  os::unix::{
+    io::{AsRawFd, FromRawFd},
+    process::CommandExt,
+  },
+  process::Command,
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+pub struct Pty {
+  pub master: File,
+  slave: Option<File>,
+}
+
+fn last_error(what: &str) -> String {
+  format!("pty: {}: {}", what, std::io::Error::last_os_error())
+}
+
+/// The window size of whichever of doit's stdin, stdout or stderr is a terminal.
+fn window_size() -> Option<libc::winsize> {
+  let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+  [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO]
+    .into_iter()
+    .find(|fd| unsafe { libc::ioctl(*fd, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0)
+    .map(|_| size)
+}
+
+impl Pty {
+  pub fn open() -> Result<Self, String> {
+    let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if master < 0 {
+      return Err(last_error("posix_openpt"));
+    }
+    let master = unsafe { File::from_raw_fd(master) };
+    if unsafe { libc::grantpt(master.as_raw_fd()) } != 0 || unsafe { libc::unlockpt(master.as_raw_fd()) } != 0 {
+      return Err(last_error("unlockpt"));
+    }
+    let mut name = [0 as libc::c_char; 128];
+    if unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) } != 0 {
+      return Err(last_error("ptsname"));
+    }
+    let slave = unsafe { libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if slave < 0 {
+      return Err(last_error("open"));
+    }
+    let slave = unsafe { File::from_raw_fd(slave) };
+
+    let size = window_size().unwrap_or(libc::winsize { ws_row: 24, ws_col: 80, ws_xpixel: 0, ws_ypixel: 0 });
+    unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+    // Output going to a file or pipe shouldn't end up with the terminal's \r\n line endings.
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 0 {
+      let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+      if unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut termios) } == 0 {
+        termios.c_oflag &= !libc::ONLCR;
+        unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) };
+      }
+    }
+    Ok(Pty { master, slave: Some(slave) })
+  }
+
+  /// Gives `command` the terminal as its stdin, stdout and (unless it goes elsewhere) stderr, and makes it the
+  /// controlling terminal of a new session for it. The terminal can only be attached once: the master only
+  /// sees the end of the output once the command's end is all that is left open.
+  pub fn attach(&mut self, command: &mut Command, stderr: bool) -> Result<(), String> {
+    let slave = self.slave.take().ok_or("pty: already attached")?;
+    let clone = || slave.try_clone().map_err(|e| format!("pty: {}", e));
+    command.stdin(clone()?).stdout(clone()?);
+    if stderr {
+      command.stderr(clone()?);
+    }
+    unsafe {
+      command.pre_exec(|| {
+        if libc::setsid() < 0 || libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) < 0 {
+          return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+      });
+    }
+    Ok(())
+  }
+}
+
+/// Puts doit's terminal in raw mode for as long as it lives, so keys (Ctrl-C included) go to the command.
+struct RawMode(libc::termios);
+
+impl RawMode {
+  fn new() -> Option<Self> {
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+      return None;
+    }
+    let mut raw = saved;
+    unsafe { libc::cfmakeraw(&mut raw) };
+    // Output processing stays on, so doit's own newlines still return the cursor.
+    raw.c_oflag = saved.c_oflag;
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) };
+    Some(RawMode(saved))
+  }
+}
+
+impl Drop for RawMode {
+  fn drop(&mut self) {
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+  }
+}
+
+/// Copies doit's stdin to the terminal until `done` is set, passing on window size changes as it goes. Once stdin
+/// ends the command is sent the end-of-file character.
+pub fn proxy_input(mut master: File, done: &AtomicBool) {
+  let _raw = RawMode::new();
+  let mut size = window_size();
+  let mut open = true;
+  let mut buf = [0u8; 4096];
+  while !done.load(Ordering::SeqCst) {
+    let mut poll = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    let ready = open && unsafe { libc::poll(&mut poll, 1, 100) } > 0;
+    if !open {
+      std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    if ready {
+      match unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) } {
+        n if n > 0 => {
+          let _ = master.write_all(&buf[..n as usize]);
+        }
+        _ => {
+          open = false;
+          let _ = master.write_all(b"\x04");
+        }
+      }
+    }
+    let now = window_size();
+    if let Some(now) = now.filter(|now| size.is_none_or(|size| (size.ws_row, size.ws_col) != (now.ws_row, now.ws_col)))
+    {
+      unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &now) };
+      size = Some(now);
+    }
+  }
+}
+
+/// Copies the terminal's output as it comes, without waiting for whole lines, until the command is gone.
+pub fn copy_output(mut master: impl Read, out: &mut dyn Write) {
+  let mut buf = [0u8; 4096];
+  // Reading fails with EIO rather than returning 0 once the command's end of the terminal is closed.
+  while let Ok(n @ 1..) = master.read(&mut buf) {
+    if out.write_all(&buf[..n]).and_then(|_| out.flush()).is_err() {
+      break;
+    }
+  }
+}
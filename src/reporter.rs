@@ -0,0 +1,134 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Everything doit prints about what it is doing, as opposed to the output of the commands it runs, goes through
+//! here so that it is prefixed on --jobs workers and colored by level.
+use crate::settings;
+use std::{
+  cell::RefCell,
+  env,
+  io::{BufRead, BufReader, Read},
+  sync::atomic::{AtomicBool, Ordering},
+};
+use toml_edit::{Item, TableLike};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+  /// Set while a task runs on a --jobs worker so its output can be told apart.
+  static TASK_PREFIX: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Level {
+  /// The "Running command" lines.
+  Banner,
+  Info,
+  /// Retries, skipped work and the like.
+  Warning,
+  Error,
+  /// -v output, and the settings or trace lines under another line.
+  Detail,
+}
+
+impl Level {
+  fn color(self) -> &'static str {
+    match self {
+      Level::Banner => "\x1b[1;36m",
+      Level::Info => "",
+      Level::Warning => "\x1b[33m",
+      Level::Error => "\x1b[1;31m",
+      Level::Detail => "\x1b[2m",
+    }
+  }
+}
+
+/// Applies `--color`: `always`, `never`, or `auto` to color only when stdout is a terminal and NO_COLOR is unset.
+pub fn set_color(choice: &str) -> Result<(), String> {
+  let color = match choice {
+    "always" => true,
+    "never" => false,
+    "auto" => {
+      let tty = unsafe { libc::isatty(libc::STDOUT_FILENO) } != 0;
+      tty && env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty())
+    }
+    _ => return Err(format!("--color must be auto, always or never, not {:?}", choice)),
+  };
+  COLOR.store(color, Ordering::Relaxed);
+  Ok(())
+}
+
+fn paint(color: &str, text: &str) -> String {
+  if color.is_empty() || !COLOR.load(Ordering::Relaxed) {
+    text.to_string()
+  } else {
+    format!("{}{}\x1b[0m", color, text)
+  }
+}
+
+fn prefixed(prefix: &str, line: &str) -> String {
+  format!("{} {}", paint("\x1b[35m", &format!("[{}]", prefix)), line)
+}
+
+pub fn task_prefix() -> Option<String> {
+  TASK_PREFIX.with(|prefix| prefix.borrow().clone())
+}
+
+pub fn set_task_prefix(prefix: Option<String>) {
+  TASK_PREFIX.with(|task_prefix| *task_prefix.borrow_mut() = prefix);
+}
+
+pub fn report(level: Level, line: &str) {
+  let line = paint(level.color(), line);
+  match task_prefix() {
+    Some(prefix) => println!("{}", prefixed(&prefix, &line)),
+    None => println!("{}", line),
+  }
+}
+
+pub fn info(line: &str) {
+  report(Level::Info, line);
+}
+
+pub fn warning(line: &str) {
+  report(Level::Warning, line);
+}
+
+pub fn error(line: &str) {
+  report(Level::Error, line);
+}
+
+pub fn detail(line: &str) {
+  report(Level::Detail, line);
+}
+
+pub fn verbose(line: &str) {
+  if settings().verbose > 0 {
+    detail(line);
+  }
+}
+
+/// Prints a "Running command" style status line, unless --quiet or the task's (or step's) `quiet = true` asks for
+/// only the commands' own output.
+pub fn banner(table: &dyn TableLike, line: &str) {
+  if !settings().quiet && !table.get("quiet").and_then(Item::as_bool).unwrap_or(false) {
+    report(Level::Banner, line);
+  }
+}
+
+/// Copies a command's output line by line, each prefixed with the task it belongs to.
+pub fn prefix_lines(reader: impl Read, prefix: &str, to_stderr: bool) {
+  let mut reader = BufReader::new(reader);
+  let mut buf = Vec::new();
+  while let Ok(n) = reader.read_until(b'\n', &mut buf) {
+    if n == 0 {
+      break;
+    }
+    let line = String::from_utf8_lossy(&buf);
+    let line = prefixed(prefix, line.trim_end_matches(['\n', '\r']));
+    if to_stderr {
+      eprintln!("{}", line);
+    } else {
+      println!("{}", line);
+    }
+    buf.clear();
+  }
+}
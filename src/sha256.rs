@@ -0,0 +1,158 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! SHA-256, just enough of it for &download to check what it fetched.
+use std::{fs::File, io::Read, path::Path};
+
+const K: [u32; 64] = [
+  0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98,
+  0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+  0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8,
+  0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+  0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819,
+  0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+  0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+  0xc67178f2,
+];
+
+struct Sha256 {
+  state: [u32; 8],
+  block: Vec<u8>,
+  len: u64,
+}
+
+impl Sha256 {
+  fn new() -> Self {
+    Sha256 {
+      state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+      block: Vec::with_capacity(64),
+      len: 0,
+    }
+  }
+
+  fn update(&mut self, mut data: &[u8]) {
+    self.len += data.len() as u64;
+    while !data.is_empty() {
+      let take = (64 - self.block.len()).min(data.len());
+      self.block.extend_from_slice(&data[..take]);
+      data = &data[take..];
+      if self.block.len() == 64 {
+        self.compress();
+      }
+    }
+  }
+
+  fn compress(&mut self) {
+    let mut w = [0u32; 64];
+    for (i, word) in self.block.chunks(4).enumerate() {
+      w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+    for i in 0..64 {
+      let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+      let ch = (e & f) ^ (!e & g);
+      let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+      let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+      let maj = (a & b) ^ (a & c) ^ (b & c);
+      let t2 = s0.wrapping_add(maj);
+      h = g;
+      g = f;
+      f = e;
+      e = d.wrapping_add(t1);
+      d = c;
+      c = b;
+      b = a;
+      a = t1.wrapping_add(t2);
+    }
+    for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+      *state = state.wrapping_add(value);
+    }
+    self.block.clear();
+  }
+
+  fn hex_digest(mut self) -> String {
+    let bits = self.len.wrapping_mul(8);
+    self.block.push(0x80);
+    if self.block.len() > 56 {
+      self.block.resize(64, 0);
+      self.compress();
+    }
+    self.block.resize(56, 0);
+    self.block.extend_from_slice(&bits.to_be_bytes());
+    self.compress();
+    self.state.iter().map(|word| format!("{:08x}", word)).collect()
+  }
+}
+
+/// The lowercase hex SHA-256 of a file's contents.
+pub fn file_digest(path: &Path) -> std::io::Result<String> {
+  let mut file = File::open(path)?;
+  let mut hasher = Sha256::new();
+  let mut buf = vec![0u8; 64 * 1024];
+  loop {
+    let n = file.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    hasher.update(&buf[..n]);
+  }
+  Ok(hasher.hex_digest())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn digest(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.hex_digest()
+  }
+
+  #[test]
+  fn known_answers() {
+    assert_eq!(digest(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(digest(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(
+      digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+      "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+    assert_eq!(digest(&vec![b'a'; 1_000_000]), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+  }
+
+  #[test]
+  fn padding_at_block_boundaries() {
+    let expected = [
+      (55, "d5e285683cd4efc02d021a5c62014694958901005d6f71e89e0989fac77e4072"),
+      (56, "04c26261370ee7541549d16dee320c723e3fd14671e66a099afe0a377c16888e"),
+      (63, "75220b47218278e656f2013bb8f0c455a25eaf01e86c64924e9d48d89776d6f2"),
+      (64, "7ce100971f64e7001e8fe5a51973ecdfe1ced42befe7ee8d5fd6219506b5393c"),
+      (65, "9537c5fdf120482f7d58d25e9ed583f52c02b4e304ea814db1633ad565aed7e9"),
+    ];
+    for (len, sha256) in expected {
+      assert_eq!(digest(&vec![b'x'; len]), sha256, "{} bytes", len);
+    }
+  }
+
+  #[test]
+  fn updates_in_pieces() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    let mut hasher = Sha256::new();
+    for piece in data.chunks(37) {
+      hasher.update(piece);
+    }
+    assert_eq!(hasher.hex_digest(), digest(&data));
+  }
+
+  #[test]
+  fn file_digests() {
+    let path = std::env::temp_dir().join(format!("doit-sha256-test-{}", std::process::id()));
+    std::fs::write(&path, b"abc").unwrap();
+    let sha256 = file_digest(&path);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(sha256.unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+  }
+}
@@ -0,0 +1,477 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Listing commands for --cmds and describing them for --show, --why and --print-config.
+use crate::{
+  config::{
+    all_tasks, find_task, get_aliases, get_section, group_members, is_group, source_location, Config, SETTINGS_SECTION,
+  },
+  docs, picker, settings,
+  task::{apply_overlays, get_command, get_params, get_profiles, load_dotenv, with_dotenv},
+  template::{literal_value, render_template, PREVIEWING},
+  Context, DoitError, Settings,
+};
+use std::sync::Arc;
+use toml_edit::{Array, DocumentMut, Item, Table, Value};
+
+/// Helper tasks meant only for `pre`/`post`/`depends` are marked `hidden = true` or named with a leading `_`.
+pub fn is_hidden(cmd_name: &str, table: &Table) -> bool {
+  cmd_name.starts_with('_') || table.get("hidden").and_then(|hidden| hidden.as_bool()).unwrap_or(false)
+}
+
+fn format_cmd_name(cmd: &str, table: Option<&Table>) -> String {
+  match table.map(get_aliases).filter(|aliases| !aliases.is_empty()) {
+    Some(aliases) => format!("{} ({})", cmd, aliases.join(", ")),
+    None => cmd.to_string(),
+  }
+}
+
+/// Prints the members of a group under it, indented by nesting depth.
+pub fn list_group(prefix: &str, group: &Table, indent: usize, all: bool) {
+  for (key, member) in group_members(group) {
+    let name = format!("{}.{}", prefix, key);
+    if !all && is_hidden(key, member) {
+      continue;
+    }
+    println!("{:indent$}{}", "", format_cmd_name(&name, Some(member)), indent = indent);
+    if is_group(member) {
+      list_group(&name, member, indent + 2, all);
+    }
+  }
+}
+
+/// How --cmds and --show print: for people, as JSON for editors and other tools, or (--cmds only) as bare
+/// names for shell completion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+  Text,
+  Json,
+  Plain,
+}
+
+pub fn toml_json(value: &Value) -> serde_json::Value {
+  match value {
+    Value::String(s) => s.value().as_str().into(),
+    Value::Integer(n) => (*n.value()).into(),
+    Value::Float(n) => (*n.value()).into(),
+    Value::Boolean(b) => (*b.value()).into(),
+    Value::Datetime(date) => date.value().to_string().into(),
+    Value::Array(array) => array.iter().map(toml_json).collect(),
+    Value::InlineTable(table) => table.iter().map(|(key, value)| (key.to_string(), toml_json(value))).collect(),
+  }
+}
+
+/// The metadata --format json gives for a command: `key` is its own name within its group, for is_hidden.
+fn task_json(name: &str, key: &str, index: Option<usize>, table: &Table) -> serde_json::Value {
+  serde_json::json!({
+    "name": name,
+    "index": index,
+    "description": table.get("description").and_then(Item::as_str),
+    "args": table.get("args").and_then(Item::as_value).map(toml_json),
+    "aliases": get_aliases(table),
+    "tags": get_tags(table),
+    "hidden": is_hidden(key, table),
+    "group": is_group(table),
+  })
+}
+
+/// Adds the members of a group, and theirs, to a --format json listing.
+fn list_group_json(prefix: &str, group: &Table, all: bool, tasks: &mut Vec<serde_json::Value>) {
+  for (key, member) in group_members(group) {
+    let name = format!("{}.{}", prefix, key);
+    if !all && is_hidden(key, member) {
+      continue;
+    }
+    tasks.push(task_json(&name, key, None, member));
+    if is_group(member) {
+      list_group_json(&name, member, all, tasks);
+    }
+  }
+}
+
+/// The tags a command has; anything that isn't a string is left for --check to point out.
+pub fn get_tags(table: &Table) -> Vec<&str> {
+  table.get("tags").and_then(Item::as_array).map_or(vec![], |tags| tags.iter().filter_map(Value::as_str).collect())
+}
+
+/// Whether a command has every one of `tags`.
+fn has_tags(table: &Table, tags: &[String]) -> bool {
+  let own = get_tags(table);
+  tags.iter().all(|tag| own.contains(&tag.as_str()))
+}
+
+/// The commands that aren't hidden and have every one of `tags`, for `--tag ... --all` to run.
+pub fn tagged_tasks(config: &Config, tags: &[String]) -> Vec<String> {
+  all_tasks("", config.doc.as_table())
+    .into_iter()
+    .filter(|(name, table)| !is_hidden(name.rsplit('.').next().unwrap_or(name), table) && has_tags(table, tags))
+    .map(|(name, _)| name)
+    .collect()
+}
+
+/// Lists the commands for --cmds; with `tags`, only the ones having all of them.
+pub fn list_cmds(config: &Config, all: bool, format: Format, tags: &[String]) -> Result<(), String> {
+  if format == Format::Plain {
+    for (name, table) in all_tasks("", config.doc.as_table()) {
+      if (all || !is_hidden(name.rsplit('.').next().unwrap_or(&name), table)) && has_tags(table, tags) {
+        println!("{}", name);
+        get_aliases(table).iter().for_each(|alias| println!("{}", alias));
+      }
+    }
+    return Ok(());
+  }
+  if format == Format::Json {
+    let mut tasks = Vec::new();
+    for (i, (cmd, section)) in config.doc.as_table().iter().enumerate() {
+      let Some(table) = section.as_table().filter(|table| all || !is_hidden(cmd, table)) else {
+        continue;
+      };
+      tasks.push(task_json(cmd, cmd, Some(i + 1), table));
+      if is_group(table) {
+        list_group_json(cmd, table, all, &mut tasks);
+      }
+    }
+    tasks.retain(|task| {
+      tags.iter().all(|tag| task["tags"].as_array().is_some_and(|own| own.contains(&tag.as_str().into())))
+    });
+    println!("{}", serde_json::to_string_pretty(&tasks).map_err(|e| e.to_string())?);
+    return Ok(());
+  }
+
+  // Tagged commands are listed flat, whether or not they are in groups, top-level ones by their @N.
+  if !tags.is_empty() {
+    for (name, table) in all_tasks("", config.doc.as_table()) {
+      if !all && is_hidden(name.rsplit('.').next().unwrap_or(&name), table) || !has_tags(table, tags) {
+        continue;
+      }
+      let index = config.doc.as_table().iter().position(|(cmd, _)| cmd == name);
+      let label = index.map_or_else(String::new, |i| format!("@{} : ", i + 1));
+      println!("{:>6}{}", label, format_cmd_name(&name, Some(table)));
+    }
+    return Ok(());
+  }
+
+  // Hidden commands are skipped rather than filtered out first so the @N indices stay valid.
+  for (i, (cmd, section)) in config.doc.as_table().iter().enumerate() {
+    if !all && section.as_table().is_some_and(|table| is_hidden(cmd, table)) {
+      continue;
+    }
+    let label = format!("@{} : ", i + 1);
+    let replaces = if config.shadowed.iter().any(|key| key == cmd) { " [replaces a default]" } else { "" };
+    println!("{}{}{}", label, format_cmd_name(cmd, section.as_table()), replaces);
+    if let Some(group) = section.as_table().filter(|table| is_group(table)) {
+      list_group(cmd, group, label.len() + 2, all);
+    }
+  }
+  Ok(())
+}
+
+/// Offers the commands --cmds would list in the interactive picker.
+pub fn pick_cmd(config: &Config) -> Result<Option<String>, String> {
+  let entries: Vec<picker::Entry> = all_tasks("", config.doc.as_table())
+    .into_iter()
+    .filter(|(name, table)| !is_hidden(name.rsplit('.').next().unwrap_or(name), table))
+    .map(|(name, table)| picker::Entry {
+      description: table.get("description").and_then(Item::as_str).unwrap_or_default().to_string(),
+      name,
+    })
+    .collect();
+  picker::pick(&entries)
+}
+
+/// Explains for --why where a command's definition came from: the file it is in effect from, the definitions it
+/// replaced and the names it can be run by.
+pub fn explain_task(config: &Config, name: &str) -> Result<(), DoitError> {
+  let (table, actual) = find_task(config, name)?;
+  let top = actual.split('.').next().unwrap_or(&actual);
+  let default = || "the bundled default commands".to_string();
+  let mut files: Vec<String> =
+    config.origins.get(top).into_iter().flatten().map(|path| path.display().to_string()).collect();
+  if files.is_empty() || config.shadowed.iter().any(|key| key == top) {
+    files.insert(0, default());
+  }
+  let defined = files.pop().unwrap_or_else(default);
+  println!("{}", actual);
+  if name != actual {
+    println!("  given as:    {}", name);
+  }
+  match source_location(config, &actual, None) {
+    Some((Some(_), line, _)) => println!("  defined in:  {}:{}", defined, line + 1),
+    _ => println!("  defined in:  {}", defined),
+  }
+  if top != actual {
+    println!("  as part of:  the {} group", top);
+  }
+  for file in files.iter().rev() {
+    println!("  overrides:   {}", file);
+  }
+  let aliases = get_aliases(table);
+  if !aliases.is_empty() {
+    println!("  aliases:     {}", aliases.join(", "));
+  }
+  if let Some(index) = config.doc.as_table().iter().position(|(key, _)| key == actual) {
+    println!("  index:       @{}", index + 1);
+  }
+  Ok(())
+}
+
+/// Prints the configuration doit ended up with for --print-config, each section under a comment naming the file
+/// it came from.
+pub fn print_config(config: &Config) {
+  let print = |key: &str, item: &Item, origin: String| {
+    let mut doc = DocumentMut::new();
+    doc.insert(key, item.clone());
+    println!("# {}\n{}", origin, doc.to_string().trim());
+    println!();
+  };
+  if !config.settings.is_empty() {
+    print(
+      SETTINGS_SECTION,
+      &Item::Table(config.settings.clone()),
+      "doit's settings, merged from every file that sets them".into(),
+    );
+  }
+  for (key, item) in config.doc.iter() {
+    let origin = match config.origin(key) {
+      Some(path) => format!("from {}", path.display()),
+      None => "from the bundled default commands".into(),
+    };
+    print(key, item, origin);
+  }
+}
+
+/// What a command would run with its parameter defaults and the current environment, for --show. Parameters
+/// without a default show as `<name>`; scripts aren't previewed. It is rendered as --dry-run would, with
+/// `%sh:...%` and the git placeholders left as they are, so that showing a command runs nothing.
+fn preview_command(cmd_name: &str, table: &Table) -> Result<Option<String>, DoitError> {
+  if table.contains_key("script") {
+    return Ok(None);
+  }
+  let merged = apply_overlays(cmd_name, table)?;
+  let table = merged.as_ref().unwrap_or(table);
+  let context =
+    Context { settings: Arc::new(Settings { dry_run: true, ..(*settings()).clone() }), ..Context::current() };
+  context.enter(|| {
+    let outer = PREVIEWING.with(|previewing| previewing.replace(true));
+    let preview = load_dotenv(cmd_name, table).and_then(|vars| with_dotenv(vars, || render_preview(cmd_name, table)));
+    PREVIEWING.with(|previewing| previewing.set(outer));
+    preview
+  })
+}
+
+fn render_preview(cmd_name: &str, table: &Table) -> Result<Option<String>, DoitError> {
+  let mut table = table.clone();
+  for param in get_params(cmd_name, &table)? {
+    let value = param.default.unwrap_or_else(|| format!("<{}>", param.name));
+    table.insert(&format!("arg:{}", param.name), literal_value(&value));
+  }
+  let render_argv = |argv: &Array| -> Result<Vec<String>, DoitError> {
+    argv.iter().map(|arg| render_template(&table, arg.as_str().unwrap_or_default())).collect()
+  };
+  let preview = match get_command(cmd_name, &table)? {
+    Value::String(command) => render_template(&table, command.value())?,
+    Value::Array(stages) if stages.iter().all(Value::is_array) => stages
+      .iter()
+      .filter_map(Value::as_array)
+      .map(|stage| render_argv(stage).map(|argv| format!("{:?}", argv)))
+      .collect::<Result<Vec<_>, _>>()?
+      .join(" | "),
+    Value::Array(argv) => format!("{:?}", render_argv(argv)?),
+    _ => return Ok(None),
+  };
+  Ok(Some(preview))
+}
+
+/// What --show --format json says about a command, `given` being the name or alias it was asked for by.
+fn details_json(config: &Config, given: &str, actual_cmd: &str, table: &Table) -> Result<serde_json::Value, DoitError> {
+  let key = actual_cmd.rsplit('.').next().unwrap_or(actual_cmd);
+  let index = config.doc.as_table().iter().position(|(cmd, _)| cmd == actual_cmd).map(|i| i + 1);
+  let mut details = task_json(actual_cmd, key, index, table);
+  details["given"] = given.into();
+  details["command"] = table.get("command").and_then(Item::as_value).map(toml_json).into();
+  details["script"] = table.get("script").and_then(Item::as_str).into();
+  let doc = docs::task_doc(actual_cmd, table)?;
+  details["help"] = doc.help.into();
+  details["params"] = doc
+    .params
+    .iter()
+    .map(|param| serde_json::json!({"name": param.name, "default": param.default, "description": param.description}))
+    .collect();
+  details["needs_env"] = doc.needs_env.into();
+  details["sets_env"] = doc.sets_env.into();
+  details["profiles"] = get_profiles(table).into();
+  details["examples"] = doc
+    .examples
+    .iter()
+    .map(|(command, description)| serde_json::json!({"command": command, "description": description}))
+    .collect();
+  details["preview"] = preview_command(actual_cmd, table).unwrap_or_else(|e| Some(format!("error: {}", e))).into();
+  Ok(details)
+}
+
+/// --show without a command: every command in a block of its own, or with --format json all of their details.
+pub fn show_all(config: &Config, all: bool, format: Format, tags: &[String]) -> Result<(), DoitError> {
+  let tasks: Vec<(String, &Table)> = all_tasks("", config.doc.as_table())
+    .into_iter()
+    .filter(|(name, table)| {
+      (all || !is_hidden(name.rsplit('.').next().unwrap_or(name), table)) && has_tags(table, tags)
+    })
+    .collect();
+  if format == Format::Json {
+    let details =
+      tasks.iter().map(|(name, table)| details_json(config, name, name, table)).collect::<Result<Vec<_>, _>>()?;
+    println!("{}", serde_json::to_string_pretty(&details).map_err(|e| e.to_string())?);
+    return Ok(());
+  }
+  for (name, table) in tasks {
+    let doc = docs::task_doc(&name, table)?;
+    let mut block = match doc.description {
+      Some(description) => format!("{}: {}\n", name, description),
+      None => format!("{}\n", name),
+    };
+    let runs = preview_command(&name, table).ok().flatten().or(doc.command);
+    if let Some(runs) = runs {
+      block.push_str(&format!("  runs: {}\n", runs.lines().next().unwrap_or_default()));
+    }
+    if !doc.params.is_empty() {
+      let params: Vec<String> = doc
+        .params
+        .iter()
+        .map(|param| match &param.default {
+          Some(default) => format!("{}={}", param.name, default),
+          None => format!("{} (required)", param.name),
+        })
+        .collect();
+      block.push_str(&format!("  params: {}\n", params.join(", ")));
+    }
+    for (label, items) in [
+      ("aliases", doc.aliases),
+      ("tags", doc.tags),
+      ("depends", doc.depends),
+      ("needs env", doc.needs_env.iter().map(String::as_str).collect()),
+      ("sets env", doc.sets_env),
+    ] {
+      if !items.is_empty() {
+        block.push_str(&format!("  {}: {}\n", label, items.join(", ")));
+      }
+    }
+    println!("{}", block);
+  }
+  Ok(())
+}
+
+pub fn show_details(config: &Config, cmd_name: &str, format: Format) -> Result<(), DoitError> {
+  let mut errors = Vec::<String>::new();
+  match get_section(config, cmd_name) {
+    Ok((Some(table), actual_cmd)) if format == Format::Json => {
+      let details = details_json(config, cmd_name, &actual_cmd, table)?;
+      println!("{}", serde_json::to_string_pretty(&details).map_err(|e| e.to_string())?);
+    }
+    Ok((Some(table), actual_cmd)) => {
+      let command = match table.get("script") {
+        Some(script) => script.to_string(),
+        None => get_command(cmd_name, table)?.to_string(),
+      };
+
+      let description = table
+        .get("description")
+        .ok_or_else(|| "No description provided".to_string())
+        .and_then(|x| x.as_str().ok_or_else(|| "description must be a string".to_string()))?;
+
+      let args = if table.contains_key("args") {
+        let toml_args = table["args"].as_array();
+        toml_args
+          .iter()
+          .map(|arg| match render_template(table, &arg.to_string()) {
+            Ok(s) => s,
+            Err(e) => {
+              errors.push(e.to_string());
+              "????".into()
+            }
+          })
+          .collect::<Vec<_>>()
+          .join(" ")
+      } else {
+        String::default()
+      };
+      let tags = match get_tags(table).join(", ") {
+        tags if tags.is_empty() => tags,
+        tags => format!("Tags: {}\n", tags),
+      };
+      let mut more = String::new();
+      let doc = docs::task_doc(&actual_cmd, table)?;
+      if let Some(help) = doc.help {
+        more.push_str(&format!(
+          "Help:\n{}\n",
+          help.trim().lines().map(|line| format!("  {}", line).trim_end().to_string()).collect::<Vec<_>>().join("\n")
+        ));
+      }
+      if !doc.params.is_empty() {
+        more.push_str("Parameters:\n");
+        for param in &doc.params {
+          let default =
+            param.default.as_ref().map_or_else(|| "required".to_string(), |value| format!("default {}", value));
+          let description = param.description.as_ref().map_or_else(String::new, |text| format!(": {}", text));
+          more.push_str(&format!("  {}=<value> ({}){}\n", param.name, default, description));
+        }
+      }
+      if !doc.needs_env.is_empty() {
+        more.push_str(&format!("Needs in the environment: {}\n", doc.needs_env.join(", ")));
+      }
+      if !doc.sets_env.is_empty() {
+        more.push_str(&format!("Sets in the environment: {}\n", doc.sets_env.join(", ")));
+      }
+      let profiles = get_profiles(table);
+      if !profiles.is_empty() {
+        more.push_str(&format!("Profiles: {}\n", profiles.join(", ")));
+      }
+      if !doc.examples.is_empty() {
+        more.push_str("Examples:\n");
+        for (command, description) in &doc.examples {
+          match description {
+            Some(description) => more.push_str(&format!("  {}  # {}\n", command, description)),
+            None => more.push_str(&format!("  {}\n", command)),
+          }
+        }
+      }
+      match preview_command(cmd_name, table) {
+        Ok(Some(preview)) => more.push_str(&format!("Preview: {}\n", preview)),
+        Ok(None) => {}
+        Err(e) => errors.push(e.to_string()),
+      }
+      println!(
+        "Given: {}\nActual: {}\nCommand: {}\nArguments:{}\n{}Description: {}\n{}",
+        cmd_name, actual_cmd, command, args, tags, description, more
+      );
+    }
+    Ok((None, _)) => return Err(DoitError::NotFound(format!("Command {} not found", cmd_name))),
+    Err(e) => return Err(e.map_message(|e| format!("Command {} not found: {}", cmd_name, e))),
+  }
+  if !errors.is_empty() {
+    Err(DoitError::Template(errors.join("\n")))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn preview(toml: &str) -> Option<String> {
+    let doc: DocumentMut = toml.parse().unwrap();
+    preview_command("task", doc["task"].as_table().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn previews_run_nothing() {
+    let marker = std::env::temp_dir().join(format!("doit-preview-test-{}", std::process::id()));
+    let shown =
+      preview(&format!("[task]\ncommand = [\"echo\", \":%sh:touch {}% %git:sha% %capture:out%\"]", marker.display()));
+    assert_eq!(shown, Some(format!("[\"echo\", \"%sh:touch {}% %git:sha% <captured out>\"]", marker.display())));
+    assert!(!marker.exists());
+    assert_eq!(
+      preview("[task]\nwho = \"me\"\ncommand = \":echo %who% %git:branch%\""),
+      Some("echo me %git:branch%".into())
+    );
+    assert_eq!(preview("[task]\nscript = \"touch x\""), None);
+  }
+}
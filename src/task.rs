@@ -0,0 +1,1239 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Running tasks: their steps, dependencies, matrices, parameters and how far a failed run got.
+use crate::{
+  config::{
+    all_tasks, config_dir, doit_dir, find_task, get_section, get_string_array, is_group, section_index,
+    with_source_context, Config, SECTION_RANGE_RE,
+  },
+  dotenv,
+  exec::{
+    check_cancelled, get_duration_setting, run_argv, run_argv_pipeline, run_options, run_script, run_shell,
+    start_background, stop_background, BACKGROUND, CANCELLED, IN_FINALLY, PROMPTING,
+  },
+  loaded_config, reporter, settings,
+  show::{is_hidden, list_group},
+  template::{literal_value, render_template},
+  Context, DoitError,
+};
+use glob::glob;
+use regex::Regex;
+use std::{
+  cell::RefCell,
+  collections::{HashMap, HashSet},
+  env,
+  fs::{write as write_file, File},
+  io::Write,
+  os::unix::io::AsRawFd,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Condvar, Mutex,
+  },
+  thread,
+  time::{Duration, Instant, SystemTime},
+};
+use toml_edit::{Item, Table, TableLike, Value};
+
+/// The step being run on this thread and, once it has failed by exiting nonzero, its exit code; read by the
+/// `on_failure` handlers as `%failed_step%` and `%exit_code%`.
+#[derive(Clone, Debug, Default)]
+pub struct StepStatus {
+  step: String,
+  pub exit_code: Option<i32>,
+}
+
+thread_local! {
+  pub static STEP_STATUS: RefCell<StepStatus> = RefCell::new(StepStatus::default());
+}
+
+fn begin_step(step: String) {
+  STEP_STATUS.with(|status| *status.borrow_mut() = StepStatus { step, exit_code: None });
+}
+
+/// How long one step took, for the --timings summary.
+struct Timing {
+  task: String,
+  step: String,
+  duration: Duration,
+  ok: bool,
+}
+
+static TIMINGS: Mutex<Vec<Timing>> = Mutex::new(Vec::new());
+
+/// Runs one step of a task, recording how long it took when --timings is on.
+fn run_timed_step(cmd_name: &str, step: String, run: impl FnOnce() -> Result<(), DoitError>) -> Result<(), DoitError> {
+  begin_step(step.clone());
+  if !settings().timings {
+    return run();
+  }
+  let start = Instant::now();
+  let result = run();
+  let timing = Timing { task: cmd_name.into(), step, duration: start.elapsed(), ok: result.is_ok() };
+  TIMINGS.lock().unwrap().push(timing);
+  result
+}
+
+pub fn print_timings() {
+  let timings = TIMINGS.lock().unwrap();
+  if timings.is_empty() {
+    return;
+  }
+  let width = |column: &dyn Fn(&Timing) -> usize| timings.iter().map(column).max().unwrap_or(0);
+  let (task_width, step_width) = (width(&|t| t.task.len()).max(4), width(&|t| t.step.len()).max(4));
+  let header = format!("{:task_width$}  {:step_width$}  {:>10}  status", "task", "step", "time");
+  reporter::report(reporter::Level::Banner, &header);
+  for timing in timings.iter() {
+    let line = format!(
+      "{:task_width$}  {:step_width$}  {:>10}  {}",
+      timing.task,
+      timing.step,
+      format!("{:.2?}", timing.duration),
+      if timing.ok { "ok" } else { "failed" }
+    );
+    if timing.ok {
+      reporter::info(&line);
+    } else {
+      reporter::error(&line);
+    }
+  }
+  let total: Duration = timings.iter().map(|timing| timing.duration).sum();
+  reporter::info(&format!("{:task_width$}  {:step_width$}  {:>10}", "total", "", format!("{:.2?}", total)));
+}
+
+/// Runs a `command` value, either an argv array or a string for the shell.
+fn run_step(
+  command: &Value,
+  which: &str,
+  cmd_name: &str,
+  table: &Table,
+  index: usize,
+  args: &[String],
+  step: Option<&dyn TableLike>,
+) -> Result<(), DoitError> {
+  let options = run_options(cmd_name, table, step)?;
+  match (command.as_array(), command.as_str(), &options.shell) {
+    (Some(stages), _, _) if stages.get(0).is_some_and(Value::is_array) => {
+      run_argv_pipeline(stages, which, table, index, args, &options)
+    }
+    (Some(argv), _, _) => run_argv(argv, which, table, index, args, &options),
+    (None, Some(script), Some(shell)) => run_shell(script, cmd_name, table, args, shell, &options),
+    (None, Some(_), None) => {
+      Err(format!("{}[{}]: command is a string; set shell = true to run it through the shell", which, index).into())
+    }
+    _ => Err(format!("{}[{}]: command is not an array or string", which, index).into()),
+  }
+}
+
+fn process_pre_post_cmd(which: &str, cmd_name: &str, table: &Table) -> Result<(), DoitError> {
+  let sub_args = match table[which].as_array() {
+    Some(args) => args,
+    None => {
+      return Err(format!("{} is not an array", which).into());
+    }
+  };
+
+  for (index, args_in) in sub_args.iter().enumerate() {
+    if let Some(step) = args_in.as_inline_table() {
+      if !platform_matches(&format!("{}[{}]", which, index), step)? {
+        reporter::verbose(&format!("Skipping {}:{}:{}: not for {}", cmd_name, which, index + 1, env::consts::OS));
+        continue;
+      }
+    }
+    let label = format!("{}:{}", which, index + 1);
+    let run = || -> Result<(), DoitError> {
+      // A step's own `quiet` wins over the task's.
+      let quiet: &dyn TableLike = match args_in.as_inline_table().filter(|step| step.contains_key("quiet")) {
+        Some(step) => step,
+        None => table,
+      };
+      reporter::banner(quiet, &format!("Running command {}:{}", cmd_name, label));
+
+      let background = match args_in.as_inline_table().and_then(|step| step.get("background")) {
+        None => false,
+        Some(background) => {
+          background.as_bool().ok_or_else(|| format!("{}[{}]: background must be a boolean", which, index))?
+        }
+      };
+      // Under --dry-run a background step is just printed like any other.
+      if let Some(step) = args_in.as_inline_table().filter(|_| background && !settings().dry_run) {
+        return start_background(which, cmd_name, table, index, step);
+      }
+
+      // A step is a command (argv array or shell string) or an inline table with a `command` or `script`
+      // and per-step settings.
+      run_timed_step(cmd_name, label.clone(), || match args_in.as_inline_table() {
+        None => run_step(args_in, which, cmd_name, table, index, &[], None),
+        Some(step) => run_inline_step(step, which, cmd_name, table, index),
+      })
+      .map_err(|e| with_source_context(cmd_name, which, e))
+    };
+    match which {
+      "pre" | "post" => resumable(cmd_name, &label, run)?,
+      _ => run()?,
+    }
+  }
+  Ok(())
+}
+
+pub fn run_inline_step(
+  step: &toml_edit::InlineTable,
+  which: &str,
+  cmd_name: &str,
+  table: &Table,
+  index: usize,
+) -> Result<(), DoitError> {
+  match (step.get("command"), TableLike::get(step, "script"), step.get("pipeline")) {
+    (_, Some(script), _) => run_script(script, which, cmd_name, table, index, &[], Some(step)),
+    (Some(command), None, _) => run_step(command, which, cmd_name, table, index, &[], Some(step)),
+    (None, None, Some(pipeline)) => {
+      let pipeline = get_pipeline(Some(pipeline)).map_err(|e| format!("{}[{}]: {}", which, index, e))?;
+      run_step(pipeline, which, cmd_name, table, index, &[], Some(step))
+    }
+    (None, None, None) => Err(format!("{}[{}]: missing command", which, index).into()),
+  }
+}
+
+pub fn get_command<'a>(cmd_name: &str, table: &'a Table) -> Result<&'a Value, String> {
+  let pipeline = table.get("pipeline").filter(|_| !table.contains_key("command"));
+  if let Some(pipeline) = pipeline {
+    return get_pipeline(pipeline.as_value()).map_err(|e| format!("{}: {}", cmd_name, e));
+  }
+  table
+    .get("command")
+    .ok_or_else(|| format!("{}: missing command array", cmd_name))
+    .and_then(|command| command.as_value().ok_or_else(|| format!("{}: command is not an array or string", cmd_name)))
+}
+
+/// A `pipeline` setting, which has to be an array of argv arrays.
+pub fn get_pipeline(pipeline: Option<&Value>) -> Result<&Value, String> {
+  pipeline
+    .filter(|pipeline| pipeline.as_array().is_some_and(|stages| stages.iter().next().is_some_and(Value::is_array)))
+    .ok_or_else(|| "pipeline must be an array of argv arrays".into())
+}
+
+/// The paths matching a glob pattern, sorted.
+pub fn glob_paths(pattern: &str) -> Result<Vec<String>, String> {
+  let paths = glob(pattern).map_err(|e| format!("{}: {}", pattern, e))?;
+  let mut paths: Vec<String> = paths.flatten().map(|path| path.display().to_string()).collect();
+  paths.sort();
+  Ok(paths)
+}
+
+/// Expands a (templated) glob pattern into the modification times of every matching file.
+fn glob_mtimes(cmd_name: &str, table: &Table, pattern: &str) -> Result<Vec<SystemTime>, DoitError> {
+  let pattern = render_template(table, pattern)?;
+  let paths = glob(&pattern).map_err(|e| format!("{}: {}: {}", cmd_name, pattern, e))?;
+  Ok(paths.flatten().filter_map(|path| path.metadata().and_then(|meta| meta.modified()).ok()).collect())
+}
+
+/// A task with `outputs` is up to date when every output pattern matched something and the oldest
+/// output is at least as new as the newest of its `inputs`.
+fn is_up_to_date(cmd_name: &str, table: &Table) -> Result<bool, DoitError> {
+  let outputs = get_string_array(cmd_name, table, "outputs")?;
+  if outputs.is_empty() {
+    return Ok(false);
+  }
+
+  let mut oldest_output = SystemTime::now();
+  for output in outputs {
+    match glob_mtimes(cmd_name, table, output)?.into_iter().min() {
+      None => return Ok(false),
+      Some(mtime) => oldest_output = oldest_output.min(mtime),
+    }
+  }
+
+  for input in get_string_array(cmd_name, table, "inputs")? {
+    if glob_mtimes(cmd_name, table, input)?.into_iter().any(|mtime| mtime > oldest_output) {
+      return Ok(false);
+    }
+  }
+  Ok(true)
+}
+
+/// A parameter declared in a task's `params` table: either `name = "default"` or
+/// `name = { default = "...", description = "..." }`, where leaving out `default` makes it required.
+pub struct Param {
+  pub name: String,
+  pub default: Option<String>,
+  pub description: Option<String>,
+}
+
+pub fn get_params(cmd_name: &str, table: &Table) -> Result<Vec<Param>, String> {
+  let params = match table.get("params") {
+    None => return Ok(vec![]),
+    Some(params) => params.as_table_like().ok_or_else(|| format!("{}: params is not a table", cmd_name))?,
+  };
+
+  let mut declared = Vec::new();
+  for (name, decl) in params.iter() {
+    let param = if let Some(default) = decl.as_str() {
+      Param { name: name.into(), default: Some(default.into()), description: None }
+    } else if let Some(decl) = decl.as_table_like() {
+      let field = |key: &str| -> Result<Option<String>, String> {
+        decl
+          .get(key)
+          .map(|value| {
+            value
+              .as_str()
+              .map(String::from)
+              .ok_or_else(|| format!("{}: params.{}.{} is not a string", cmd_name, name, key))
+          })
+          .transpose()
+      };
+      Param { name: name.into(), default: field("default")?, description: field("description")? }
+    } else {
+      return Err(format!("{}: params.{} must be a string or a table", cmd_name, name));
+    };
+    declared.push(param);
+  }
+  Ok(declared)
+}
+
+fn params_usage(cmd_name: &str, params: &[Param]) -> String {
+  let mut usage = format!(
+    "Usage: doit {} {} [args...]",
+    cmd_name,
+    params
+      .iter()
+      .map(|param| match param.default {
+        None => format!("{}=<value>", param.name),
+        Some(_) => format!("[{}=<value>]", param.name),
+      })
+      .collect::<Vec<_>>()
+      .join(" ")
+  );
+  for param in params {
+    let default = match &param.default {
+      None => "required".to_string(),
+      Some(default) => format!("default: {:?}", default),
+    };
+    usage.push_str(&format!("\n  {} ({})", param.name, default));
+    if let Some(description) = &param.description {
+      usage.push_str(&format!(" {}", description));
+    }
+  }
+  usage
+}
+
+/// Pulls `name=value` arguments for declared params out of `args`, and returns a copy of the task table with
+/// every param available to templates as `%arg:name%` along with the remaining args.
+fn apply_params(cmd_name: &str, table: &Table, args: &[String]) -> Result<(Table, Vec<String>), String> {
+  let params = get_params(cmd_name, table)?;
+  let mut table = table.clone();
+  if params.is_empty() {
+    return Ok((table, args.to_vec()));
+  }
+
+  let mut values = HashMap::new();
+  let mut rest = Vec::new();
+  for arg in args {
+    match arg.split_once('=') {
+      Some((name, value)) if params.iter().any(|param| param.name == name) => {
+        values.insert(name, value);
+      }
+      _ => rest.push(arg.clone()),
+    }
+  }
+
+  let mut missing = Vec::new();
+  for param in &params {
+    match values.get(param.name.as_str()).copied().or(param.default.as_deref()) {
+      Some(value) => {
+        table.insert(&format!("arg:{}", param.name), literal_value(value));
+      }
+      None => missing.push(param.name.as_str()),
+    }
+  }
+  if !missing.is_empty() {
+    return Err(format!(
+      "{}: missing required parameter(s): {}\n{}",
+      cmd_name,
+      missing.join(", "),
+      params_usage(cmd_name, &params)
+    ));
+  }
+  Ok((table, rest))
+}
+
+/// Expands a task's `matrix` table into the cartesian product of its value arrays, each combination
+/// given as `(key, value)` pairs in declaration order.
+fn get_matrix(cmd_name: &str, table: &Table) -> Result<Vec<Vec<(String, String)>>, String> {
+  let matrix = match table.get("matrix") {
+    None => return Ok(vec![]),
+    Some(matrix) => matrix.as_table_like().ok_or_else(|| format!("{}: matrix is not a table", cmd_name))?,
+  };
+
+  let mut combinations = vec![vec![]];
+  for (key, values) in matrix.iter() {
+    let values = values.as_array().ok_or_else(|| format!("{}: matrix.{} is not an array", cmd_name, key))?;
+    let mut expanded = Vec::new();
+    for combination in &combinations {
+      for value in values {
+        let value = match (value.as_str(), value.as_integer(), value.as_bool()) {
+          (Some(value), _, _) => value.to_string(),
+          (None, Some(value), _) => value.to_string(),
+          (None, None, Some(value)) => value.to_string(),
+          _ => {
+            return Err(format!("{}: matrix.{} entry {} must be a string, integer or boolean", cmd_name, key, value))
+          }
+        };
+        let mut combination: Vec<(String, String)> = combination.clone();
+        combination.push((key.to_string(), value));
+        expanded.push(combination);
+      }
+    }
+    combinations = expanded;
+  }
+  Ok(combinations)
+}
+
+/// Runs the task once per `matrix` combination with each value available as `%matrix:key%`, spreading the
+/// combinations over `--jobs` workers.
+fn run_matrix(
+  cmd_name: &str,
+  table: &Table,
+  args: &[String],
+  combinations: Vec<Vec<(String, String)>>,
+) -> Result<(), DoitError> {
+  let runs: Vec<(String, Table)> = combinations
+    .into_iter()
+    .map(|combination| {
+      let mut table = table.clone();
+      for (key, value) in &combination {
+        table.insert(&format!("matrix:{}", key), literal_value(value));
+      }
+      let label = combination.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>();
+      (label.join(" "), table)
+    })
+    .collect();
+
+  let jobs = settings().jobs.min(runs.len());
+  if jobs <= 1 {
+    for (label, table) in &runs {
+      reporter::banner(table, &format!("Matrix {}: {}", cmd_name, label));
+      run_foreach(cmd_name, table, args)?;
+    }
+    return Ok(());
+  }
+
+  let next = AtomicUsize::new(0);
+  let errors = Mutex::new(Vec::new());
+  // The workers see what the task's .env files set, as the runs do one after another.
+  let context = Context::current();
+  let dotenv = DOTENV.with(|dotenv| dotenv.borrow().clone());
+  thread::scope(|scope| {
+    for _ in 0..jobs {
+      scope.spawn(|| {
+        with_dotenv(dotenv.clone(), || {
+          context.clone().enter(|| {
+            while let Some((label, table)) = runs.get(next.fetch_add(1, Ordering::Relaxed)) {
+              if !errors.lock().unwrap().is_empty() {
+                return;
+              }
+              reporter::set_task_prefix(Some(format!("{} {}", cmd_name, label)));
+              let result = run_foreach(cmd_name, table, args);
+              reporter::set_task_prefix(None);
+              if let Err(e) = result {
+                errors.lock().unwrap().push(e.map_message(|e| format!("{} {}: {}", cmd_name, label, e)));
+              }
+            }
+          })
+        })
+      });
+    }
+  });
+
+  DoitError::join(errors.into_inner().unwrap())
+}
+
+/// The items a task iterates over: a literal `foreach` array, or the sorted matches of a `foreach` glob.
+fn get_foreach(cmd_name: &str, table: &Table) -> Result<Option<Vec<String>>, DoitError> {
+  let foreach = match table.get("foreach") {
+    None => return Ok(None),
+    Some(foreach) => foreach,
+  };
+  if let Some(pattern) = foreach.as_str() {
+    let pattern = render_template(table, pattern)?;
+    return Ok(Some(glob_paths(&pattern).map_err(|e| format!("{}: {}", cmd_name, e))?));
+  }
+  Ok(Some(get_string_array(cmd_name, table, "foreach")?.into_iter().map(String::from).collect()))
+}
+
+/// Runs the task's steps, once per `foreach` item with the item available as `%item%` when present.
+fn run_foreach(cmd_name: &str, table: &Table, args: &[String]) -> Result<(), DoitError> {
+  let items = match get_foreach(cmd_name, table)? {
+    None => return run_task_steps(cmd_name, table, args),
+    Some(items) => items,
+  };
+  if items.is_empty() {
+    reporter::warning(&format!("{}: foreach matched nothing", cmd_name));
+  }
+  for item in items {
+    let mut table = table.clone();
+    table.insert("item", literal_value(&item));
+    reporter::banner(&table, &format!("Item {}: {}", cmd_name, item));
+    run_task_steps(cmd_name, &table, args)
+      .map_err(|e| e.map_message(|e| format!("{}: item {} failed: {}", cmd_name, item, e)))?;
+  }
+  Ok(())
+}
+
+/// Checks a task's or step's `only_on`/`skip_on` lists against the current OS (`linux`, `macos`, `windows`,
+/// ...) or OS family (`unix`, `windows`).
+fn platform_matches(which: &str, table: &dyn TableLike) -> Result<bool, String> {
+  let listed = |key: &str| -> Result<Option<bool>, String> {
+    match table.get(key) {
+      None => Ok(None),
+      Some(platforms) => {
+        let platforms = platforms.as_array().ok_or_else(|| format!("{}: {} is not an array", which, key))?;
+        Ok(Some(
+          platforms
+            .iter()
+            .any(|platform| matches!(platform.as_str(), Some(p) if p == env::consts::OS || p == env::consts::FAMILY)),
+        ))
+      }
+    }
+  };
+  Ok(listed("only_on")?.unwrap_or(true) && !listed("skip_on")?.unwrap_or(false))
+}
+
+static PROMPT_LOCK: Mutex<()> = Mutex::new(());
+
+/// With `lock = true`, in the task or for every task in [doit], takes an advisory lock on `.doit/locks/TASK.lock`,
+/// which is let go of when the file is dropped, so no other doit runs the task at the same time. `lock_wait` says
+/// what to do when another has it: fail straight away (`false`, the default), wait for it (`true`), or wait for
+/// at most a duration like `"30s"`.
+fn lock_task(cmd_name: &str, table: &Table) -> Result<Option<File>, DoitError> {
+  let config = loaded_config();
+  let setting = |key| table.get(key).or_else(|| config.as_ref().and_then(|config| config.settings.get(key)));
+  let lock = match setting("lock") {
+    None => false,
+    Some(lock) => lock.as_bool().ok_or_else(|| format!("{}: lock must be true or false", cmd_name))?,
+  };
+  if !lock || settings().dry_run {
+    return Ok(None);
+  }
+  let wait = match setting("lock_wait") {
+    None => Some(Duration::ZERO),
+    Some(wait) => match wait.as_bool() {
+      Some(true) => None,
+      Some(false) => Some(Duration::ZERO),
+      None => Some(get_duration_setting(wait, "lock_wait").map_err(|e| format!("{}: {}", cmd_name, e))?),
+    },
+  };
+
+  let path = doit_dir().join("locks").join(format!("{}.lock", cmd_name.replace(['/', '\\'], "_")));
+  let fail = |e: std::io::Error| DoitError::Other(format!("{}: {}", path.display(), e));
+  std::fs::create_dir_all(path.parent().unwrap_or(Path::new("."))).map_err(fail)?;
+  let mut file =
+    std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).map_err(fail)?;
+  let deadline = wait.map(|wait| Instant::now() + wait);
+  let mut waiting = false;
+  while unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() != Some(libc::EWOULDBLOCK) {
+      return Err(fail(e));
+    }
+    // The holder wrote its pid in the file once it had the lock.
+    let holder = std::fs::read_to_string(&path).unwrap_or_default();
+    let holder = match holder.trim() {
+      "" => String::new(),
+      pid => format!(" in pid {}", pid),
+    };
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+      return Err(DoitError::Other(format!(
+        "{} is already running{} ({} is locked)",
+        cmd_name,
+        holder,
+        path.display()
+      )));
+    }
+    if !waiting {
+      reporter::info(&format!("Waiting for {} to finish running{}", cmd_name, holder));
+      waiting = true;
+    }
+    check_cancelled()?;
+    thread::sleep(Duration::from_millis(100));
+  }
+  file.set_len(0).and_then(|_| writeln!(file, "{}", std::process::id())).map_err(fail)?;
+  Ok(Some(file))
+}
+
+/// Asks the `confirm` question of a task, failing rather than waiting when stdin is not a terminal.
+fn confirm_task(cmd_name: &str, table: &Table) -> Result<(), DoitError> {
+  let Some(question) = table.get("confirm") else {
+    return Ok(());
+  };
+  let question =
+    render_template(table, question.as_str().ok_or_else(|| format!("{}: confirm must be a string", cmd_name))?)?;
+  if settings().yes {
+    return Ok(());
+  }
+  if settings().dry_run {
+    reporter::info(&format!("{}: would ask: {}", cmd_name, question));
+    return Ok(());
+  }
+  if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+    return Err(
+      format!("{}: {}\nrefusing to run without confirmation; pass --yes to run non-interactively", cmd_name, question)
+        .into(),
+    );
+  }
+
+  let _prompt = PROMPT_LOCK.lock().unwrap();
+  eprint!("{} [y/N] ", question);
+  let _ = std::io::stderr().flush();
+  let mut answer = String::new();
+  PROMPTING.store(true, Ordering::SeqCst);
+  let read = std::io::stdin().read_line(&mut answer);
+  PROMPTING.store(false, Ordering::SeqCst);
+  read.map_err(|e| e.to_string())?;
+  match answer.trim().to_lowercase().as_str() {
+    "y" | "yes" => Ok(()),
+    _ => Err(format!("{}: not confirmed", cmd_name).into()),
+  }
+}
+
+fn run_task(cmd_name: &str, table: &Table, args: &[String]) -> Result<(), DoitError> {
+  let merged = apply_overlays(cmd_name, table)?;
+  let table = merged.as_ref().unwrap_or(table);
+  if !platform_matches(cmd_name, table)? {
+    reporter::verbose(&format!("Skipping {}: not for {}", cmd_name, env::consts::OS));
+    return Ok(());
+  }
+  with_dotenv(load_dotenv(cmd_name, table)?, || run_loaded_task(cmd_name, table, args))
+}
+
+/// The names of a task's profiles.
+pub fn get_profiles(table: &Table) -> Vec<&str> {
+  table
+    .get("profile")
+    .and_then(Item::as_table_like)
+    .map_or(vec![], |profiles| profiles.iter().map(|(name, _)| name).collect())
+}
+
+/// The task as it runs here: with the subtable for this OS, like `[build.linux]`, merged over its own keys, and then
+/// the `profile.NAME` table for the profile in effect. `None` when neither has anything to merge.
+pub fn apply_overlays(cmd_name: &str, table: &Table) -> Result<Option<Table>, DoitError> {
+  let mut merged = None;
+  if let Some(overlay) = table.get(env::consts::OS).and_then(Item::as_table_like) {
+    let mut platform = table.clone();
+    merge_overlay(&mut platform, overlay);
+    merged = Some(platform);
+  }
+  let base = merged.as_ref().unwrap_or(table);
+  Ok(apply_profile(cmd_name, base)?.or(merged))
+}
+
+/// Merges the keys of `overlay` over those of `table`, tables such as `env` key by key.
+fn merge_overlay(table: &mut Table, overlay: &dyn TableLike) {
+  for (key, value) in overlay.iter() {
+    match (table.get_mut(key).and_then(Item::as_table_like_mut), value.as_table_like()) {
+      (Some(own), Some(values)) => {
+        for (name, value) in values.iter() {
+          own.insert(name, value.clone());
+        }
+      }
+      _ => {
+        table.insert(key, value.clone());
+      }
+    }
+  }
+}
+
+/// The task with its `profile.NAME` table for the profile in effect merged over its own keys, or `None` when
+/// there's no profile or the task has nothing for it.
+fn apply_profile(cmd_name: &str, table: &Table) -> Result<Option<Table>, DoitError> {
+  let settings = settings();
+  let (Some(profile), Some(profiles)) = (settings.profile.as_deref(), table.get("profile")) else {
+    return Ok(None);
+  };
+  let profiles =
+    profiles.as_table_like().ok_or_else(|| format!("{}: profile must be a table of profiles", cmd_name))?;
+  let Some(overlay) = profiles.get(profile) else {
+    reporter::verbose(&format!("{} has nothing for the {} profile", cmd_name, profile));
+    return Ok(None);
+  };
+  let overlay = overlay.as_table_like().ok_or_else(|| format!("{}: profile.{} must be a table", cmd_name, profile))?;
+  let mut merged = table.clone();
+  merge_overlay(&mut merged, overlay);
+  Ok(Some(merged))
+}
+
+fn run_loaded_task(cmd_name: &str, table: &Table, args: &[String]) -> Result<(), DoitError> {
+  let (table, args) = apply_params(cmd_name, table, args)?;
+  confirm_task(cmd_name, &table)?;
+  let _lock = lock_task(cmd_name, &table)?;
+
+  let combinations = get_matrix(cmd_name, &table)?;
+  if combinations.is_empty() {
+    run_foreach(cmd_name, &table, &args)
+  } else {
+    run_matrix(cmd_name, &table, &args, combinations)
+  }
+}
+
+thread_local! {
+  /// What the .env files of the task being run on this thread set, later files' values after earlier ones'.
+  pub static DOTENV: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An environment variable, or else what the .env files of the task being run set it to.
+pub fn env_var(name: &str) -> Option<String> {
+  env::var(name).ok().or_else(|| {
+    DOTENV.with(|dotenv| dotenv.borrow().iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.clone()))
+  })
+}
+
+/// The variables of the .env files a task reads, next to the doit.toml: from its `dotenv` key, or else [doit]'s,
+/// which may be `false` for none or the files to read; by default `.env` and `.env.local`, if they are there.
+pub fn load_dotenv(cmd_name: &str, table: &Table) -> Result<Vec<(String, String)>, DoitError> {
+  let config = loaded_config();
+  let setting = table.get("dotenv").or_else(|| config.as_ref().and_then(|config| config.settings.get("dotenv")));
+  let invalid = || format!("{}: dotenv must be false or an array of files", cmd_name);
+  let (files, required) = match setting {
+    None => (vec![".env", ".env.local"], false),
+    Some(setting) if setting.as_bool() == Some(false) => return Ok(vec![]),
+    Some(setting) => {
+      let files = setting.as_array().ok_or_else(invalid)?;
+      (files.iter().map(Value::as_str).collect::<Option<Vec<_>>>().ok_or_else(invalid)?, true)
+    }
+  };
+  let dir = config_dir();
+  let mut vars = Vec::new();
+  for file in files {
+    let path = dir.join(file);
+    if required || path.is_file() {
+      vars.extend(dotenv::load(&path).map_err(|e| DoitError::Config(format!("{}: {}", cmd_name, e)))?);
+    }
+  }
+  Ok(vars)
+}
+
+/// Runs `f` with `vars` as what the .env files set, as the task it runs sees them.
+pub fn with_dotenv<T>(vars: Vec<(String, String)>, f: impl FnOnce() -> T) -> T {
+  let outer = DOTENV.with(|dotenv| dotenv.replace(vars));
+  let result = f();
+  DOTENV.with(|dotenv| dotenv.replace(outer));
+  result
+}
+
+thread_local! {
+  /// The commands being run on this thread, innermost last, so `&task` can't recurse forever.
+  static TASK_STACK: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+  /// The output the steps of the task being run on this thread have captured so far, by name.
+  pub static CAPTURES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+fn run_task_steps(cmd_name: &str, table: &Table, args: &[String]) -> Result<(), DoitError> {
+  TASK_STACK.with(|stack| {
+    let mut stack = stack.borrow_mut();
+    match stack.iter().position(|running| running == cmd_name) {
+      Some(pos) => Err(format!("task cycle: {} -> {}", stack[pos..].join(" -> "), cmd_name)),
+      None => {
+        stack.push(cmd_name.into());
+        Ok(())
+      }
+    }
+  })?;
+  // Each task, including one run by `&task`, starts out with nothing captured.
+  let outer = CAPTURES.with(|captures| captures.take());
+  let outer_jobs = BACKGROUND.with(|jobs| jobs.take());
+  let result = run_steps(cmd_name, table, args);
+  // Background steps don't outlive their task.
+  stop_background(BACKGROUND.with(|jobs| jobs.replace(outer_jobs)));
+  CAPTURES.with(|captures| captures.replace(outer));
+  TASK_STACK.with(|stack| stack.borrow_mut().pop());
+  result
+}
+
+fn run_steps(cmd_name: &str, table: &Table, args: &[String]) -> Result<(), DoitError> {
+  if is_up_to_date(cmd_name, table)? {
+    reporter::info(&format!("{} is up to date", cmd_name));
+    return Ok(());
+  }
+
+  let result = run_pre_main_post(cmd_name, table, args);
+  let result = run_outcome_handlers(cmd_name, table, result);
+
+  // `finally` steps run whether or not anything before them failed; an earlier error still wins.
+  if !table.contains_key("finally") {
+    return result;
+  }
+  let outer = IN_FINALLY.with(|in_finally| in_finally.replace(true));
+  let finally = process_pre_post_cmd("finally", cmd_name, table);
+  IN_FINALLY.with(|in_finally| in_finally.set(outer));
+  match (result, finally) {
+    (Err(e), Err(finally)) => Err(e.map_message(|e| format!("{}\nfinally also failed: {}", e, finally))),
+    (Err(e), Ok(())) => Err(e),
+    (Ok(()), finally) => finally,
+  }
+}
+
+/// Runs `on_success` or `on_failure` depending on `result`. Failure handlers see the failing step (`pre:2`,
+/// `main`, ...) as `%failed_step%` and its exit code, if it exited nonzero, as `%exit_code%`.
+fn run_outcome_handlers(cmd_name: &str, table: &Table, result: Result<(), DoitError>) -> Result<(), DoitError> {
+  match result {
+    Ok(()) if table.contains_key("on_success") => process_pre_post_cmd("on_success", cmd_name, table),
+    Ok(()) => Ok(()),
+    Err(e) if table.contains_key("on_failure") => {
+      let status = STEP_STATUS.with(|status| status.borrow().clone());
+      let mut table = table.clone();
+      table.insert("failed_step", toml_edit::value(status.step));
+      table.insert("exit_code", toml_edit::value(status.exit_code.map(|rc| rc.to_string()).unwrap_or_default()));
+      match process_pre_post_cmd("on_failure", cmd_name, &table) {
+        Ok(()) => Err(e),
+        Err(handler) => Err(e.map_message(|e| format!("{}\non_failure also failed: {}", e, handler))),
+      }
+    }
+    Err(e) => Err(e),
+  }
+}
+
+/// How far the task run from the command line has got through its `pre`, main and `post` steps, so a failed run
+/// can be picked up where it stopped with --resume.
+pub struct Progress {
+  pub task: String,
+  /// How many steps to skip, as the last run got through them.
+  pub skip: usize,
+  /// How many steps have been got through, skipped ones included.
+  pub done: usize,
+  /// The step that failed, if one has.
+  pub failed: Option<String>,
+}
+
+thread_local! {
+  pub static PROGRESS: RefCell<Option<Progress>> = const { RefCell::new(None) };
+}
+
+/// Runs a `pre`, main or `post` step of `cmd_name`, unless it is the task being tracked and one of the steps
+/// --resume or --from-step skips. Only the task's own steps count, not those of its dependencies or `&task`s.
+fn resumable(cmd_name: &str, step: &str, run: impl FnOnce() -> Result<(), DoitError>) -> Result<(), DoitError> {
+  let tracked = TASK_STACK.with(|stack| stack.borrow().len() == 1)
+    && PROGRESS.with(|progress| progress.borrow().as_ref().is_some_and(|progress| progress.task == cmd_name));
+  if !tracked {
+    return run();
+  }
+  let start = PROGRESS.with(|progress| {
+    progress.borrow().as_ref().filter(|progress| progress.done < progress.skip).map(|progress| progress.skip + 1)
+  });
+  let result = match start {
+    Some(start) => {
+      reporter::info(&format!("Skipping {}:{}, as this run starts at step {}", cmd_name, step, start));
+      Ok(())
+    }
+    None => run(),
+  };
+  PROGRESS.with(|progress| {
+    if let Some(progress) = progress.borrow_mut().as_mut() {
+      match &result {
+        Ok(()) => progress.done += 1,
+        Err(_) => progress.failed = Some(step.to_string()),
+      }
+    }
+  });
+  result
+}
+
+/// Where the progress of the last failed run of `task` is kept: `.doit/state/TASK.json` next to the doit.toml.
+fn progress_file(config: &Config, task: &str) -> PathBuf {
+  let dir = Path::new(&config.file).parent().unwrap_or(Path::new("")).join(".doit").join("state");
+  dir.join(format!("{}.json", task.replace(['/', '\\'], "_")))
+}
+
+/// The arguments and number of steps got through of the last run of `task`, if it failed.
+pub fn load_progress(config: &Config, task: &str) -> Result<Option<(Vec<String>, usize)>, DoitError> {
+  let path = progress_file(config, task);
+  if !path.is_file() {
+    return Ok(None);
+  }
+  let broken = |e: &dyn std::fmt::Display| DoitError::Other(format!("{}: {}", path.display(), e));
+  let text = std::fs::read_to_string(&path).map_err(|e| broken(&e))?;
+  let state: serde_json::Value = serde_json::from_str(&text).map_err(|e| broken(&e))?;
+  let args = state["args"].as_array().and_then(|args| args.iter().map(|arg| arg.as_str().map(String::from)).collect());
+  match (args, state["done"].as_u64()) {
+    (Some(args), Some(done)) => Ok(Some((args, done as usize))),
+    _ => Err(broken(&"not a record of a run")),
+  }
+}
+
+/// Keeps how far a failed run of `task` got for --resume, or forgets it once a run succeeds.
+pub fn save_progress(
+  config: &Config,
+  task: &str,
+  args: &[String],
+  result: &Result<(), DoitError>,
+) -> Result<(), String> {
+  let path = progress_file(config, task);
+  let Some(progress) = PROGRESS.with(|progress| progress.take()) else {
+    return Ok(());
+  };
+  let fail = |e: std::io::Error| format!("{}: {}", path.display(), e);
+  match (result, progress.failed) {
+    (Ok(()), _) if path.exists() => std::fs::remove_file(&path).map_err(fail),
+    (Ok(()), _) | (Err(_), None) => Ok(()),
+    // With nothing got through there is nothing to skip, though an older record may have to go.
+    (Err(_), Some(_)) if progress.done == 0 => match path.exists() {
+      true => std::fs::remove_file(&path).map_err(fail),
+      false => Ok(()),
+    },
+    (Err(_), Some(step)) => {
+      std::fs::create_dir_all(path.parent().unwrap_or(Path::new("."))).map_err(fail)?;
+      let state = serde_json::json!({"task": task, "args": args, "done": progress.done, "failed_step": step});
+      write_file(&path, format!("{}\n", state)).map_err(fail)?;
+      reporter::info(&format!(
+        "{} failed at {} after {} step(s); doit --resume {} picks up from there",
+        task, step, progress.done, task
+      ));
+      Ok(())
+    }
+  }
+}
+
+fn run_pre_main_post(cmd_name: &str, table: &Table, args: &[String]) -> Result<(), DoitError> {
+  if table.contains_key("pre") {
+    process_pre_post_cmd("pre", cmd_name, table)?;
+  }
+
+  resumable(cmd_name, "main", || {
+    reporter::banner(table, &format!("Running command {}", cmd_name));
+    run_timed_step(cmd_name, "main".into(), || match table.get("script") {
+      Some(script) => run_script(script, "main", cmd_name, table, 0, args, None),
+      None => run_step(get_command(cmd_name, table)?, "main", cmd_name, table, 0, args, None),
+    })
+    .map_err(|e| {
+      let key = ["script", "command", "pipeline"].into_iter().find(|key| table.contains_key(key));
+      with_source_context(cmd_name, key.unwrap_or("command"), e)
+    })
+  })?;
+
+  if table.contains_key("post") {
+    process_pre_post_cmd("post", cmd_name, table)?;
+  }
+  Ok(())
+}
+
+pub fn get_depends<'a>(cmd_name: &str, table: &'a Table) -> Result<Vec<&'a str>, String> {
+  get_string_array(cmd_name, table, "depends")
+}
+
+/// Depth-first walk of the `depends` graph, appending each task to `order` after its prerequisites.
+/// `stack` holds the tasks currently being visited so a back edge can be reported as a cycle.
+fn visit_depends(
+  config: &Config,
+  name: &str,
+  stack: &mut Vec<String>,
+  order: &mut Vec<String>,
+) -> Result<(), DoitError> {
+  let (table, actual_cmd) = find_task(config, name)?;
+  if order.contains(&actual_cmd) {
+    return Ok(());
+  }
+  if let Some(pos) = stack.iter().position(|visiting| visiting == &actual_cmd) {
+    return Err(format!("dependency cycle: {} -> {}", stack[pos..].join(" -> "), actual_cmd).into());
+  }
+
+  stack.push(actual_cmd.clone());
+  for dep in get_depends(&actual_cmd, table)? {
+    visit_depends(config, dep, stack, order)?;
+  }
+  stack.pop();
+  order.push(actual_cmd);
+  Ok(())
+}
+
+#[derive(Default)]
+struct Schedule {
+  started: HashSet<String>,
+  done: HashSet<String>,
+  running: usize,
+  errors: Vec<DoitError>,
+}
+
+/// Runs `order` on up to `jobs` worker threads, starting a task once everything it depends on has finished.
+/// After the first failure no new tasks are started, but those already running are waited for.
+fn run_parallel(config: &Config, order: &[String], jobs: usize) -> Result<(), DoitError> {
+  let mut tasks = Vec::new();
+  for name in order {
+    let (table, _) = find_task(config, name)?;
+    let mut deps = Vec::new();
+    for dep in get_depends(name, table)? {
+      deps.push(find_task(config, dep)?.1);
+    }
+    tasks.push((name, table, deps));
+  }
+
+  let schedule = Mutex::new(Schedule::default());
+  let wakeup = Condvar::new();
+
+  let worker = || loop {
+    let (name, table) = {
+      let mut state = schedule.lock().unwrap();
+      loop {
+        if !state.errors.is_empty() || state.started.len() == tasks.len() {
+          return;
+        }
+        let ready = tasks.iter().find(|(name, _, deps)| {
+          !state.started.contains(name.as_str()) && deps.iter().all(|dep| state.done.contains(dep))
+        });
+        match ready {
+          Some((name, table, _)) => {
+            state.started.insert(name.to_string());
+            state.running += 1;
+            break (*name, *table);
+          }
+          None if state.running == 0 => return,
+          None => state = wakeup.wait(state).unwrap(),
+        }
+      }
+    };
+
+    reporter::set_task_prefix(Some(name.clone()));
+    let result = run_task(name, table, &[]);
+    reporter::set_task_prefix(None);
+
+    let mut state = schedule.lock().unwrap();
+    state.running -= 1;
+    match result {
+      Ok(()) => {
+        state.done.insert(name.clone());
+      }
+      Err(e) => state.errors.push(e.map_message(|e| format!("{}: {}", name, e))),
+    }
+    wakeup.notify_all();
+  };
+
+  let context = Context::current();
+  thread::scope(|scope| {
+    for _ in 0..jobs.min(tasks.len()) {
+      scope.spawn(|| context.clone().enter(worker));
+    }
+  });
+
+  DoitError::join(schedule.into_inner().unwrap().errors)
+}
+
+pub fn process_cmd(config: &Config, cmd_name: &str, table: &Table, args: &[String]) -> Result<(), DoitError> {
+  let mut order = Vec::new();
+  visit_depends(config, cmd_name, &mut Vec::new(), &mut order)?;
+  order.pop();
+
+  let jobs = settings().jobs;
+  if jobs > 1 && order.len() > 1 {
+    run_parallel(config, &order, jobs)?;
+  } else {
+    for dep in &order {
+      let (dep_table, _) = find_task(config, dep)?;
+      run_task(dep, dep_table, &[])?;
+    }
+  }
+  run_task(cmd_name, table, args)
+}
+
+pub fn primary(config: &Config, cmd_name: &str, args: &[String]) -> Result<(), DoitError> {
+  let (table, actual_cmd) = find_task(config, cmd_name)?;
+  if is_group(table) {
+    println!("{} is a group of commands:", actual_cmd);
+    list_group(&actual_cmd, table, 2, false);
+    return Ok(());
+  }
+  process_cmd(config, &actual_cmd, table, args)
+}
+
+/// Whether `word` picks commands by pattern: a glob like `test:*`, a regex between slashes like `/^test/`, or a
+/// range of @N numbers like `@2..@4`.
+fn is_task_pattern(word: &str) -> bool {
+  word.contains(['*', '?', '['])
+    || word.len() > 2 && word.starts_with('/') && word.ends_with('/')
+    || SECTION_RANGE_RE.is_match(word)
+}
+
+/// The commands numbered `first` to `last` by --cmds, both included, other than hidden ones.
+fn task_range(config: &Config, first: &str, last: &str) -> Result<Vec<String>, DoitError> {
+  let doc = &config.doc;
+  let index = |number| {
+    section_index(doc, number).ok_or_else(|| {
+      DoitError::NotFound(format!("@{} is not a command; the {} has {}", number, config.file, doc.as_table().len()))
+    })
+  };
+  let (first, last) = (index(first)?, index(last)?);
+  if first > last {
+    return Err(DoitError::Usage(format!("@{}..@{} is an empty range", first + 1, last + 1)));
+  }
+  let names: Vec<String> = doc
+    .as_table()
+    .iter()
+    .skip(first)
+    .take(last - first + 1)
+    .filter(|(key, section)| section.as_table().is_some_and(|table| !is_hidden(key, table)))
+    .map(|(key, _)| key.to_string())
+    .collect();
+  if names.is_empty() {
+    return Err(DoitError::NotFound(format!("@{}..@{} only has hidden commands", first + 1, last + 1)));
+  }
+  Ok(names)
+}
+
+/// The commands `word` names: itself, or for a pattern every command it matches that isn't hidden, in the order
+/// --cmds lists them.
+fn expand_task_pattern(config: &Config, word: &str) -> Result<Vec<String>, DoitError> {
+  if !is_task_pattern(word) || matches!(get_section(config, word), Ok((Some(_), _))) {
+    return Ok(vec![word.to_string()]);
+  }
+  if let Some(caps) = SECTION_RANGE_RE.captures(word) {
+    return task_range(config, &caps[1], &caps[2]);
+  }
+  let matcher: Box<dyn Fn(&str) -> bool> = match word.strip_prefix('/').and_then(|word| word.strip_suffix('/')) {
+    Some(re) => {
+      let re = Regex::new(re).map_err(|e| DoitError::Usage(format!("{}: {}", word, e)))?;
+      Box::new(move |name| re.is_match(name))
+    }
+    None => {
+      let pattern = glob::Pattern::new(word).map_err(|e| DoitError::Usage(format!("{}: {}", word, e)))?;
+      Box::new(move |name| pattern.matches(name))
+    }
+  };
+  let names: Vec<String> = all_tasks("", config.doc.as_table())
+    .into_iter()
+    .filter(|(name, table)| !is_hidden(name.rsplit('.').next().unwrap_or(name), table) && matcher(name))
+    .map(|(name, _)| name)
+    .collect();
+  if names.is_empty() {
+    return Err(DoitError::NotFound(format!("no command in the {} matches {}", config.file, word)));
+  }
+  Ok(names)
+}
+
+/// Splits the words naming what to run into the commands to run one after another, each with its arguments: at
+/// every `+`, or, unless `single` is set, at every word when they all name commands, so `doit fmt lint test`
+/// runs three. Otherwise the first word is the command and the rest are its arguments. A pattern in place of a
+/// command stands for every command it matches, each given the same arguments.
+pub fn command_chain(config: &Config, words: &[String], single: bool) -> Result<Vec<(String, Vec<String>)>, DoitError> {
+  let names_commands = |word: &String| match is_task_pattern(word) {
+    true => expand_task_pattern(config, word).is_ok(),
+    false => matches!(get_section(config, word), Ok((Some(_), _))),
+  };
+  let parts: Vec<&[String]> = if words.iter().any(|word| word == "+") {
+    words.split(|word| word == "+").filter(|part| !part.is_empty()).collect()
+  } else if !single && words.len() > 1 && words.iter().all(names_commands) {
+    words.chunks(1).collect()
+  } else {
+    vec![words]
+  };
+  let mut chain = Vec::new();
+  for part in parts {
+    for name in expand_task_pattern(config, &part[0])? {
+      chain.push((name, part[1..].to_vec()));
+    }
+  }
+  Ok(chain)
+}
+
+/// Runs chained commands in order, stopping at the first failure unless `keep_going`, then says how each went.
+/// With `parallel` they run at once instead, on up to --jobs threads (or as many as there are commands).
+pub fn run_chain(
+  config: &Config,
+  chain: &[(String, Vec<String>)],
+  keep_going: bool,
+  parallel: bool,
+) -> Result<(), DoitError> {
+  let outcomes = Mutex::new(vec![None; chain.len()]);
+  let errors = Mutex::new(Vec::new());
+  let next = AtomicUsize::new(0);
+  let worker = || {
+    while let Some((index, (name, args))) = chain.iter().enumerate().nth(next.fetch_add(1, Ordering::SeqCst)) {
+      if CANCELLED.load(Ordering::SeqCst) || !errors.lock().unwrap().is_empty() && !keep_going {
+        return;
+      }
+      if parallel {
+        reporter::set_task_prefix(Some(name.clone()));
+      }
+      let result = primary(config, name, args);
+      reporter::set_task_prefix(None);
+      outcomes.lock().unwrap()[index] = Some(result.is_ok());
+      errors.lock().unwrap().extend(result.err().map(|e| e.map_message(|e| format!("{}: {}", name, e))));
+    }
+  };
+  match parallel {
+    false => worker(),
+    true => {
+      let jobs = if settings().jobs > 1 { settings().jobs } else { chain.len() };
+      let context = Context::current();
+      thread::scope(|scope| {
+        for _ in 0..jobs.min(chain.len()) {
+          scope.spawn(|| context.clone().enter(worker));
+        }
+      });
+    }
+  }
+
+  let width = chain.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+  for ((name, _), outcome) in chain.iter().zip(outcomes.into_inner().unwrap()) {
+    match outcome {
+      Some(true) => reporter::info(&format!("{:width$}  ok", name)),
+      Some(false) => reporter::error(&format!("{:width$}  failed", name)),
+      None => reporter::warning(&format!("{:width$}  not run", name)),
+    }
+  }
+  DoitError::join(errors.into_inner().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Settings;
+  use std::sync::Arc;
+  use toml_edit::DocumentMut;
+
+  fn task(text: &str) -> Table {
+    let doc: DocumentMut = text.parse().unwrap();
+    doc["task"].as_table().unwrap().clone()
+  }
+
+  fn with_profile<T>(profile: Option<&str>, f: impl FnOnce() -> T) -> T {
+    let settings = Settings { profile: profile.map(String::from), ..Settings::default() };
+    Context { config: None, settings: Arc::new(settings) }.enter(f)
+  }
+
+  #[test]
+  fn overlays_merge_tables_key_by_key() {
+    let mut table = task("[task]\ncommand = [\"a\"]\nenv = { A = \"1\", B = \"2\" }\nretries = 1");
+    let overlay = task("[task]\ncommand = [\"b\"]\nenv = { B = \"3\", C = \"4\" }\ncwd = \"sub\"");
+    merge_overlay(&mut table, &overlay);
+    assert_eq!(table["command"].as_array().unwrap().get(0).and_then(Value::as_str), Some("b"));
+    let env: Vec<_> = table["env"].as_table_like().unwrap().iter().map(|(k, v)| (k, v.as_str().unwrap())).collect();
+    assert_eq!(env, [("A", "1"), ("B", "3"), ("C", "4")]);
+    assert_eq!((table["retries"].as_integer(), table["cwd"].as_str()), (Some(1), Some("sub")));
+  }
+
+  #[test]
+  fn profiles() {
+    let table = task("[task]\nwho = \"dev\"\nprofile.prod = { who = \"prod\", env = { MODE = \"fast\" } }");
+    let who = |profile| {
+      with_profile(profile, || apply_profile("task", &table))
+        .unwrap()
+        .map(|merged| (merged["who"].as_str().unwrap().to_string(), merged.contains_key("env")))
+    };
+    assert_eq!(who(Some("prod")), Some(("prod".into(), true)));
+    assert_eq!((who(None), who(Some("staging"))), (None, None));
+
+    let bad = |text| with_profile(Some("prod"), || apply_profile("task", &task(text))).unwrap_err().to_string();
+    assert_eq!(bad("[task]\nprofile = \"prod\""), "task: profile must be a table of profiles");
+    assert_eq!(bad("[task]\nprofile.prod = 1"), "task: profile.prod must be a table");
+  }
+
+  #[test]
+  fn profiles_merge_over_the_platform_table() {
+    let table = task(&format!(
+      "[task]\nwho = \"any\"\nwhere = \"any\"\n[task.{}]\nwho = \"os\"\nwhere = \"os\"\n[task.profile.prod]\nwho = \"prod\"",
+      env::consts::OS
+    ));
+    let keys = |profile| {
+      let merged = with_profile(profile, || apply_overlays("task", &table)).unwrap().unwrap();
+      (merged["who"].as_str().unwrap().to_string(), merged["where"].as_str().unwrap().to_string())
+    };
+    assert_eq!(keys(None), ("os".into(), "os".into()));
+    assert_eq!(keys(Some("prod")), ("prod".into(), "os".into()));
+    assert!(with_profile(None, || apply_overlays("task", &task("[task]\nwho = \"any\""))).unwrap().is_none());
+  }
+}
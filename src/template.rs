@@ -0,0 +1,680 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! Rendering the `%...%` placeholders of templates against a task's keys, the environment and what doit knows.
+use crate::{
+  builtins::state_value,
+  reporter, settings,
+  task::{env_var, glob_paths, CAPTURES},
+  DoitError,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{
+  cell::{Cell, RefCell},
+  env,
+  path::Path,
+  process::{Command, Stdio},
+};
+use toml_edit::{Item, Table};
+use users::{get_user_by_name, os::unix::UserExt};
+
+pub const ASCII_SUB1: &str = "\x1A\x01";
+
+static HOME: Lazy<String> =
+  Lazy::new(|| dirs::home_dir().expect("home_dir undefined").to_str().expect("String").to_string());
+
+static ENV0_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"%env:(.*?):(.*?)%").unwrap());
+pub static ENV1_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"%env:(.*?)%").unwrap());
+pub static VAR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"%(.*?)%").unwrap());
+pub static FILTER_CALL_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^([a-z]+)\((.*)\)$").unwrap());
+pub static DATE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"%date\{(.*?)\}%").unwrap());
+static SH_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"%sh:(.*?)%").unwrap());
+pub static POSITIONAL_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"%(\d+|@)%").unwrap());
+
+/// How a task's `template` key says placeholders are treated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemplateMode {
+  /// Any placeholder that can't be resolved is an error, the default.
+  Strict,
+  /// Unknown keys and environment variables are left as they are, for commands with `%`s of their own.
+  Lenient,
+  /// Nothing is expanded, the leading `:` is just dropped.
+  Off,
+}
+
+pub fn template_mode(table: &Table) -> Result<TemplateMode, DoitError> {
+  match table.get("template").map(|mode| mode.as_str()) {
+    None | Some(Some("strict")) => Ok(TemplateMode::Strict),
+    Some(Some("lenient")) => Ok(TemplateMode::Lenient),
+    Some(Some("off")) => Ok(TemplateMode::Off),
+    Some(_) => Err(DoitError::Config("template must be \"strict\", \"lenient\" or \"off\"".into())),
+  }
+}
+
+pub fn render_template(table: &Table, template: &str) -> Result<String, DoitError> {
+  if template.is_empty() {
+    return Ok(template.into());
+  }
+
+  let mode = template_mode(table)?;
+  let x1 = {
+    match &template[..1] {
+      ":" => {
+        let x0 = &template[1..];
+        if x0.is_empty() || mode == TemplateMode::Off {
+          return Ok(x0.into());
+        }
+        x0
+      }
+      _ => return Ok(template.into()),
+    }
+    .replace("%%", ASCII_SUB1)
+  };
+  let lenient = mode == TemplateMode::Lenient;
+
+  // With -vv every substitution each pass makes is printed, then the text as that pass left it.
+  let tracing = settings().verbose > 1;
+  let trace = |line: String| {
+    if tracing {
+      reporter::detail(&line.replace(ASCII_SUB1, "%%"));
+    }
+  };
+  let resolved = |matched: &str, value: String| -> String {
+    trace(format!("  {} -> {}", matched, value));
+    value
+  };
+  let after = |pass: &str, before: &str, text: &str| {
+    if before != text {
+      trace(format!("  after {}: {}", pass, text));
+    }
+  };
+  trace(format!("template: {}", x1));
+
+  let errors = std::cell::RefCell::new(Vec::<String>::new());
+  let push_error = |e: String| -> String {
+    errors.borrow_mut().push(e);
+    String::default()
+  };
+
+  // Dates go first, while the `%`s of their formats can't be mistaken for other placeholders.
+  let x1 = {
+    let dated = expand_dates(&x1, resolved, push_error);
+    after("dates", &x1, &dated);
+    dated
+  };
+
+  let x2 = ENV0_RE.replace_all(&x1, |caps: &regex::Captures| {
+    let evar = &caps[1];
+    match env_var(evar) {
+      Some(value) => resolved(&caps[0], value),
+      None => resolved(&caps[0], caps[2].into()),
+    }
+  });
+  after("env defaults", &x1, &x2);
+
+  let x3 = ENV1_RE.replace_all(&x2, |caps: &regex::Captures| {
+    let evar = &caps[1];
+    match env_var(evar) {
+      Some(value) => resolved(&caps[0], value),
+      None if lenient => caps[0].to_string(),
+      None => push_error(format!("(Unknown ENV variable: {}: environment variable not found", evar)),
+    }
+  });
+  after("env", &x2, &x3);
+
+  let table_key = |key: &str, matched: &str| -> Option<String> {
+    // `%sh:...%` is left for its own pass, which runs last so a command's output is never expanded further.
+    if key.starts_with("sh:") {
+      return Some(matched.to_string());
+    }
+    // Within a larger argument `%glob:...%` gives the matching paths separated by spaces.
+    if let Some(pattern) = key.strip_prefix("glob:") {
+      return Some(match glob_paths(&pattern.replace(ASCII_SUB1, "%%")) {
+        Ok(paths) if paths.is_empty() && table.get("glob_empty").and_then(Item::as_str) != Some("empty") => {
+          push_error(format!("{} matched nothing; set glob_empty = \"empty\" to allow that", pattern))
+        }
+        Ok(paths) => resolved(matched, paths.join(" ").replace('%', ASCII_SUB1)),
+        Err(e) => push_error(e),
+      });
+    }
+    match lookup_key(table, key, &mut Vec::new()) {
+      Ok(value) => Some(resolved(matched, value)),
+      Err(_) if lenient => None,
+      Err(e) => Some(push_error(e)),
+    }
+  };
+  let x4 = if lenient {
+    replace_lenient(&x3, table_key).into()
+  } else {
+    VAR_RE.replace_all(&x3, |caps: &regex::Captures| table_key(&caps[1], &caps[0]).unwrap_or_default())
+  };
+  after("table keys", &x3, &x4);
+
+  let x5 = expand_tildes(&x4, |username| match username {
+    None => Some(resolved("~", HOME.to_string())),
+    Some(username) => {
+      get_user_by_name(username).map(|user| resolved(&format!("~{}", username), user.home_dir().display().to_string()))
+    }
+  })
+  .unwrap_or_else(|e| {
+    push_error(e);
+    x4.to_string()
+  });
+  after("tilde", &x4, &x5);
+
+  let x6 = SH_RE.replace_all(&x5, |caps: &regex::Captures| {
+    let script = caps[1].replace(ASCII_SUB1, "%");
+    if PREVIEWING.with(Cell::get) {
+      return resolved(&caps[0], caps[0].to_string());
+    }
+    if !settings().sh_substitution {
+      return push_error(format!("%sh:{}%: shell substitution is turned off", script));
+    }
+    if settings().dry_run {
+      return resolved(&caps[0], format!("$({})", script));
+    }
+    match shell_output(&script) {
+      Ok(output) => resolved(&caps[0], output.replace('%', ASCII_SUB1)),
+      Err(e) => push_error(format!("%sh:{}%: {}", script, e)),
+    }
+  });
+  after("sh", &x5, &x6);
+  if !errors.borrow().is_empty() {
+    return Err(DoitError::Template(errors.borrow().join("\n")));
+  }
+  Ok(x6.replace(ASCII_SUB1, "%"))
+}
+
+/// Replaces `~` and `~user` with the home directory `home_of` gives for them (`None` for the current user). They
+/// are only taken as such at the start of a word or after `=` or `:`, as in `--prefix=~/opt` or
+/// `PATH=~/bin:~/.local/bin`, and only when followed by `/`, `:`, whitespace or the end of the text.
+fn expand_tildes(text: &str, home_of: impl Fn(Option<&str>) -> Option<String>) -> Result<String, String> {
+  let mut expanded = String::with_capacity(text.len());
+  let mut rest = text;
+  let mut after_boundary = true;
+  while let Some(c) = rest.chars().next() {
+    if c == '~' && after_boundary {
+      let name_len = rest[1..]
+        .char_indices()
+        .take_while(|(i, c)| {
+          *i < 31 && (c.is_ascii_lowercase() || *c == '_' || (*i > 0 && (c.is_ascii_digit() || *c == '-')))
+        })
+        .count();
+      let end = &rest[1 + name_len..];
+      if end.chars().next().is_none_or(|c| c == '/' || c == ':' || c.is_whitespace()) {
+        let username = (name_len > 0).then(|| &rest[1..1 + name_len]);
+        match home_of(username) {
+          Some(home) => expanded.push_str(&home),
+          None => return Err(format!("user '{}' not found!", username.unwrap_or_default())),
+        }
+        rest = end;
+        after_boundary = false;
+        continue;
+      }
+    }
+    expanded.push(c);
+    after_boundary = c == '=' || c == ':' || c.is_whitespace();
+    rest = &rest[c.len_utf8()..];
+  }
+  Ok(expanded)
+}
+
+/// Replaces `%...%` placeholders like VAR_RE does, except that when `resolve` gives None for one it is left as it
+/// is and its closing `%` may open the next one, so that in `+%Y-%m %name%` it is `%name%` that gets resolved.
+fn replace_lenient(text: &str, mut resolve: impl FnMut(&str, &str) -> Option<String>) -> String {
+  let mut replaced = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('%') {
+    let Some(len) = rest[start + 1..].find('%') else {
+      break;
+    };
+    let matched = &rest[start..start + len + 2];
+    replaced.push_str(&rest[..start]);
+    match resolve(&matched[1..len + 1], matched) {
+      Some(value) => {
+        replaced.push_str(&value);
+        rest = &rest[start + len + 2..];
+      }
+      None => {
+        replaced.push_str(&matched[..len + 1]);
+        rest = &rest[start + len + 1..];
+      }
+    }
+  }
+  replaced.push_str(rest);
+  replaced
+}
+
+/// How deeply table values may refer to other keys before rendering gives up.
+const MAX_KEY_DEPTH: usize = 16;
+
+/// Resolves the inside of a `%...%` table placeholder: a key, optionally with a `:-default`, followed by any
+/// number of `|filter`s, or a single filter in function form like `basename(key)`.
+fn lookup_key(table: &Table, placeholder: &str, stack: &mut Vec<String>) -> Result<String, String> {
+  let (placeholder, filters) = match FILTER_CALL_RE.captures(placeholder) {
+    Some(caps) => (caps.get(2).map_or("", |key| key.as_str()), vec![caps.get(1).map_or("", |name| name.as_str())]),
+    None => {
+      let mut parts = placeholder.split('|');
+      (parts.next().unwrap_or_default(), parts.collect())
+    }
+  };
+  let value = resolve_key(table, placeholder, stack)?;
+  filters.into_iter().try_fold(value, |value, filter| apply_filter(filter.trim(), &value))
+}
+
+/// The template filters, for `%key|filter%` and `%filter(key)%`.
+fn apply_filter(filter: &str, value: &str) -> Result<String, String> {
+  let path = Path::new(value);
+  Ok(match filter {
+    "upper" => value.to_uppercase(),
+    "lower" => value.to_lowercase(),
+    "trim" => value.trim().to_string(),
+    "basename" => path.file_name().map_or(String::default(), |name| name.to_string_lossy().into()),
+    "dirname" => match path.parent().map(|parent| parent.display().to_string()) {
+      Some(parent) if !parent.is_empty() => parent,
+      Some(_) => ".".into(),
+      None => value.into(),
+    },
+    _ => return Err(format!("(Unknown filter: {}; use upper, lower, trim, basename or dirname)", filter)),
+  })
+}
+
+/// The placeholders that are always available, with what --placeholders says about them.
+pub const META_PLACEHOLDERS: &[(&str, &str)] = &[
+  ("cwd", "the directory doit runs in, which is where the doit.toml is"),
+  ("home", "the home directory"),
+  ("user", "the name of the user running doit"),
+  ("hostname", "the name of this machine"),
+  ("os", "the operating system, like linux or macos"),
+  ("arch", "the CPU architecture, like x86_64 or aarch64"),
+  ("date", "the date doit started, as YYYY-MM-DD"),
+  ("timestamp", "the time doit started, in seconds since 1970"),
+  ("git:branch", "the current git branch, or HEAD when detached"),
+  ("git:sha", "the short hash of the current git commit"),
+  ("git:root", "the top directory of the git work tree"),
+  ("git:dirty", "true when the git work tree has uncommitted changes, else false"),
+];
+
+/// The other placeholder syntax, for --placeholders.
+pub const PLACEHOLDER_FORMS: &[(&str, &str)] = &[
+  ("%key%", "the task's string value for key"),
+  ("%key:-text%", "or text when the task has no such key"),
+  ("%key|filter%", "filtered by upper, lower, trim, basename or dirname"),
+  ("%env:NAME%", "an environment variable"),
+  ("%env:NAME:text%", "or text when NAME is unset"),
+  ("%date{format}%", "when doit started, formatted by strftime, like %date{%H:%M}%"),
+  ("%sh:script%", "the trimmed output of a shell script"),
+  ("%glob:pattern%", "the paths matching pattern"),
+  ("%1%, %@%", "the command line arguments"),
+  ("%args%", "as a whole argument, where the command line arguments go"),
+  ("%capture:name%", "what an earlier step with capture = \"name\" printed"),
+  ("%state:key%", "what &state-set last stored for key in .doit/state.toml"),
+  ("%%", "a literal %"),
+];
+
+/// The value of one of the META_PLACEHOLDERS, which take precedence over table keys of the same name. They are
+/// only worked out when used, as the git ones run git.
+fn meta_placeholder(name: &str) -> Option<Result<String, String>> {
+  let git_output = |args: &[&str]| match PREVIEWING.with(Cell::get) {
+    true => Ok(format!("%{}%", name)),
+    false => git_output(args),
+  };
+  let value = match name {
+    "cwd" => env::current_dir().ok().map(|cwd| cwd.display().to_string()),
+    "home" => dirs::home_dir().map(|home| home.display().to_string()),
+    "user" => users::get_current_username().map(|user| user.to_string_lossy().into()),
+    "hostname" => {
+      let mut buf = [0u8; 256];
+      match unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } {
+        0 => {
+          let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+          Some(String::from_utf8_lossy(&buf[..len]).into())
+        }
+        _ => None,
+      }
+    }
+    "os" => Some(env::consts::OS.into()),
+    "arch" => Some(env::consts::ARCH.into()),
+    "date" => return Some(format_time("%Y-%m-%d")),
+    "timestamp" => Some(START_TIME.to_string()),
+    "git:branch" => return Some(git_output(&["rev-parse", "--abbrev-ref", "HEAD"])),
+    "git:sha" => return Some(git_output(&["rev-parse", "--short", "HEAD"])),
+    "git:root" => return Some(git_output(&["rev-parse", "--show-toplevel"])),
+    "git:dirty" => return Some(git_output(&["status", "--porcelain"]).map(|status| (!status.is_empty()).to_string())),
+    _ => return None,
+  };
+  Some(value.ok_or_else(|| format!("(Failed to determine %{}%)", name)))
+}
+
+/// Runs git in doit's directory, returning its trimmed stdout.
+fn git_output(args: &[&str]) -> Result<String, String> {
+  let output = Command::new("git")
+    .args(args)
+    .stdin(Stdio::null())
+    .stderr(Stdio::piped())
+    .output()
+    .map_err(|e| format!("(Failed to run git: {})", e))?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("not a git repository") {
+      let cwd = env::current_dir().map(|cwd| cwd.display().to_string()).unwrap_or_default();
+      return Err(format!("(Not inside a git repository: {})", cwd));
+    }
+    return Err(format!("(git {} failed: {})", args.join(" "), stderr.trim()));
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Resolves a key or `key:-default`, expanding the `%env:...%` and `%other%` references in the key's value in
+/// turn. `stack` holds the keys being resolved, to report cycles. The result keeps `%%` masked as ASCII_SUB1.
+fn resolve_key(table: &Table, placeholder: &str, stack: &mut Vec<String>) -> Result<String, String> {
+  // `%key:-default%` falls back to the default when the table has no such key.
+  let (key, default) = match placeholder.split_once(":-") {
+    Some((key, default)) => (key, Some(default)),
+    None => (placeholder, None),
+  };
+  // What --set gives comes first, before even the placeholders doit fills in itself.
+  let settings = settings();
+  let overridden = settings.overrides.iter().rev().find(|(name, _)| name == key).map(|(_, value)| value.as_str());
+  if overridden.is_none() {
+    if let Some(value) = meta_placeholder(key) {
+      return value.map(|value| value.replace('%', ASCII_SUB1));
+    }
+    if let Some(name) = key.strip_prefix("capture:") {
+      return match CAPTURES.with(|captures| captures.borrow().get(name).cloned()) {
+        Some(value) => Ok(value.replace('%', ASCII_SUB1)),
+        // Under --dry-run nothing ran to capture anything.
+        None if settings.dry_run => Ok(format!("<captured {}>", name)),
+        None if default.is_some() => Ok(default.unwrap_or_default().into()),
+        None => Err(format!("(Nothing has been captured as {} by an earlier step)", name)),
+      };
+    }
+    if let Some(name) = key.strip_prefix("state:") {
+      return match state_value(name).map_err(|e| format!("({})", e))? {
+        Some(value) => Ok(value.replace('%', ASCII_SUB1)),
+        None if default.is_some() => Ok(default.unwrap_or_default().into()),
+        None => Err(format!("(Nothing has been stored as {} by &state-set)", name)),
+      };
+    }
+  }
+  let found = match overridden {
+    Some(value) => Some(value),
+    None => table
+      .get(key)
+      .map(|value| value.as_str().ok_or_else(|| format!("(Failed to convert value to string for key: {})", key)))
+      .transpose()?,
+  };
+  let value = match (found, default) {
+    (None, Some(default)) => return Ok(default.into()),
+    (None, None) => {
+      let keys: Vec<&str> = table.iter().filter(|(_, value)| value.is_str()).map(|(key, _)| key).collect();
+      return Err(format!("(Unknown table key: {}; the keys with string values are: {})", key, keys.join(", ")));
+    }
+    (Some(value), _) => value,
+  };
+  if !value.contains('%') {
+    return Ok(value.into());
+  }
+  if let Some(pos) = stack.iter().position(|resolving| resolving == key) {
+    return Err(format!("(Reference cycle: {} -> {})", stack[pos..].join(" -> "), key));
+  }
+  if stack.len() >= MAX_KEY_DEPTH {
+    return Err(format!("(References nested more than {} deep: {})", MAX_KEY_DEPTH, stack.join(" -> ")));
+  }
+
+  stack.push(key.into());
+  let errors = RefCell::new(Vec::new());
+  let masked = value.replace("%%", ASCII_SUB1);
+  let dated = expand_dates(
+    &masked,
+    |_, value| value,
+    |e| {
+      errors.borrow_mut().push(e);
+      String::default()
+    },
+  );
+  let mut errors = errors.into_inner();
+  let with_defaults =
+    ENV0_RE.replace_all(&dated, |caps: &regex::Captures| env_var(&caps[1]).unwrap_or_else(|| caps[2].into()));
+  let with_env = ENV1_RE.replace_all(&with_defaults, |caps: &regex::Captures| {
+    env_var(&caps[1]).unwrap_or_else(|| {
+      errors.push(format!("(Unknown ENV variable: {}: environment variable not found", &caps[1]));
+      String::default()
+    })
+  });
+  let expanded = VAR_RE.replace_all(&with_env, |caps: &regex::Captures| {
+    if caps[1].starts_with("sh:") {
+      return caps[0].to_string();
+    }
+    lookup_key(table, &caps[1], stack).unwrap_or_else(|e| {
+      errors.push(e);
+      String::default()
+    })
+  });
+  let expanded = expanded.to_string();
+  stack.pop();
+  if errors.is_empty() {
+    Ok(expanded)
+  } else {
+    Err(errors.join("\n"))
+  }
+}
+
+thread_local! {
+  /// Set while --show renders its preview of a command, which leaves `%sh:...%` and the git placeholders as they
+  /// are written rather than running anything for them.
+  pub static PREVIEWING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// When doit started, which every date placeholder uses so that all the steps of a run agree.
+pub static START_TIME: Lazy<libc::time_t> = Lazy::new(|| unsafe { libc::time(std::ptr::null_mut()) });
+
+/// Formats START_TIME in local time with strftime.
+fn format_time(format: &str) -> Result<String, String> {
+  format_time_at(*START_TIME, format)
+}
+
+/// Formats `time` in local time with strftime.
+pub fn format_time_at(time: libc::time_t, format: &str) -> Result<String, String> {
+  let format = std::ffi::CString::new(format).map_err(|_| "(Date formats can't contain NUL)".to_string())?;
+  let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+  if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+    return Err("(Failed to determine the local time)".into());
+  }
+  let mut buf = [0u8; 1024];
+  let len = unsafe { libc::strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), format.as_ptr(), &tm) };
+  if len == 0 && !format.as_bytes().is_empty() {
+    return Err(format!("(Date format {:?} gives nothing or is too long)", format));
+  }
+  Ok(String::from_utf8_lossy(&buf[..len]).into())
+}
+
+/// Replaces each `%date{format}%` in text masked as render_template does, reporting each replacement to
+/// `resolved` and each failure to `failed`.
+fn expand_dates(text: &str, resolved: impl Fn(&str, String) -> String, failed: impl Fn(String) -> String) -> String {
+  DATE_RE
+    .replace_all(text, |caps: &regex::Captures| match format_time(&caps[1].replace(ASCII_SUB1, "%%")) {
+      Ok(value) => resolved(&caps[0], value.replace('%', ASCII_SUB1)),
+      Err(e) => failed(e),
+    })
+    .into()
+}
+
+/// A value doit fills in for a placeholder such as `%item%`, escaped so that it is used as is.
+pub fn literal_value(value: &str) -> Item {
+  toml_edit::value(value.replace('%', "%%"))
+}
+
+/// Runs a `%sh:...%` script, returning its trimmed stdout.
+fn shell_output(script: &str) -> Result<String, String> {
+  let output = Command::new("sh")
+    .args(["-c", script])
+    .stdin(Stdio::null())
+    .stderr(Stdio::piped())
+    .output()
+    .map_err(|e| e.to_string())?;
+  if !output.status.success() {
+    return Err(format!("failed with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Renders the value of a key that is always templated, with or without the leading `:` argv elements need.
+pub fn render_value(table: &Table, value: &str) -> Result<String, DoitError> {
+  render_template(table, &format!(":{}", value.strip_prefix(':').unwrap_or(value)))
+}
+
+/// Substitutes `%N%` (1-based) and `%@%` (the args not taken by any `%N%`) in a templated argv element.
+/// Substituted values have their `%` doubled so render_template passes them through literally.
+pub fn expand_positional(template: &str, args: &[String], rest: &[String]) -> Result<String, DoitError> {
+  let escape = |value: &str| value.replace('%', "%%");
+  let mut missing = None;
+  let masked = template.replace("%%", ASCII_SUB1);
+  let expanded = POSITIONAL_RE.replace_all(&masked, |caps: &regex::Captures| {
+    if &caps[1] == "@" {
+      return escape(&rest.join(" "));
+    }
+    match caps[1].parse::<usize>().ok().filter(|n| *n > 0).and_then(|n| args.get(n - 1)) {
+      Some(value) => escape(value),
+      None => {
+        missing.get_or_insert_with(|| caps[0].to_string());
+        String::default()
+      }
+    }
+  });
+  match missing {
+    Some(placeholder) => Err(DoitError::Template(format!("{} refers to an argument that was not given", placeholder))),
+    None => Ok(expanded.replace(ASCII_SUB1, "%%")),
+  }
+}
+
+pub fn meta_placeholder_name(name: &str) -> bool {
+  META_PLACEHOLDERS.iter().any(|(meta, _)| *meta == name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Context, Settings};
+  use std::sync::Arc;
+  use toml_edit::DocumentMut;
+
+  fn tildes(text: &str) -> Result<String, String> {
+    expand_tildes(text, |username| match username {
+      None => Some("/home/me".into()),
+      Some("bob") | Some("b_o-b9") => Some(format!("/home/{}", username.unwrap_or_default())),
+      Some(_) => None,
+    })
+  }
+
+  #[test]
+  fn tilde_alone_and_at_the_end() {
+    assert_eq!(tildes("~"), Ok("/home/me".into()));
+    assert_eq!(tildes("~bob"), Ok("/home/bob".into()));
+    assert_eq!(tildes("cd ~"), Ok("cd /home/me".into()));
+  }
+
+  #[test]
+  fn tilde_with_slash() {
+    assert_eq!(tildes("~/src"), Ok("/home/me/src".into()));
+    assert_eq!(tildes("~bob/src"), Ok("/home/bob/src".into()));
+    assert_eq!(tildes("~b_o-b9/"), Ok("/home/b_o-b9/".into()));
+  }
+
+  #[test]
+  fn tilde_after_equals_and_colons() {
+    assert_eq!(tildes("--prefix=~/opt"), Ok("--prefix=/home/me/opt".into()));
+    assert_eq!(tildes("PATH=~/bin:~bob/bin:~"), Ok("PATH=/home/me/bin:/home/bob/bin:/home/me".into()));
+    assert_eq!(tildes("ls ~/a ~bob"), Ok("ls /home/me/a /home/bob".into()));
+  }
+
+  #[test]
+  fn tilde_inside_a_word_is_left_alone() {
+    assert_eq!(tildes("a~/b"), Ok("a~/b".into()));
+    assert_eq!(tildes("x~"), Ok("x~".into()));
+    assert_eq!(tildes("~~"), Ok("~~".into()));
+    assert_eq!(tildes("~.bak"), Ok("~.bak".into()));
+    assert_eq!(tildes("~1"), Ok("~1".into()));
+    assert_eq!(tildes("file~bob"), Ok("file~bob".into()));
+  }
+
+  fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+  }
+
+  fn positional(template: &str, args: &[String], rest: &[String]) -> Result<String, String> {
+    expand_positional(template, args, rest).map_err(|e| {
+      assert_eq!(e.exit_code(), 5);
+      e.to_string()
+    })
+  }
+
+  #[test]
+  fn positional_args() {
+    let args = strings(&["a", "b c", "50%"]);
+    assert_eq!(positional("%1%-%2%", &args, &[]), Ok("a-b c".into()));
+    assert_eq!(positional("%3%", &args, &[]), Ok("50%%".into()));
+    assert_eq!(positional("all: %@%", &args, &args[1..]), Ok("all: b c 50%%".into()));
+    assert_eq!(positional("100%% %1%", &args, &[]), Ok("100%% a".into()));
+    assert_eq!(positional("%%1%%", &args, &[]), Ok("%%1%%".into()));
+  }
+
+  #[test]
+  fn positional_args_not_given() {
+    let args = strings(&["a"]);
+    assert_eq!(positional("%2%", &args, &[]), Err("%2% refers to an argument that was not given".into()));
+    assert_eq!(positional("%0%", &args, &[]), Err("%0% refers to an argument that was not given".into()));
+    assert_eq!(positional("%@%", &args, &[]), Ok("".into()));
+  }
+
+  fn lenient(text: &str) -> String {
+    replace_lenient(text, |key, _| (key == "name").then(|| "doit".to_string()))
+  }
+
+  #[test]
+  fn lenient_placeholders() {
+    assert_eq!(lenient("%name%"), "doit");
+    assert_eq!(lenient("+%Y-%m %name%"), "+%Y-%m doit");
+    assert_eq!(lenient("%other% and %name%"), "%other% and doit");
+    assert_eq!(lenient("100% done"), "100% done");
+    assert_eq!(lenient("%name%%name%"), "doitdoit");
+    assert_eq!(lenient(""), "");
+  }
+
+  #[test]
+  fn lenient_placeholders_see_the_whole_match() {
+    let mut seen = vec![];
+    replace_lenient("a %x% b", |key, matched| {
+      seen.push((key.to_string(), matched.to_string()));
+      None
+    });
+    assert_eq!(seen, [("x".to_string(), "%x%".to_string())]);
+  }
+
+  #[test]
+  fn unknown_user() {
+    assert_eq!(tildes("~nobody-here/x"), Err("user 'nobody-here' not found!".into()));
+    assert_eq!(tildes("~Bob/x"), Ok("~Bob/x".into()));
+  }
+
+  fn rendered(settings: Settings, template: &str) -> String {
+    let table = "cwd = \"/table\"\nregion = \"us\"\nurl = \"%region%.example.com\"".parse::<DocumentMut>().unwrap();
+    let context = Context { config: None, settings: Arc::new(settings) };
+    context.enter(|| render_template(table.as_table(), template).unwrap())
+  }
+
+  #[test]
+  fn overrides_come_first() {
+    let set = |pairs: &[(&str, &str)]| Settings {
+      overrides: pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+      ..Settings::default()
+    };
+    assert_eq!(rendered(set(&[]), ":%os% %region%"), format!("{} us", env::consts::OS));
+    assert_eq!(
+      rendered(set(&[("cwd", "/x"), ("os", "plan9"), ("git:sha", "abc")]), ":%cwd% %os% %git:sha%"),
+      "/x plan9 abc"
+    );
+    assert_eq!(rendered(set(&[("region", "eu"), ("region", "ap")]), ":%url%"), "ap.example.com");
+    assert_eq!(rendered(set(&[("capture:out", "given")]), ":%capture:out%"), "given");
+    assert_eq!(rendered(set(&[("missing", "here")]), ":%missing:-default%"), "here");
+  }
+}
@@ -0,0 +1,30 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! What more than one module's tests need.
+use std::{
+  fs,
+  path::PathBuf,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory under the system temp dir, removed again when dropped.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+  /// The name is only there to tell the directories apart; each one also gets a number of its own, so tests in
+  /// different modules can use the same name.
+  pub fn new(name: &str) -> Self {
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("doit-test-{}-{}-{}", std::process::id(), n, name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    TempDir(dir)
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
@@ -0,0 +1,539 @@
+// code: language=Rust insertSpaces=true tabSize=2
+//! The host for `.doit/plugins/*.wasm` builtins. A plugin is a WASI command (as `cargo build --target wasm32-wasip1`
+//! makes) run in-process, sandboxed: it gets its args, the builtin context JSON on stdin, stdout and stderr, the
+//! clock and random numbers, but no environment and no directories. Anything more goes through the `doit` module it
+//! may import, and only once the config grants it in `[doit.plugins]`:
+//!
+//! - `render(ptr, len) -> i32` renders a template with the task's keys, needing `"render"`
+//! - `read_file(path_ptr, path_len) -> i32` reads a file under the step's directory, needing `"read"`
+//! - `write_file(path_ptr, path_len, data_ptr, data_len) -> i32` writes one there, needing `"write"`
+//! - `result(ptr)` copies what the last call gave to `ptr`
+//!
+//! The first three return the length of what they gave (nothing, for write_file), or `-1 - len` when what they gave
+//! is an error message instead.
+use std::{
+  fs,
+  io::{Read, Write},
+  path::{Component, Path, PathBuf},
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use wasmi::{Caller, Config, Engine, Error, Extern, Linker, Module, Store, TypedResumableCall, Val};
+
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_IO: i32 = 29;
+const ERRNO_NOSYS: i32 = 52;
+
+/// How much a plugin runs before doit checks whether it has been cancelled or has run out of time.
+const FUEL_SLICE: u64 = 10_000_000;
+
+/// Where a plugin's stdout or stderr goes.
+pub enum Sink {
+  Stdout,
+  Stderr,
+  File(fs::File),
+  /// Kept for `capture`.
+  Buffer(Vec<u8>),
+}
+
+impl Sink {
+  fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+    match self {
+      Sink::Stdout => std::io::stdout().write_all(data),
+      Sink::Stderr => std::io::stderr().write_all(data),
+      Sink::File(file) => file.write_all(data),
+      Sink::Buffer(buffer) => {
+        buffer.extend_from_slice(data);
+        Ok(())
+      }
+    }
+  }
+}
+
+/// Renders a template for the `doit.render` import.
+pub type Render = dyn FnMut(&str) -> Result<String, String>;
+
+/// What a plugin runs with.
+pub struct Plugin {
+  pub name: String,
+  pub args: Vec<String>,
+  pub stdin: Vec<u8>,
+  /// The step's directory, which read_file and write_file paths are relative to and may not leave.
+  pub cwd: PathBuf,
+  /// What `[doit.plugins]` lets it do: `"render"`, `"read"` and `"write"`.
+  pub grants: Vec<String>,
+  pub render: Box<Render>,
+  pub stdout: Sink,
+  pub stderr: Sink,
+  pub timeout: Option<Duration>,
+  pub cancelled: fn() -> bool,
+}
+
+struct Host {
+  plugin: Plugin,
+  stdin_pos: usize,
+  /// What the last `doit` call gave, for `result` to copy out.
+  result: Vec<u8>,
+}
+
+fn memory(caller: &Caller<'_, Host>) -> Result<wasmi::Memory, Error> {
+  caller.get_export("memory").and_then(Extern::into_memory).ok_or_else(|| Error::new("the plugin exports no memory"))
+}
+
+fn read_bytes(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Result<Vec<u8>, Error> {
+  let mut buf = vec![0u8; len.max(0) as usize];
+  memory(caller)?.read(caller, ptr as u32 as usize, &mut buf).map_err(|e| Error::new(e.to_string()))?;
+  Ok(buf)
+}
+
+fn read_string(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Result<String, Error> {
+  String::from_utf8(read_bytes(caller, ptr, len)?).map_err(|_| Error::new("a string that isn't UTF-8"))
+}
+
+fn write_bytes(caller: &mut Caller<'_, Host>, ptr: i32, data: &[u8]) -> Result<(), Error> {
+  memory(caller)?.write(caller, ptr as u32 as usize, data).map_err(|e| Error::new(e.to_string()))
+}
+
+fn read_u32(caller: &Caller<'_, Host>, ptr: i32) -> Result<u32, Error> {
+  let bytes = read_bytes(caller, ptr, 4)?;
+  Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The `(ptr, len)` pairs of a WASI iovec array.
+fn iovecs(caller: &Caller<'_, Host>, iovs: i32, count: i32) -> Result<Vec<(i32, i32)>, Error> {
+  (0..count.max(0))
+    .map(|i| Ok((read_u32(caller, iovs + i * 8)? as i32, read_u32(caller, iovs + i * 8 + 4)? as i32)))
+    .collect()
+}
+
+/// Writes a list of strings the way args_get and environ_get do: pointers at `ptrs`, NUL-terminated text at `buf`.
+fn write_strings(caller: &mut Caller<'_, Host>, strings: &[String], ptrs: i32, buf: i32) -> Result<i32, Error> {
+  let mut offset = buf;
+  for (i, string) in strings.iter().enumerate() {
+    write_bytes(caller, ptrs + i as i32 * 4, &(offset as u32).to_le_bytes())?;
+    write_bytes(caller, offset, string.as_bytes())?;
+    write_bytes(caller, offset + string.len() as i32, &[0])?;
+    offset += string.len() as i32 + 1;
+  }
+  Ok(ERRNO_SUCCESS)
+}
+
+fn argv(host: &Host) -> Vec<String> {
+  std::iter::once(&host.plugin.name).chain(&host.plugin.args).cloned().collect()
+}
+
+/// `path`, which must be relative and stay under `cwd` once symlinks are followed.
+fn confined(cwd: &Path, path: &str) -> Result<PathBuf, String> {
+  let relative = Path::new(path);
+  if relative.is_absolute() || relative.components().any(|part| part == Component::ParentDir) {
+    return Err(format!("{}: plugins may only use relative paths that don't go up with ..", path));
+  }
+  let root = cwd.canonicalize().map_err(|e| format!("{}: {}", cwd.display(), e))?;
+  let full = root.join(relative);
+  // A file that isn't there yet is checked by way of the directory it would go in.
+  let (existing, rest) = match full.canonicalize() {
+    Ok(real) => (real, None),
+    Err(_) => match (full.parent().map(Path::canonicalize), full.file_name()) {
+      (Some(Ok(parent)), Some(name)) => (parent, Some(name.to_owned())),
+      _ => return Err(format!("{}: no such directory", path)),
+    },
+  };
+  if !existing.starts_with(&root) {
+    return Err(format!("{}: is outside of {}", path, cwd.display()));
+  }
+  Ok(rest.map_or(existing.clone(), |name| existing.join(name)))
+}
+
+/// Gives what a `doit` call came up with to `result`, returning its length, or `-1 - len` for an error.
+fn give(caller: &mut Caller<'_, Host>, outcome: Result<Vec<u8>, String>) -> i32 {
+  let host = caller.data_mut();
+  match outcome {
+    Ok(value) => {
+      let len = value.len() as i32;
+      host.result = value;
+      len
+    }
+    Err(e) => {
+      host.result = e.into_bytes();
+      -1 - host.result.len() as i32
+    }
+  }
+}
+
+fn granted(host: &Host, capability: &str, what: &str) -> Result<(), String> {
+  if host.plugin.grants.iter().any(|grant| grant == capability) {
+    return Ok(());
+  }
+  Err(format!(
+    "&{} may not {} (add \"{}\" to its entry in [doit.plugins] to let it)",
+    host.plugin.name, what, capability
+  ))
+}
+
+fn define_doit(linker: &mut Linker<Host>) -> Result<(), Error> {
+  linker.func_wrap("doit", "render", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> Result<i32, Error> {
+    let template = read_string(&caller, ptr, len)?;
+    let host = caller.data_mut();
+    let outcome = granted(host, "render", "render templates").and_then(|_| (host.plugin.render)(&template));
+    Ok(give(&mut caller, outcome.map(String::into_bytes)))
+  })?;
+  linker.func_wrap("doit", "read_file", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> Result<i32, Error> {
+    let path = read_string(&caller, ptr, len)?;
+    let host = caller.data();
+    let outcome = granted(host, "read", "read files")
+      .and_then(|_| confined(&host.plugin.cwd, &path))
+      .and_then(|full| fs::read(&full).map_err(|e| format!("{}: {}", path, e)));
+    Ok(give(&mut caller, outcome))
+  })?;
+  linker.func_wrap(
+    "doit",
+    "write_file",
+    |mut caller: Caller<'_, Host>, ptr: i32, len: i32, data: i32, data_len: i32| -> Result<i32, Error> {
+      let path = read_string(&caller, ptr, len)?;
+      let data = read_bytes(&caller, data, data_len)?;
+      let host = caller.data();
+      let outcome = granted(host, "write", "write files")
+        .and_then(|_| confined(&host.plugin.cwd, &path))
+        .and_then(|full| fs::write(&full, data).map_err(|e| format!("{}: {}", path, e)));
+      Ok(give(&mut caller, outcome.map(|_| vec![])))
+    },
+  )?;
+  linker.func_wrap("doit", "result", |mut caller: Caller<'_, Host>, ptr: i32| -> Result<(), Error> {
+    let result = std::mem::take(&mut caller.data_mut().result);
+    write_bytes(&mut caller, ptr, &result)
+  })?;
+  Ok(())
+}
+
+fn define_wasi(linker: &mut Linker<Host>) -> Result<(), Error> {
+  const WASI: &str = "wasi_snapshot_preview1";
+  linker.func_wrap(WASI, "args_sizes_get", |mut caller: Caller<'_, Host>, count: i32, size: i32| {
+    let argv = argv(caller.data());
+    write_bytes(&mut caller, count, &(argv.len() as u32).to_le_bytes())?;
+    let len: usize = argv.iter().map(|arg| arg.len() + 1).sum();
+    write_bytes(&mut caller, size, &(len as u32).to_le_bytes())?;
+    Ok(ERRNO_SUCCESS)
+  })?;
+  linker.func_wrap(WASI, "args_get", |mut caller: Caller<'_, Host>, ptrs: i32, buf: i32| {
+    let argv = argv(caller.data());
+    write_strings(&mut caller, &argv, ptrs, buf)
+  })?;
+  // Plugins get no environment.
+  linker.func_wrap(WASI, "environ_sizes_get", |mut caller: Caller<'_, Host>, count: i32, size: i32| {
+    write_bytes(&mut caller, count, &[0; 4])?;
+    write_bytes(&mut caller, size, &[0; 4])?;
+    Ok(ERRNO_SUCCESS)
+  })?;
+  linker.func_wrap(WASI, "environ_get", |_: Caller<'_, Host>, _: i32, _: i32| ERRNO_SUCCESS)?;
+  linker.func_wrap(
+    WASI,
+    "fd_write",
+    |mut caller: Caller<'_, Host>, fd: i32, iovs: i32, count: i32, written: i32| -> Result<i32, Error> {
+      if fd != 1 && fd != 2 {
+        return Ok(ERRNO_BADF);
+      }
+      let mut data = Vec::new();
+      for (ptr, len) in iovecs(&caller, iovs, count)? {
+        data.extend(read_bytes(&caller, ptr, len)?);
+      }
+      let host = caller.data_mut();
+      let sink = if fd == 1 { &mut host.plugin.stdout } else { &mut host.plugin.stderr };
+      if sink.write(&data).is_err() {
+        return Ok(ERRNO_IO);
+      }
+      write_bytes(&mut caller, written, &(data.len() as u32).to_le_bytes())?;
+      Ok(ERRNO_SUCCESS)
+    },
+  )?;
+  linker.func_wrap(
+    WASI,
+    "fd_read",
+    |mut caller: Caller<'_, Host>, fd: i32, iovs: i32, count: i32, read: i32| -> Result<i32, Error> {
+      if fd != 0 {
+        return Ok(ERRNO_BADF);
+      }
+      let mut total = 0;
+      for (ptr, len) in iovecs(&caller, iovs, count)? {
+        let host = caller.data_mut();
+        let rest = &host.plugin.stdin[host.stdin_pos..];
+        let chunk = rest[..rest.len().min(len.max(0) as usize)].to_vec();
+        host.stdin_pos += chunk.len();
+        write_bytes(&mut caller, ptr, &chunk)?;
+        total += chunk.len();
+        if chunk.len() < len as usize {
+          break;
+        }
+      }
+      write_bytes(&mut caller, read, &(total as u32).to_le_bytes())?;
+      Ok(ERRNO_SUCCESS)
+    },
+  )?;
+  linker.func_wrap(WASI, "fd_fdstat_get", |mut caller: Caller<'_, Host>, fd: i32, stat: i32| {
+    if !(0..=2).contains(&fd) {
+      return Ok(ERRNO_BADF);
+    }
+    // A character device, with no flags and every right.
+    let mut fdstat = [0u8; 24];
+    fdstat[0] = 2;
+    fdstat[8..24].fill(0xff);
+    write_bytes(&mut caller, stat, &fdstat)?;
+    Ok(ERRNO_SUCCESS)
+  })?;
+  // There are no preopened directories, so a plugin can't open files except through read_file and write_file.
+  linker.func_wrap(WASI, "fd_prestat_get", |_: Caller<'_, Host>, _: i32, _: i32| ERRNO_BADF)?;
+  linker.func_wrap(
+    WASI,
+    "fd_close",
+    |_: Caller<'_, Host>, fd: i32| {
+      if (0..=2).contains(&fd) {
+        ERRNO_SUCCESS
+      } else {
+        ERRNO_BADF
+      }
+    },
+  )?;
+  linker.func_wrap(WASI, "proc_exit", |_: Caller<'_, Host>, status: i32| -> Result<(), Error> {
+    Err(Error::i32_exit(status))
+  })?;
+  linker.func_wrap(WASI, "sched_yield", |_: Caller<'_, Host>| ERRNO_SUCCESS)?;
+  linker.func_wrap(WASI, "clock_time_get", |mut caller: Caller<'_, Host>, clock: i32, _: i64, time: i32| {
+    static START: once_cell::sync::Lazy<Instant> = once_cell::sync::Lazy::new(Instant::now);
+    let nanos = match clock {
+      0 => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64,
+      1..=3 => START.elapsed().as_nanos() as u64,
+      _ => return Ok(ERRNO_INVAL),
+    };
+    write_bytes(&mut caller, time, &nanos.to_le_bytes())?;
+    Ok(ERRNO_SUCCESS)
+  })?;
+  linker.func_wrap(WASI, "random_get", |mut caller: Caller<'_, Host>, buf: i32, len: i32| {
+    let mut random = vec![0u8; len.max(0) as usize];
+    if fs::File::open("/dev/urandom").and_then(|mut urandom| urandom.read_exact(&mut random)).is_err() {
+      return Ok(ERRNO_IO);
+    }
+    write_bytes(&mut caller, buf, &random)?;
+    Ok(ERRNO_SUCCESS)
+  })?;
+  Ok(())
+}
+
+/// Anything else a plugin imports from WASI fails as not supported, so that instantiating it doesn't.
+fn define_unsupported(linker: &mut Linker<Host>, module: &Module) {
+  for import in module.imports() {
+    let (Some(ty), "wasi_snapshot_preview1") = (import.ty().func(), import.module()) else {
+      continue;
+    };
+    // Those defined already are left as they are.
+    let _ = linker.func_new(import.module(), import.name(), ty.clone(), |_, _, results| {
+      if let Some(errno) = results.first_mut() {
+        *errno = Val::I32(ERRNO_NOSYS);
+      }
+      Ok(())
+    });
+  }
+}
+
+/// Runs a plugin to the end, giving its exit status and, when its stdout is a `Sink::Buffer`, what it printed.
+pub fn run(path: &Path, plugin: Plugin) -> Result<(i32, Vec<u8>), String> {
+  let wasm = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+  let mut config = Config::default();
+  config.consume_fuel(true);
+  let engine = Engine::new(&config);
+  let fail = |e: Error| format!("{}: {}", path.display(), e);
+  let module = Module::new(&engine, &wasm).map_err(fail)?;
+  let mut linker = Linker::new(&engine);
+  define_doit(&mut linker).map_err(fail)?;
+  define_wasi(&mut linker).map_err(fail)?;
+  define_unsupported(&mut linker, &module);
+
+  let (timeout, cancelled) = (plugin.timeout, plugin.cancelled);
+  let mut store = Store::new(&engine, Host { plugin, stdin_pos: 0, result: vec![] });
+  store.set_fuel(FUEL_SLICE).map_err(fail)?;
+  let instance = linker.instantiate_and_start(&mut store, &module).map_err(fail)?;
+  let start = instance.get_typed_func::<(), ()>(&store, "_start").map_err(|_| {
+    format!(
+      "{}: exports no _start function (build it as a WASI command, like a Rust binary for wasm32-wasip1)",
+      path.display()
+    )
+  })?;
+
+  let deadline = timeout.map(|timeout| Instant::now() + timeout);
+  let exit = |e: &Error| e.i32_exit_status().ok_or_else(|| format!("{}: {}", path.display(), e));
+  let mut call = start.call_resumable(&mut store, ());
+  let status = loop {
+    match call {
+      Ok(TypedResumableCall::Finished(())) => break Ok(0),
+      Ok(TypedResumableCall::OutOfFuel(paused)) => {
+        if cancelled() {
+          return Err("cancelled".into());
+        }
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+          return Err(format!("timed out after {:?}", timeout.unwrap_or_default()));
+        }
+        store.set_fuel(FUEL_SLICE).map_err(fail)?;
+        call = paused.resume(&mut store);
+      }
+      Ok(TypedResumableCall::HostTrap(trap)) => break exit(trap.host_error()),
+      Err(e) => break exit(&e),
+    }
+  };
+  let status = status?;
+  let stdout = match std::mem::replace(&mut store.data_mut().plugin.stdout, Sink::Stdout) {
+    Sink::Buffer(buffer) => buffer,
+    _ => vec![],
+  };
+  Ok((status, stdout))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::TempDir;
+
+  /// Every import the tests use, a `$print` to stdout, and a `$print_result` of what a `doit` call gave.
+  const PRELUDE: &str = r#"
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+    (import "wasi_snapshot_preview1" "path_open"
+      (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "doit" "render" (func $render (param i32 i32) (result i32)))
+    (import "doit" "read_file" (func $read_file (param i32 i32) (result i32)))
+    (import "doit" "write_file" (func $write_file (param i32 i32 i32 i32) (result i32)))
+    (import "doit" "result" (func $result (param i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 16) "error: ")
+    (func $print (param $ptr i32) (param $len i32)
+      (i32.store (i32.const 0) (local.get $ptr))
+      (i32.store (i32.const 4) (local.get $len))
+      (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+    (func $print_result (param $n i32)
+      (if (i32.lt_s (local.get $n) (i32.const 0))
+        (then
+          (call $print (i32.const 16) (i32.const 7))
+          (local.set $n (i32.sub (i32.const -1) (local.get $n)))))
+      (call $result (i32.const 4096))
+      (call $print (i32.const 4096) (local.get $n)))
+  "#;
+
+  fn plugin(dir: &Path, grants: &[&str]) -> Plugin {
+    Plugin {
+      name: "test".into(),
+      args: vec![],
+      stdin: vec![],
+      cwd: dir.to_path_buf(),
+      grants: grants.iter().map(|grant| grant.to_string()).collect(),
+      render: Box::new(|template| Ok(template.replace("%name%", "world"))),
+      stdout: Sink::Buffer(vec![]),
+      stderr: Sink::Buffer(vec![]),
+      timeout: None,
+      cancelled: || false,
+    }
+  }
+
+  /// Runs a module made of the prelude and `body`, giving its exit status and stdout.
+  fn run_wat(dir: &Path, body: &str, plugin: Plugin) -> Result<(i32, String), String> {
+    let path = dir.join("test.wat");
+    fs::write(&path, format!("(module {} {})", PRELUDE, body)).unwrap();
+    run(&path, plugin).map(|(status, stdout)| (status, String::from_utf8(stdout).unwrap()))
+  }
+
+  #[test]
+  fn args_and_stdin() {
+    let dir = TempDir::new("args");
+    let start = r#"(func (export "_start")
+      (drop (call $args_sizes_get (i32.const 100) (i32.const 104)))
+      (drop (call $args_get (i32.const 200) (i32.const 1024)))
+      (call $print (i32.const 1024) (i32.load (i32.const 104)))
+      (i32.store (i32.const 8) (i32.const 2048))
+      (i32.store (i32.const 12) (i32.const 1000))
+      (drop (call $fd_read (i32.const 0) (i32.const 8) (i32.const 1) (i32.const 104)))
+      (call $print (i32.const 2048) (i32.load (i32.const 104))))"#;
+    let plugin = Plugin { args: vec!["a b".into(), "c".into()], stdin: b"{\"x\": 1}".to_vec(), ..plugin(&dir.0, &[]) };
+    assert_eq!(run_wat(&dir.0, start, plugin).unwrap(), (0, "test\0a b\0c\0{\"x\": 1}".into()));
+  }
+
+  #[test]
+  fn exit_status() {
+    let dir = TempDir::new("exit");
+    let start = r#"(data (i32.const 64) "bye") (func (export "_start")
+      (call $print (i32.const 64) (i32.const 3))
+      (call $proc_exit (i32.const 3))
+      (call $print (i32.const 64) (i32.const 3)))"#;
+    assert_eq!(run_wat(&dir.0, start, plugin(&dir.0, &[])).unwrap(), (3, "bye".into()));
+  }
+
+  #[test]
+  fn render_needs_a_grant() {
+    let dir = TempDir::new("render");
+    let start = r#"(data (i32.const 64) "hello %name%")
+      (func (export "_start") (call $print_result (call $render (i32.const 64) (i32.const 12))))"#;
+    assert_eq!(run_wat(&dir.0, start, plugin(&dir.0, &["render"])).unwrap(), (0, "hello world".into()));
+    let (status, stdout) = run_wat(&dir.0, start, plugin(&dir.0, &[])).unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(
+      stdout,
+      "error: &test may not render templates (add \"render\" to its entry in [doit.plugins] to let it)"
+    );
+  }
+
+  #[test]
+  fn files_stay_under_the_directory() {
+    let dir = TempDir::new("files");
+    let start = r#"(data (i32.const 64) "out.txt") (data (i32.const 80) "data") (data (i32.const 96) "../out.txt")
+      (func (export "_start")
+        (call $print_result (call $write_file (i32.const 64) (i32.const 7) (i32.const 80) (i32.const 4)))
+        (call $print_result (call $read_file (i32.const 64) (i32.const 7)))
+        (call $print (i32.const 0) (i32.const 0))
+        (call $print_result (call $read_file (i32.const 96) (i32.const 10))))"#;
+    let (status, stdout) = run_wat(&dir.0, start, plugin(&dir.0, &["read", "write"])).unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "dataerror: ../out.txt: plugins may only use relative paths that don't go up with ..");
+    assert_eq!(fs::read_to_string(dir.0.join("out.txt")).unwrap(), "data");
+
+    fs::remove_file(dir.0.join("out.txt")).unwrap();
+    let (_, stdout) = run_wat(&dir.0, start, plugin(&dir.0, &["read"])).unwrap();
+    assert!(stdout.starts_with("error: &test may not write files"), "{}", stdout);
+    assert!(!dir.0.join("out.txt").exists());
+  }
+
+  #[test]
+  fn symlinks_out_are_refused() {
+    let dir = TempDir::new("symlink");
+    fs::create_dir(dir.0.join("inside")).unwrap();
+    fs::write(dir.0.join("secret"), "no").unwrap();
+    std::os::unix::fs::symlink("../secret", dir.0.join("inside/link")).unwrap();
+    assert!(confined(&dir.0.join("inside"), "link").unwrap_err().contains("is outside of"));
+    assert_eq!(confined(&dir.0, "inside/new").unwrap(), dir.0.canonicalize().unwrap().join("inside/new"));
+    assert!(confined(&dir.0, "/etc/passwd").is_err());
+    assert!(confined(&dir.0, "missing/new").is_err());
+  }
+
+  #[test]
+  fn other_wasi_calls_are_not_supported() {
+    let dir = TempDir::new("nosys");
+    let start = r#"(func (export "_start") (call $proc_exit (call $path_open (i32.const 3) (i32.const 0) (i32.const 0)
+      (i32.const 0) (i32.const 0) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 0))))"#;
+    assert_eq!(run_wat(&dir.0, start, plugin(&dir.0, &[])).unwrap(), (ERRNO_NOSYS, "".into()));
+  }
+
+  #[test]
+  fn commands_only() {
+    let dir = TempDir::new("start");
+    let err = run_wat(&dir.0, "", plugin(&dir.0, &[])).unwrap_err();
+    assert!(
+      err.ends_with("exports no _start function (build it as a WASI command, like a Rust binary for wasm32-wasip1)")
+    );
+  }
+
+  #[test]
+  fn timeouts() {
+    let dir = TempDir::new("timeout");
+    let start = r#"(func (export "_start") (loop $forever (br $forever)))"#;
+    let plugin = Plugin { timeout: Some(Duration::from_millis(50)), ..plugin(&dir.0, &[]) };
+    assert_eq!(run_wat(&dir.0, start, plugin).unwrap_err(), "timed out after 50ms");
+  }
+}